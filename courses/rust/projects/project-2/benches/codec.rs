@@ -0,0 +1,63 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use kvs::{Codec, KvStore, OpenOptions};
+use tempfile::TempDir;
+
+const CODECS: [(&str, Codec); 3] = [
+    ("cbor", Codec::Cbor),
+    ("bincode", Codec::Bincode),
+    ("json", Codec::Json),
+];
+
+fn open(dir: &TempDir, codec: Codec) -> KvStore {
+    OpenOptions::new()
+        .codec(codec)
+        .open(dir.path())
+        .expect("unable to open the store")
+}
+
+fn set_bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("set");
+    for (name, codec) in CODECS.iter() {
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter_batched(
+                || {
+                    let temp_dir = TempDir::new().unwrap();
+                    (open(&temp_dir, *codec), temp_dir)
+                },
+                |(store, _temp_dir)| {
+                    for i in 0..1000 {
+                        store
+                            .set(format!("key{}", i), format!("value{}", i))
+                            .unwrap();
+                    }
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn get_bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get");
+    for (name, codec) in CODECS.iter() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = open(&temp_dir, *codec);
+        for i in 0..1000 {
+            store
+                .set(format!("key{}", i), format!("value{}", i))
+                .unwrap();
+        }
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                for i in 0..1000 {
+                    store.get(format!("key{}", i)).unwrap();
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, set_bench, get_bench);
+criterion_main!(benches);
@@ -0,0 +1,84 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use crossbeam_utils::sync::WaitGroup;
+use kvs::thread_pool::{NaiveThreadPool, RayonThreadPool, SharedQueueThreadPool, ThreadPool};
+use kvs::{KvStore, KvsEngine, SledKvsEngine};
+use tempfile::TempDir;
+
+const KEYS: usize = 1000;
+
+// 1, 2, 4 and every even number up to twice the number of CPUs
+fn thread_counts() -> Vec<u32> {
+    let max = 2 * num_cpus::get() as u32;
+    let mut counts = vec![1, 2];
+    counts.extend((4..=max).step_by(2));
+    counts.dedup();
+    counts
+}
+
+// run one job per key on the pool and wait for all of them
+fn run_jobs<P: ThreadPool, E: KvsEngine>(pool: &P, engine: &E, job: fn(&E, usize)) {
+    let wg = WaitGroup::new();
+    for i in 0..KEYS {
+        let engine = engine.clone();
+        let wg = wg.clone();
+        pool.spawn(move || {
+            job(&engine, i);
+            drop(wg);
+        });
+    }
+    wg.wait();
+}
+
+fn set<E: KvsEngine>(engine: &E, i: usize) {
+    engine
+        .set(format!("key{}", i), "value".to_owned())
+        .expect("set failed");
+}
+
+fn get<E: KvsEngine>(engine: &E, i: usize) {
+    assert_eq!(
+        engine.get(format!("key{}", i)).expect("get failed"),
+        Some("value".to_owned())
+    );
+}
+
+fn bench_pool<P: ThreadPool, E: KvsEngine>(c: &mut Criterion, name: &str, open: fn(&TempDir) -> E) {
+    let mut group = c.benchmark_group(name);
+    group.sample_size(10);
+    for threads in thread_counts() {
+        let pool = P::new(threads).expect("unable to build the pool");
+        let temp_dir = TempDir::new().unwrap();
+        let engine = open(&temp_dir);
+        for i in 0..KEYS {
+            set(&engine, i);
+        }
+
+        group.bench_with_input(
+            BenchmarkId::new("write_heavy", threads),
+            &threads,
+            |b, _| b.iter(|| run_jobs(&pool, &engine, set)),
+        );
+        group.bench_with_input(BenchmarkId::new("read_heavy", threads), &threads, |b, _| {
+            b.iter(|| run_jobs(&pool, &engine, get))
+        });
+    }
+    group.finish();
+}
+
+fn open_kvs(dir: &TempDir) -> KvStore {
+    KvStore::open(dir.path()).expect("unable to open the store")
+}
+
+fn open_sled(dir: &TempDir) -> SledKvsEngine {
+    SledKvsEngine::open(dir.path()).expect("unable to open sled")
+}
+
+fn pools(c: &mut Criterion) {
+    bench_pool::<NaiveThreadPool, _>(c, "naive_kvs", open_kvs);
+    bench_pool::<SharedQueueThreadPool, _>(c, "shared_queue_kvs", open_kvs);
+    bench_pool::<RayonThreadPool, _>(c, "rayon_kvs", open_kvs);
+    bench_pool::<RayonThreadPool, _>(c, "rayon_sled", open_sled);
+}
+
+criterion_group!(benches, pools);
+criterion_main!(benches);
@@ -0,0 +1,84 @@
+//! async_engine offers the operations of a KvsEngine as futures.
+//!
+use crate::error::KvErr;
+use crate::thread_pool::ThreadPool;
+use crate::{KvsEngine, Result};
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::oneshot;
+
+/// Runs the blocking calls of a KvsEngine on a thread pool, and returns
+/// futures that resolve once the call is done. A call is sent to the pool
+/// right away, awaiting the future only waits for its result.
+pub struct AsyncEngine<E: KvsEngine, P: ThreadPool> {
+    engine: E,
+    pool: Arc<P>,
+}
+
+impl<E: KvsEngine, P: ThreadPool> AsyncEngine<E, P> {
+    pub fn new(engine: E, pool: P) -> Self {
+        Self {
+            engine,
+            pool: Arc::new(pool),
+        }
+    }
+
+    pub fn get(&self, key: String) -> impl Future<Output = Result<Option<String>>> {
+        self.run(move |engine| engine.get(key))
+    }
+
+    pub fn set(&self, key: String, value: String) -> impl Future<Output = Result<()>> {
+        self.run(move |engine| engine.set(key, value))
+    }
+
+    pub fn remove(&self, key: String) -> impl Future<Output = Result<()>> {
+        self.run(move |engine| engine.remove(key))
+    }
+
+    pub fn compare_and_swap(
+        &self,
+        key: String,
+        expected: String,
+        new: String,
+    ) -> impl Future<Output = Result<bool>> {
+        self.run(move |engine| engine.compare_and_swap(key, expected, new))
+    }
+
+    pub fn set_if_absent(&self, key: String, value: String) -> impl Future<Output = Result<bool>> {
+        self.run(move |engine| engine.set_if_absent(key, value))
+    }
+
+    pub fn remove_if_equals(
+        &self,
+        key: String,
+        expected: String,
+    ) -> impl Future<Output = Result<bool>> {
+        self.run(move |engine| engine.remove_if_equals(key, expected))
+    }
+
+    fn run<T, F>(&self, call: F) -> impl Future<Output = Result<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&E) -> Result<T> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let engine = self.engine.clone();
+        self.pool.spawn(move || {
+            // the caller may have dropped the future already
+            let _ = tx.send(call(&engine));
+        });
+        async move {
+            rx.await
+                .map_err(|_| KvErr::ServerError("the engine call panicked".to_owned()))?
+        }
+    }
+}
+
+impl<E: KvsEngine, P: ThreadPool> Clone for AsyncEngine<E, P> {
+    fn clone(&self) -> Self {
+        Self {
+            engine: self.engine.clone(),
+            pool: self.pool.clone(),
+        }
+    }
+}
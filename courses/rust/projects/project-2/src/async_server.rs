@@ -0,0 +1,128 @@
+//! async_server serves a KvsEngine over TCP on the tokio runtime.
+//!
+//! Connections are handled by tasks on a few runtime threads, while the
+//! blocking engine calls run on the thread pool of an `AsyncEngine`, so a slow
+//! disk does not hold up the network threads.
+use crate::async_engine::AsyncEngine;
+use crate::protocol::{
+    read_frame_async, write_message_as_async, write_message_async, ErrorKind, Request, Response,
+    PROTOCOL_VERSION,
+};
+use crate::server::{reply_version, response, unsupported_version};
+use crate::thread_pool::ThreadPool;
+use crate::{KvsEngine, Result};
+use log::{debug, error};
+use std::future::Future;
+use std::net::ToSocketAddrs;
+use std::pin::Pin;
+use tokio::io::{BufReader, BufWriter};
+use tokio::net::{TcpListener, TcpStream};
+
+pub struct AsyncKvsServer<E: KvsEngine, P: ThreadPool> {
+    engine: AsyncEngine<E, P>,
+}
+
+impl<E, P> AsyncKvsServer<E, P>
+where
+    E: KvsEngine,
+    P: ThreadPool + Send + Sync + 'static,
+{
+    pub fn new(engine: E, pool: P) -> Self {
+        Self {
+            engine: AsyncEngine::new(engine, pool),
+        }
+    }
+
+    /// Listen on `addr` and serve every connection on a task of a tokio
+    /// runtime with one thread per CPU. Does not return unless the listener
+    /// fails.
+    pub fn run(self, addr: impl ToSocketAddrs) -> Result<()> {
+        let listener = std::net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_io()
+            .build()?;
+        runtime.block_on(self.serve(listener))
+    }
+
+    async fn serve(self, listener: std::net::TcpListener) -> Result<()> {
+        let listener = TcpListener::from_std(listener)?;
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let engine = self.engine.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = serve(engine, stream).await {
+                            error!("Error serving client: {}", e);
+                        }
+                    });
+                }
+                Err(e) => error!("Connection failed: {}", e),
+            }
+        }
+    }
+}
+
+async fn serve<E: KvsEngine, P: ThreadPool + Send + Sync + 'static>(
+    engine: AsyncEngine<E, P>,
+    stream: TcpStream,
+) -> Result<()> {
+    let peer = stream.peer_addr()?;
+    let (reader, writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut writer = BufWriter::new(writer);
+
+    while let Some((version, body)) = read_frame_async(&mut reader).await? {
+        if version != PROTOCOL_VERSION {
+            let reply = unsupported_version(version);
+            write_message_as_async(&mut writer, reply_version(version), &reply).await?;
+            return Ok(());
+        }
+
+        let response = match serde_cbor::from_slice::<Request>(&body) {
+            Ok(request) => {
+                debug!("Request from {}: {:?}", peer, request);
+                handle(&engine, request).await
+            }
+            Err(e) => Response::Err(ErrorKind::BadRequest, e.to_string()),
+        };
+        debug!("Response to {}: {:?}", peer, response);
+        write_message_async(&mut writer, &response).await?;
+    }
+    Ok(())
+}
+
+/// Start the engine call of `request`. The returned future does not borrow
+/// the engine, so the engine does not need to be `Sync`.
+fn handle<E: KvsEngine, P: ThreadPool + Send + Sync + 'static>(
+    engine: &AsyncEngine<E, P>,
+    request: Request,
+) -> impl Future<Output = Response> {
+    let call: Pin<Box<dyn Future<Output = Result<Response>> + Send>> = match request {
+        Request::Get { key } => {
+            let get = engine.get(key);
+            Box::pin(async move { get.await.map(Response::Ok) })
+        }
+        Request::Set { key, value } => {
+            let set = engine.set(key, value);
+            Box::pin(async move { set.await.map(|_| Response::Ok(None)) })
+        }
+        Request::Remove { key } => {
+            let remove = engine.remove(key);
+            Box::pin(async move { remove.await.map(|_| Response::Ok(None)) })
+        }
+        Request::CompareAndSwap { key, expected, new } => {
+            let swap = engine.compare_and_swap(key, expected, new);
+            Box::pin(async move { swap.await.map(Response::Applied) })
+        }
+        Request::SetIfAbsent { key, value } => {
+            let set = engine.set_if_absent(key, value);
+            Box::pin(async move { set.await.map(Response::Applied) })
+        }
+        Request::RemoveIfEquals { key, expected } => {
+            let remove = engine.remove_if_equals(key, expected);
+            Box::pin(async move { remove.await.map(Response::Applied) })
+        }
+    };
+    async move { response(call.await) }
+}
@@ -0,0 +1,76 @@
+//! batch groups several writes so that they are applied all at once.
+//!
+use crate::expiry;
+use crate::txlog::LogOperation;
+use std::time::Duration;
+
+/// A group of writes stored as a single record of the log. After a crash
+/// either every write of the batch is found, or none of them.
+///
+/// ```no_run
+/// # fn main() -> kvs::Result<()> {
+/// let store = kvs::KvStore::open("data")?;
+/// let mut batch = kvs::WriteBatch::new();
+/// batch
+///     .set("from".to_owned(), "90".to_owned())
+///     .set("to".to_owned(), "110".to_owned())
+///     .remove("pending".to_owned());
+/// store.write(batch)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    ops: Vec<LogOperation>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, key: String, value: String) -> &mut Self {
+        self.set_bytes(key.into_bytes(), value.into_bytes())
+    }
+
+    pub fn set_bytes(&mut self, key: Vec<u8>, value: Vec<u8>) -> &mut Self {
+        self.ops.push(LogOperation::Set(key, value));
+        self
+    }
+
+    /// Set a key that expires once `ttl` has passed since the batch was
+    /// built, see `KvStore::set_with_ttl`.
+    pub fn set_with_ttl(&mut self, key: String, value: String, ttl: Duration) -> &mut Self {
+        self.set_bytes_with_ttl(key.into_bytes(), value.into_bytes(), ttl)
+    }
+
+    pub fn set_bytes_with_ttl(&mut self, key: Vec<u8>, value: Vec<u8>, ttl: Duration) -> &mut Self {
+        let expires_at = expiry::expires_at(ttl);
+        self.ops
+            .push(LogOperation::SetExpiring(key, value, expires_at));
+        self
+    }
+
+    /// Remove a key, writing the batch fails if the key does not exist by then.
+    pub fn remove(&mut self, key: String) -> &mut Self {
+        self.remove_bytes(key.into_bytes())
+    }
+
+    pub fn remove_bytes(&mut self, key: Vec<u8>) -> &mut Self {
+        self.ops.push(LogOperation::Rm(key));
+        self
+    }
+
+    /// number of writes in the batch
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub(crate) fn into_ops(self) -> Vec<LogOperation> {
+        self.ops
+    }
+}
@@ -0,0 +1,38 @@
+#[macro_use]
+extern crate clap;
+use clap::App;
+use kvs::{KvsClient, Result};
+
+const DEFAULT_ADDR: &str = "127.0.0.1:4000";
+
+fn app() -> Result<()> {
+    let yaml = load_yaml!("client.yaml");
+    let matches = App::from_yaml(yaml).version(crate_version!()).get_matches();
+
+    let (name, sub_cmd) = matches.subcommand();
+    let sub_cmd = sub_cmd.expect("a subcommand is required");
+    let key = sub_cmd.value_of("key").unwrap().to_owned();
+    let addr = sub_cmd.value_of("addr").unwrap_or(DEFAULT_ADDR);
+    let mut client = KvsClient::connect(addr)?;
+
+    match name {
+        "get" => match client.get(key)? {
+            Some(value) => println!("{}", value),
+            None => println!("Key not found"),
+        },
+        "set" => {
+            let value = sub_cmd.value_of("value").unwrap().to_owned();
+            client.set(key, value)?;
+        }
+        "rm" => client.remove(key)?,
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = app() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
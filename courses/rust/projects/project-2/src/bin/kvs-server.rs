@@ -0,0 +1,115 @@
+#[macro_use]
+extern crate clap;
+use clap::App;
+use kvs::thread_pool::{NaiveThreadPool, RayonThreadPool, SharedQueueThreadPool, ThreadPool};
+use kvs::{
+    AsyncKvsServer, Engine, KvStore, KvsEngine, KvsServer, MetricsServer, Result, SledKvsEngine,
+};
+use log::{error, info};
+
+const DEFAULT_ADDR: &str = "127.0.0.1:4000";
+
+fn app() -> Result<()> {
+    let yaml = load_yaml!("server.yaml");
+    let matches = App::from_yaml(yaml).version(crate_version!()).get_matches();
+
+    let addr = matches.value_of("addr").unwrap_or(DEFAULT_ADDR);
+    let dir = std::env::current_dir()?;
+    let engine = match matches.value_of("engine") {
+        Some(name) => name.parse().expect("engine name is validated by clap"),
+        None => Engine::of(&dir)?.unwrap_or(Engine::Kvs),
+    };
+
+    let asynchronous = matches.is_present("async");
+    let pool = matches.value_of("pool").unwrap_or("shared-queue");
+    let threads = match matches.value_of("threads") {
+        Some(n) => match n.parse::<u32>() {
+            Ok(n) if n > 0 => n,
+            _ => {
+                eprintln!("--threads expects a positive number, got {}", n);
+                std::process::exit(1);
+            }
+        },
+        None => num_cpus::get() as u32,
+    };
+    let metrics_addr = matches.value_of("metrics-addr");
+    if metrics_addr.is_some() && engine != Engine::Kvs {
+        eprintln!(
+            "--metrics-addr needs the kvs engine, {} keeps no stats",
+            engine
+        );
+        std::process::exit(1);
+    }
+
+    info!("kvs-server {}", crate_version!());
+    info!("Storage engine: {}", engine);
+    info!("Thread pool: {} with {} threads", pool, threads);
+    if asynchronous {
+        info!("Serving connections on the tokio runtime");
+    }
+    info!("Listening on {}", addr);
+
+    match engine {
+        Engine::Kvs => {
+            let store = KvStore::open(dir)?;
+            if let Some(metrics_addr) = metrics_addr {
+                serve_metrics(store.clone(), metrics_addr)?;
+            }
+            run_with_pool(store, pool, threads, asynchronous, addr)
+        }
+        Engine::Sled => run_with_pool(SledKvsEngine::open(dir)?, pool, threads, asynchronous, addr),
+    }
+}
+
+/// serve the stats of `store` on a thread of its own
+fn serve_metrics(store: KvStore, addr: &str) -> Result<()> {
+    let server = MetricsServer::bind(addr)?;
+    info!("Serving metrics on http://{}/metrics", addr);
+    std::thread::spawn(move || {
+        let render = move || store.stats().map(|stats| stats.to_prometheus());
+        if let Err(e) = server.run(render) {
+            error!("Metrics server failed: {}", e);
+        }
+    });
+    Ok(())
+}
+
+fn run_with_pool<E: KvsEngine>(
+    engine: E,
+    pool: &str,
+    threads: u32,
+    asynchronous: bool,
+    addr: &str,
+) -> Result<()> {
+    match pool {
+        "naive" => run(engine, NaiveThreadPool::new(threads)?, asynchronous, addr),
+        "rayon" => run(engine, RayonThreadPool::new(threads)?, asynchronous, addr),
+        _ => run(
+            engine,
+            SharedQueueThreadPool::new(threads)?,
+            asynchronous,
+            addr,
+        ),
+    }
+}
+
+fn run<E, P>(engine: E, pool: P, asynchronous: bool, addr: &str) -> Result<()>
+where
+    E: KvsEngine,
+    P: ThreadPool + Send + Sync + 'static,
+{
+    if asynchronous {
+        AsyncKvsServer::new(engine, pool).run(addr)
+    } else {
+        KvsServer::new(engine, pool).run(addr)
+    }
+}
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    if let Err(e) = app() {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+}
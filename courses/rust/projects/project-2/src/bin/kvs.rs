@@ -1,55 +1,734 @@
 #[macro_use]
 extern crate clap;
-use clap::App;
-use kvs::{KvStore, Result};
+use base64::Engine as _;
+use clap::{App, ArgMatches, ErrorKind};
+use kvs::error::KvErr;
+use kvs::{CheckReport, KvStore, OpenOptions, Result, WriteBatch};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use serde::de::{Error as _, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer as _, Serialize};
+use std::io::{BufRead, IsTerminal, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-fn app() -> Result<()> {
+/// history of `kvs shell`, in the home directory
+const HISTORY_FILE: &str = ".kvs_history";
+/// path of the store chosen with `kvs open`, in the home directory
+const CURRENT_DB_FILE: &str = ".kvs_db";
+/// environment variable naming the store, before the one chosen with `kvs open`
+const DB_VAR: &str = "KVS_DB";
+/// the commands that may set up a store, the others fail on a path that
+/// holds none
+const WRITE_COMMANDS: [&str; 5] = ["set", "batch", "import", "shell", "exec"];
+
+fn app() -> i32 {
     // The YAML file is found relative to the current file, similar to how modules are found
     let yaml = load_yaml!("cli.yaml");
-    let matches = App::from_yaml(yaml).get_matches();
+    let cli = App::from_yaml(yaml);
+    let matches = cli.clone().get_matches();
 
     if matches.is_present("version") {
         println!("kvs version: {}", std::env!("CARGO_PKG_VERSION"))
     }
+    // nothing to do with a store, it is not even looked up
+    if matches.subcommand_name().is_none() {
+        if matches.is_present("version") {
+            return 0;
+        }
+        eprintln!("no command given");
+        return 1;
+    }
+
+    let result =
+        db_path(&matches)
+            .map_err(Failure::from)
+            .and_then(|db| match matches.subcommand() {
+                // opening a store may truncate or migrate it, the check must not
+                ("check", Some(sub_cmd)) => check(&db, sub_cmd),
+                ("open", Some(sub_cmd)) => choose(&db, sub_cmd),
+                // an empty directory reads as an empty store without setting one up
+                ("get", Some(_)) if is_empty_dir(&db) => {
+                    println!("Key not found");
+                    Ok(())
+                }
+                ("rm", Some(_)) if is_empty_dir(&db) => Err(KvErr::KeyNotExistError.into()),
+                (name, sub_cmd) => open(&matches, &db)
+                    .map_err(Failure::from)
+                    .and_then(|store| match (name, sub_cmd) {
+                        ("shell", Some(_)) => shell(&cli, &store, &db),
+                        ("exec", Some(sub_cmd)) => exec(&cli, &store, &db, sub_cmd),
+                        _ => run(&store, &db, &matches),
+                    }),
+            });
+    match result {
+        Ok(()) => 0,
+        Err(Failure::Usage(e)) => {
+            eprintln!("{}", e);
+            1
+        }
+        Err(Failure::Store(e)) => {
+            print!("{}", e);
+            1
+        }
+        Err(Failure::Reported) => 1,
+    }
+}
+
+/// Why a command failed.
+enum Failure {
+    /// the command line was wrong, nothing was done
+    Usage(String),
+    Store(KvErr),
+    /// the command already printed why
+    Reported,
+}
+
+impl From<KvErr> for Failure {
+    fn from(err: KvErr) -> Self {
+        Failure::Store(err)
+    }
+}
+
+type CommandResult = std::result::Result<(), Failure>;
+
+/// The store the command uses: `--db`, then `KVS_DB`, then the one chosen
+/// with `kvs open`, then the current directory.
+fn db_path(matches: &ArgMatches) -> Result<PathBuf> {
+    // like the encoding, --db is set on the subcommand when given after it
+    let flag = matches
+        .subcommand()
+        .1
+        .and_then(|m| m.value_of("db"))
+        .or_else(|| matches.value_of("db"));
+    if let Some(path) = flag {
+        return Ok(path.into());
+    }
+    if let Some(path) = std::env::var_os(DB_VAR).filter(|path| !path.is_empty()) {
+        return Ok(path.into());
+    }
+    if let Some(file) = current_db_file() {
+        match std::fs::read_to_string(file) {
+            Ok(path) if !path.trim_end().is_empty() => return Ok(path.trim_end().into()),
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(std::env::current_dir()?)
+}
+
+/// file `kvs open` records the chosen store in
+fn current_db_file() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(CURRENT_DB_FILE))
+}
+
+/// open the store in use, with the compression asked by `kvs compact`
+fn open(matches: &ArgMatches, db: &Path) -> Result<KvStore> {
+    let mut options = OpenOptions::new();
+    options.create(WRITE_COMMANDS.contains(&matches.subcommand_name().unwrap_or("")));
+    if let ("compact", Some(sub_cmd)) = matches.subcommand() {
+        if let Some(compression) = sub_cmd.value_of("compression") {
+            options.compression(compression.parse().expect("validated by clap"));
+        }
+    }
+    options.open(db)
+}
+
+fn is_empty_dir(path: &Path) -> bool {
+    std::fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_none())
+}
+
+/// Make the store at FILENAME the one later commands use, once it opens.
+/// Without FILENAME print `db`, the store in use.
+fn choose(db: &Path, sub_cmd: &ArgMatches) -> CommandResult {
+    let file = match sub_cmd.value_of("file") {
+        Some(file) => file,
+        None => {
+            println!("{}", db.display());
+            return Ok(());
+        }
+    };
+    // a legacy database is migrated into its directory and removed by the
+    // open, the directory is the store recorded
+    let file = Path::new(file);
+    let store = match file.parent() {
+        Some(parent) if file.is_file() && parent.as_os_str().is_empty() => Path::new("."),
+        Some(parent) if file.is_file() => parent,
+        _ => file,
+    };
+    // a mistyped path should not become a new empty store
+    match OpenOptions::new()
+        .create(sub_cmd.is_present("create"))
+        .open(file)
+    {
+        Err(KvErr::StoreNotExistError(msg)) => {
+            return Err(Failure::Usage(format!(
+                "{}, pass --create to create it",
+                msg
+            )))
+        }
+        store => drop(store?),
+    }
+    let path = std::fs::canonicalize(store).map_err(KvErr::from)?;
+    let record = current_db_file()
+        .ok_or_else(|| Failure::Usage("HOME is not set, cannot record the store".to_owned()))?;
+    std::fs::write(record, format!("{}\n", path.display())).map_err(KvErr::from)?;
+    if std::env::var_os(DB_VAR).is_some_and(|path| !path.is_empty()) {
+        eprintln!("{} is set and is used before {}", DB_VAR, path.display());
+    }
+    Ok(())
+}
+
+fn check(db: &Path, sub_cmd: &ArgMatches) -> CommandResult {
+    let path = sub_cmd.value_of("path").map_or(db, Path::new);
+    match sub_cmd.value_of("repair") {
+        Some(out) => {
+            let report = KvStore::repair(path, out)?;
+            print_report(&report);
+            println!("salvaged {} records to {}", report.records, out);
+        }
+        None => {
+            let report = KvStore::check(path)?;
+            print_report(&report);
+            if !report.is_clean() {
+                return Err(Failure::Reported);
+            }
+        }
+    }
+    Ok(())
+}
 
-    let mut store = KvStore::open("test.db")?;
+/// Run the subcommand of `matches` against the open `store`, found at `db`.
+fn run(store: &KvStore, db: &Path, matches: &ArgMatches) -> CommandResult {
+    // the encoding is a global argument, it is only set on the subcommand
+    let encoding = match matches.subcommand().1.and_then(|m| m.value_of("encoding")) {
+        Some("hex") => Encoding::Hex,
+        Some("base64") => Encoding::Base64,
+        _ => Encoding::Text,
+    };
 
     match matches.subcommand() {
-        ("open", Some(sub_cmd)) => {}
         ("get", Some(sub_cmd)) => {
-            let key = sub_cmd.value_of("key").unwrap();
+            let key = encoding.decode_arg(sub_cmd.value_of("key").unwrap())?;
 
-            if let Ok(res) = store.get(key.to_owned()) {
-                if let Some(r) = res {
-                    println!("{}", r);
-                } else {
-                    println!("Key not found");
-                }
+            match store.get_bytes(&key)? {
+                Some(r) => println!("{}", encoding.encode(r)?),
+                None => println!("Key not found"),
             }
         }
         ("set", Some(sub_cmd)) => {
-            let key = sub_cmd.value_of("key").unwrap();
-            let val = sub_cmd.value_of("value").unwrap();
+            let key = encoding.decode_arg(sub_cmd.value_of("key").unwrap())?;
+            let val = encoding.decode_arg(sub_cmd.value_of("value").unwrap())?;
 
-            store.set(key.to_owned(), val.to_owned()).unwrap();
+            match sub_cmd.value_of("ttl").map(str::parse::<u64>) {
+                None => store.set_bytes(key, val)?,
+                Some(Ok(secs)) => store.set_bytes_with_ttl(key, val, Duration::from_secs(secs))?,
+                Some(Err(_)) => {
+                    return Err(Failure::Usage("--ttl takes a number of seconds".to_owned()))
+                }
+            }
         }
         ("rm", Some(sub_cmd)) => {
-            let key = sub_cmd.value_of("key").unwrap();
-            store.remove(key.to_owned())?;
+            let key = encoding.decode_arg(sub_cmd.value_of("key").unwrap())?;
+            store.remove_bytes(key)?;
+        }
+        ("scan", Some(sub_cmd)) => {
+            let scan = match sub_cmd.value_of("prefix") {
+                Some(prefix) => store.scan_prefix_bytes(&encoding.decode_arg(prefix)?),
+                None => {
+                    let start = match sub_cmd.value_of("start") {
+                        Some(key) => Bound::Included(encoding.decode_arg(key)?),
+                        None => Bound::Unbounded,
+                    };
+                    let end = match sub_cmd.value_of("end") {
+                        Some(key) => Bound::Excluded(encoding.decode_arg(key)?),
+                        None => Bound::Unbounded,
+                    };
+                    store.scan_bytes((start, end))
+                }
+            };
+            for pair in scan {
+                let (key, value) = pair?;
+                println!("{}\t{}", encoding.encode(key)?, encoding.encode(value)?);
+            }
+        }
+        ("batch", Some(_)) => {
+            let batch = read_batch(std::io::stdin().lock(), encoding).map_err(Failure::Usage)?;
+            store.write(batch)?;
         }
-        _ => {
-            unimplemented!();
+        ("stats", Some(_)) => {
+            // the latencies only cover this process, which made no calls yet
+            let stats = store.stats()?;
+            println!("live keys: {}", stats.live_keys);
+            println!("segments: {}", stats.segments);
+            println!("log bytes: {}", stats.log_bytes);
+            println!("stale bytes: {}", stats.stale_bytes);
+            println!("compaction threshold: {}", stats.compaction_threshold);
+            let compression = store.compression_stats()?;
+            println!("compression: {}", compression.compression);
+            println!(
+                "compression ratio: {:.2} ({} of {} records compressed)",
+                compression.ratio(),
+                compression.compressed_records,
+                compression.records
+            );
         }
+        ("compact", Some(_)) => {
+            store.compact()?;
+        }
+        ("backup", Some(sub_cmd)) => {
+            store.backup_to(sub_cmd.value_of("path").unwrap())?;
+        }
+        ("check", Some(sub_cmd)) => check(db, sub_cmd)?,
+        ("import", Some(sub_cmd)) => import(store, encoding, sub_cmd)?,
+        ("export", Some(sub_cmd)) => export(store, encoding, sub_cmd)?,
+        (name, Some(_)) => {
+            return Err(Failure::Usage(format!(
+                "{} cannot run inside a shell or a script",
+                name
+            )))
+        }
+        (_, None) => return Err(Failure::Usage("no command given".to_owned())),
     };
     Ok(())
 }
 
-fn main() {
-    std::process::exit(match app() {
-        Ok(_) => 0,
-        Err(e) => {
-            print!("{}", e);
-            1
+/// Run one line of a shell or a script, split into words like a shell does.
+/// Blank lines and lines starting with `#` do nothing.
+fn run_line(cli: &App, store: &KvStore, db: &Path, line: &str) -> CommandResult {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(());
+    }
+    let words = shell_words::split(line).map_err(|e| Failure::Usage(e.to_string()))?;
+    let args = std::iter::once("kvs".to_owned()).chain(words);
+    let matches = match cli.clone().get_matches_from_safe(args) {
+        Ok(matches) => matches,
+        Err(e) if e.kind == ErrorKind::HelpDisplayed || e.kind == ErrorKind::VersionDisplayed => {
+            println!("{}", e.message);
+            return Ok(());
         }
-    })
+        Err(e) => return Err(Failure::Usage(e.message)),
+    };
+    if matches.is_present("version") {
+        println!("kvs version: {}", std::env!("CARGO_PKG_VERSION"));
+    }
+    if matches.subcommand().1.is_some_and(|m| m.is_present("db")) || matches.is_present("db") {
+        return Err(Failure::Usage(
+            "--db only applies when the store is opened".to_owned(),
+        ));
+    }
+    if let ("compact", Some(sub_cmd)) = matches.subcommand() {
+        if sub_cmd.is_present("compression") {
+            return Err(Failure::Usage(
+                "--compression only applies when the store is opened".to_owned(),
+            ));
+        }
+    }
+    run(store, db, &matches)
+}
+
+/// print why a line of a shell or a script failed
+fn report(failure: Failure, line: Option<usize>) {
+    let prefix = line.map(|n| format!("line {}: ", n)).unwrap_or_default();
+    match failure {
+        Failure::Usage(e) => eprintln!("{}{}", prefix, e.trim_end()),
+        Failure::Store(e) => eprintln!("{}{}", prefix, e),
+        Failure::Reported => eprintln!("{}failed", prefix),
+    }
+}
+
+/// file the commands typed in `kvs shell` are kept in across sessions
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+/// Read commands until `exit` or the end of the input. A failed command is
+/// reported and the shell goes on, the exit code is the one of the last
+/// command.
+fn shell(cli: &App, store: &KvStore, db: &Path) -> CommandResult {
+    let mut editor = DefaultEditor::new().map_err(|e| Failure::Usage(e.to_string()))?;
+    let history = history_path();
+    if let Some(path) = &history {
+        // there is no history the first time
+        let _ = editor.load_history(path);
+    }
+    let mut last = Ok(());
+    loop {
+        let line = match editor.readline("kvs> ") {
+            Ok(line) => line,
+            // ctrl-c drops the line being typed
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(Failure::Usage(e.to_string())),
+        };
+        let command = line.trim();
+        if command == "exit" || command == "quit" {
+            break;
+        }
+        if !command.is_empty() {
+            let _ = editor.add_history_entry(command);
+        }
+        last = run_line(cli, store, db, &line).map_err(|failure| {
+            report(failure, None);
+            Failure::Reported
+        });
+    }
+    if let Some(path) = &history {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("could not save the history to {}: {}", path.display(), e);
+        }
+    }
+    last
+}
+
+/// Run every line of the script, or stop at the first failure with
+/// `--stop-on-error`. Each failure is reported with its line number, and the
+/// script fails if any command did.
+fn exec(cli: &App, store: &KvStore, db: &Path, sub_cmd: &ArgMatches) -> CommandResult {
+    let file = sub_cmd.value_of("file").unwrap();
+    let script: Box<dyn BufRead> = if file == "-" {
+        Box::new(std::io::stdin().lock())
+    } else {
+        let fd = std::fs::File::open(file)
+            .map_err(|e| Failure::Usage(format!("cannot read {}: {}", file, e)))?;
+        Box::new(std::io::BufReader::new(fd))
+    };
+    let mut failed = 0;
+    for (n, line) in script.lines().enumerate() {
+        let line = line.map_err(|e| Failure::Usage(format!("cannot read {}: {}", file, e)))?;
+        if let Err(failure) = run_line(cli, store, db, &line) {
+            report(failure, Some(n + 1));
+            failed += 1;
+            if sub_cmd.is_present("stop-on-error") {
+                break;
+            }
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        1 => Err(Failure::Usage("1 command failed".to_owned())),
+        n => Err(Failure::Usage(format!("{} commands failed", n))),
+    }
+}
+
+fn print_report(report: &CheckReport) {
+    println!("files: {}", report.files.join(" "));
+    println!("records: {}", report.records);
+    println!("live entries: {}", report.live_entries);
+    println!("stale entries: {}", report.stale_entries);
+    for bad in report.bad_records.iter() {
+        println!(
+            "bad record: {} offset {}: {}",
+            bad.file, bad.offset, bad.reason
+        );
+    }
+    for mismatch in report.mismatches.iter() {
+        println!("mismatch: {}", mismatch);
+    }
+    if report.is_clean() {
+        println!("ok");
+    }
+}
+
+/// How keys and values are written on the command line and printed, raw
+/// bytes that are not UTF-8 need hex or base64.
+#[derive(Debug, Clone, Copy)]
+enum Encoding {
+    Text,
+    Hex,
+    Base64,
+}
+
+impl Encoding {
+    fn decode(self, input: &str) -> std::result::Result<Vec<u8>, String> {
+        match self {
+            Encoding::Text => Ok(input.as_bytes().to_vec()),
+            Encoding::Hex => {
+                hex::decode(input).map_err(|e| format!("invalid hex {}: {}", input, e))
+            }
+            Encoding::Base64 => base64::engine::general_purpose::STANDARD
+                .decode(input)
+                .map_err(|e| format!("invalid base64 {}: {}", input, e)),
+        }
+    }
+
+    /// decode a command line argument
+    fn decode_arg(self, input: &str) -> std::result::Result<Vec<u8>, Failure> {
+        self.decode(input).map_err(Failure::Usage)
+    }
+
+    /// a text key or value that is not UTF-8 is a `Utf8Error`
+    fn encode(self, bytes: Vec<u8>) -> Result<String> {
+        Ok(match self {
+            Encoding::Text => String::from_utf8(bytes)?,
+            Encoding::Hex => hex::encode(bytes),
+            Encoding::Base64 => base64::engine::general_purpose::STANDARD.encode(bytes),
+        })
+    }
+}
+
+/// Parse the writes of `kvs batch`, one per line. Blank lines and lines
+/// starting with `#` are skipped, the value of a `set` is the rest of its line.
+fn read_batch(input: impl BufRead, encoding: Encoding) -> std::result::Result<WriteBatch, String> {
+    let mut batch = WriteBatch::new();
+    for (n, line) in input.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (cmd, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let args = args.trim_start();
+        match cmd {
+            "set" => match args.split_once(char::is_whitespace) {
+                Some((key, value)) => {
+                    let line_err = |e| format!("line {}: {}", n + 1, e);
+                    batch.set_bytes(
+                        encoding.decode(key).map_err(line_err)?,
+                        encoding.decode(value.trim_start()).map_err(line_err)?,
+                    );
+                }
+                None => return Err(format!("line {}: expected `set KEY VALUE`", n + 1)),
+            },
+            "rm" if !args.is_empty() && !args.contains(char::is_whitespace) => {
+                let key = encoding
+                    .decode(args)
+                    .map_err(|e| format!("line {}: {}", n + 1, e))?;
+                batch.remove_bytes(key);
+            }
+            "rm" => return Err(format!("line {}: expected `rm KEY`", n + 1)),
+            _ => return Err(format!("line {}: unknown command {}", n + 1, cmd)),
+        }
+    }
+    Ok(batch)
+}
+
+/// The file formats of `kvs import` and `kvs export`.
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    /// one object, a member per pair
+    Json,
+    /// one `{"key": .., "value": ..}` object per line
+    Ndjson,
+    /// a `key` and a `value` column under a header
+    Csv,
+}
+
+impl Format {
+    /// `--format`, else the extension of the file, else ndjson
+    fn of(sub_cmd: &ArgMatches) -> Format {
+        let name = sub_cmd.value_of("format").or_else(|| {
+            let file = Path::new(sub_cmd.value_of("file")?);
+            file.extension()?.to_str()
+        });
+        match name {
+            Some("json") => Format::Json,
+            Some("csv") => Format::Csv,
+            _ => Format::Ndjson,
+        }
+    }
+}
+
+/// a line of ndjson or a row of csv
+#[derive(Serialize, Deserialize)]
+struct Pair {
+    key: String,
+    value: String,
+}
+
+/// Writes the imported pairs in batches, a batch is applied at once but an
+/// import is not.
+struct Importer<'a> {
+    store: &'a KvStore,
+    encoding: Encoding,
+    batch: WriteBatch,
+    batch_size: usize,
+    imported: u64,
+    /// rewrite a progress line on stderr after every batch
+    progress: bool,
+    /// why the import stopped inside the json parser
+    failed: Option<Failure>,
+}
+
+impl Importer<'_> {
+    fn add(&mut self, key: &str, value: &str) -> CommandResult {
+        let key = self.encoding.decode_arg(key)?;
+        let value = self.encoding.decode_arg(value)?;
+        self.batch.set_bytes(key, value);
+        if self.batch.len() >= self.batch_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> CommandResult {
+        let batch = std::mem::replace(&mut self.batch, WriteBatch::new());
+        let len = batch.len() as u64;
+        self.store.write(batch)?;
+        self.imported += len;
+        if self.progress {
+            eprint!("\rimported {}", count_pairs(self.imported));
+        }
+        Ok(())
+    }
+}
+
+/// the members of a json object are imported as they are parsed
+impl<'de> Visitor<'de> for &mut Importer<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("an object of keys and values")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<(), A::Error> {
+        while let Some((key, value)) = map.next_entry::<String, String>()? {
+            if let Err(failure) = self.add(&key, &value) {
+                self.failed = Some(failure);
+                return Err(A::Error::custom("import stopped"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write the pairs of a file or stdin to the store, in batches.
+fn import(store: &KvStore, encoding: Encoding, sub_cmd: &ArgMatches) -> CommandResult {
+    let batch_size = match sub_cmd.value_of("batch-size").unwrap().parse() {
+        Ok(0) | Err(_) => {
+            return Err(Failure::Usage(
+                "--batch-size takes a number of pairs".to_owned(),
+            ))
+        }
+        Ok(size) => size,
+    };
+    let file = sub_cmd.value_of("file").unwrap_or("-");
+    let input: Box<dyn BufRead> = if file == "-" {
+        Box::new(std::io::stdin().lock())
+    } else {
+        let fd = std::fs::File::open(file)
+            .map_err(|e| Failure::Usage(format!("cannot read {}: {}", file, e)))?;
+        Box::new(std::io::BufReader::new(fd))
+    };
+    let mut importer = Importer {
+        store,
+        encoding,
+        batch: WriteBatch::new(),
+        batch_size,
+        imported: 0,
+        progress: std::io::stderr().is_terminal(),
+        failed: None,
+    };
+    let parsed = match Format::of(sub_cmd) {
+        Format::Json => {
+            let mut parser = serde_json::Deserializer::from_reader(input);
+            parser
+                .deserialize_map(&mut importer)
+                .and_then(|()| parser.end())
+                .map_err(|e| importer.failed.take().unwrap_or_else(|| invalid(file, e)))
+        }
+        Format::Ndjson => serde_json::Deserializer::from_reader(input)
+            .into_iter()
+            .try_for_each(|pair| {
+                let pair: Pair = pair.map_err(|e| invalid(file, e))?;
+                importer.add(&pair.key, &pair.value)
+            }),
+        Format::Csv => csv::Reader::from_reader(input)
+            .deserialize()
+            .try_for_each(|pair| {
+                let pair: Pair = pair.map_err(|e| invalid(file, e))?;
+                importer.add(&pair.key, &pair.value)
+            }),
+    };
+    // the pairs read before a failure are written all the same
+    let flushed = importer.flush();
+    if importer.progress {
+        eprintln!();
+    }
+    eprintln!("imported {}", count_pairs(importer.imported));
+    parsed.and(flushed)
+}
+
+fn count_pairs(count: u64) -> String {
+    match count {
+        1 => "1 pair".to_owned(),
+        n => format!("{} pairs", n),
+    }
+}
+
+fn invalid(file: &str, e: impl std::fmt::Display) -> Failure {
+    let file = if file == "-" { "stdin" } else { file };
+    Failure::Usage(format!("invalid {}: {}", file, e))
+}
+
+/// Write the pairs with a key starting with `--prefix` to a file or stdout,
+/// in key order.
+fn export(store: &KvStore, encoding: Encoding, sub_cmd: &ArgMatches) -> CommandResult {
+    let prefix = encoding.decode_arg(sub_cmd.value_of("prefix").unwrap_or(""))?;
+    let output: Box<dyn Write> = match sub_cmd.value_of("file") {
+        None | Some("-") => Box::new(std::io::stdout().lock()),
+        Some(file) => Box::new(
+            std::fs::File::create(file)
+                .map_err(|e| Failure::Usage(format!("cannot write {}: {}", file, e)))?,
+        ),
+    };
+    let mut output = std::io::BufWriter::new(output);
+    let pairs = store
+        .scan_prefix_bytes(&prefix)
+        .map(|pair| -> Result<Pair> {
+            let (key, value) = pair?;
+            Ok(Pair {
+                key: encoding.encode(key)?,
+                value: encoding.encode(value)?,
+            })
+        });
+    let mut exported = 0;
+    match Format::of(sub_cmd) {
+        Format::Json => {
+            write!(output, "{{").map_err(KvErr::from)?;
+            for pair in pairs {
+                let pair = pair?;
+                let separator = if exported == 0 { "" } else { "," };
+                write!(output, "{}\n  ", separator).map_err(KvErr::from)?;
+                serde_json::to_writer(&mut output, &pair.key).map_err(KvErr::from)?;
+                write!(output, ": ").map_err(KvErr::from)?;
+                serde_json::to_writer(&mut output, &pair.value).map_err(KvErr::from)?;
+                exported += 1;
+            }
+            let end = if exported == 0 { "}" } else { "\n}" };
+            writeln!(output, "{}", end).map_err(KvErr::from)?;
+        }
+        Format::Ndjson => {
+            for pair in pairs {
+                serde_json::to_writer(&mut output, &pair?).map_err(KvErr::from)?;
+                writeln!(output).map_err(KvErr::from)?;
+                exported += 1;
+            }
+        }
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(&mut output);
+            // the header is written with the first row, an empty export needs it too
+            writer
+                .write_record(["key", "value"])
+                .map_err(|e| KvErr::from(std::io::Error::from(e)))?;
+            for pair in pairs {
+                let pair = pair?;
+                writer
+                    .write_record([pair.key, pair.value])
+                    .map_err(|e| KvErr::from(std::io::Error::from(e)))?;
+                exported += 1;
+            }
+            writer.flush().map_err(KvErr::from)?;
+        }
+    }
+    output.flush().map_err(KvErr::from)?;
+    eprintln!("exported {}", count_pairs(exported));
+    Ok(())
+}
+
+fn main() {
+    std::process::exit(app())
 }
@@ -0,0 +1,407 @@
+//! check walks the files of a store without opening it, to find what is
+//! damaged, and salvages the records that are still readable.
+//!
+//! Opening a store replays it and may change it: a bad record truncates its
+//! segment, segments missing from the manifest are deleted and a legacy
+//! database is migrated. The check only reads, so it can be run on a store
+//! that fails to open, or before opening one that looks suspicious.
+use crate::engine::{Engine, ENGINE_FILE};
+use crate::error::KvErr;
+use crate::expiry::{self, Expiring};
+use crate::hint::{Hint, HINT_FILE};
+use crate::index::Index;
+use crate::segment::{
+    self, Manifest, Segment, MANIFEST_FILE, SEGMENT_HEADER_SIZE, U16_FRAME_HEADER_SIZE,
+};
+use crate::stale::StaleBytes;
+use crate::txlog::{LogEntry, LogOperation, LogPointer};
+use crate::writer::apply;
+use crate::{legacy_metadata, Codec, Result, LEGACY_DB_FILE, LEGACY_HEADER_SIZE};
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+/// A record that cannot be read back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BadRecord {
+    /// name of the segment or legacy database file
+    pub file: String,
+    pub offset: u64,
+    pub reason: String,
+}
+
+/// What `KvStore::check` found. Entries are the writes of the readable
+/// records, a write batch counts one entry per write.
+#[derive(Debug, Clone, Default)]
+pub struct CheckReport {
+    /// the files that were walked, in replay order
+    pub files: Vec<String>,
+    /// number of readable records
+    pub records: usize,
+    pub bad_records: Vec<BadRecord>,
+    /// entries that hold the current value of a key
+    pub live_entries: usize,
+    /// entries that were overwritten, removed or have expired
+    pub stale_entries: usize,
+    /// disagreements between the metadata of the store and its files
+    pub mismatches: Vec<String>,
+}
+
+impl CheckReport {
+    /// true if every record is readable and the metadata matches the files
+    pub fn is_clean(&self) -> bool {
+        self.bad_records.is_empty() && self.mismatches.is_empty()
+    }
+}
+
+/// Check the store at `path`, a directory or a legacy database file. With
+/// `out`, every readable record is also copied to a new store there.
+pub(crate) fn check(path: &Path, out: Option<&Path>) -> Result<CheckReport> {
+    // None for a directory of segments, else the legacy database file
+    let legacy = if path.is_file() {
+        Some(path.to_path_buf())
+    } else if path.join(MANIFEST_FILE).exists() || has_segments(path)? {
+        None
+    } else if path.join(LEGACY_DB_FILE).is_file() {
+        Some(path.join(LEGACY_DB_FILE))
+    } else {
+        return Err(KvErr::IOError(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("{} does not hold a store", path.display()),
+        )));
+    };
+
+    let mut salvage = match out {
+        Some(out) => Some(Salvage::new(path, out)?),
+        None => None,
+    };
+    let mut check = Check::default();
+    match legacy {
+        Some(legacy) => check.legacy(&legacy, &mut salvage)?,
+        None => check.segments(path, &mut salvage)?,
+    }
+    if let Some(salvage) = salvage {
+        salvage.finish()?;
+    }
+    Ok(check.finish())
+}
+
+fn has_segments(dir: &Path) -> Result<bool> {
+    if !dir.is_dir() {
+        return Ok(false);
+    }
+    for entry in fs::read_dir(dir)? {
+        if segment::segment_id(&entry?.path()).is_some() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Replays the readable records like opening the store would, to count the
+/// live entries.
+#[derive(Default)]
+struct Check {
+    report: CheckReport,
+    index: Index,
+    stale: StaleBytes,
+    expiring: Expiring,
+    entries: usize,
+}
+
+impl Check {
+    fn segments(&mut self, dir: &Path, salvage: &mut Option<Salvage>) -> Result<()> {
+        match Engine::of(dir) {
+            Ok(Some(Engine::Kvs)) | Ok(None) => {}
+            Ok(Some(engine)) => self.mismatch(format!("the ENGINE file names {}", engine)),
+            Err(e) => self.mismatch(format!("the ENGINE file cannot be read: {}", e)),
+        }
+
+        let mut on_disk = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            if let Some(id) = segment::segment_id(&entry?.path()) {
+                on_disk.push(id);
+            }
+        }
+        on_disk.sort_unstable();
+        let (manifest, listed) = match Manifest::load(dir) {
+            Ok(Some(manifest)) => (manifest, true),
+            Ok(None) => {
+                self.mismatch("there is no MANIFEST, every segment file is checked".to_owned());
+                (Manifest::default(), false)
+            }
+            Err(e) => {
+                self.mismatch(format!(
+                    "the MANIFEST cannot be read, every segment file is checked: {}",
+                    e
+                ));
+                (Manifest::default(), false)
+            }
+        };
+        let order = if listed {
+            manifest.segments.clone()
+        } else {
+            on_disk.clone()
+        };
+        for id in on_disk.iter().filter(|id| listed && !order.contains(id)) {
+            self.mismatch(format!(
+                "{}.{} is not in the MANIFEST, opening the store deletes it",
+                id,
+                segment::SEGMENT_EXT
+            ));
+        }
+        if let Some(salvage) = salvage {
+            salvage.manifest.codec = manifest.codec;
+            salvage.manifest.compression = manifest.compression;
+        }
+
+        let mut segments = HashMap::new();
+        let now = expiry::now();
+        for &id in order.iter() {
+            let file = format!("{}.{}", id, segment::SEGMENT_EXT);
+            if listed && id >= manifest.next_segment_id {
+                self.mismatch(format!(
+                    "{} has an id the MANIFEST has not handed out",
+                    file
+                ));
+            }
+            let segment = match Segment::open(dir, id) {
+                Ok(segment) => segment,
+                Err(KvErr::IOError(ref e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                    self.mismatch(format!("{} is in the MANIFEST but does not exist", file));
+                    continue;
+                }
+                Err(KvErr::CorruptedError(reason)) => {
+                    self.bad(&file, 0, reason);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            self.report.files.push(file.clone());
+
+            let mut offset = SEGMENT_HEADER_SIZE;
+            loop {
+                // a frame with a bad length cannot be stepped over, a frame
+                // with a bad checksum can
+                let size = match segment.frame_size_at(offset) {
+                    Ok(Some(size)) => size,
+                    Ok(None) => break,
+                    Err(KvErr::CorruptedError(reason)) => {
+                        self.bad(&file, offset, reason);
+                        break;
+                    }
+                    Err(e) => return Err(e),
+                };
+                match segment.read_body(offset, size) {
+                    Ok(body) => {
+                        let ptr = LogPointer(id, offset, size);
+                        self.record(&file, manifest.codec, ptr, body, now, salvage)?
+                    }
+                    Err(KvErr::CorruptedError(reason)) => self.bad(&file, offset, reason),
+                    Err(e) => return Err(e),
+                }
+                offset += size as u64;
+            }
+            segments.insert(id, segment);
+        }
+
+        if dir.join(HINT_FILE).exists() {
+            match Hint::load(dir) {
+                Some(hint) if !hint.covers(&order, &segments) => self.mismatch(
+                    "the HINT does not match the segments, opening the store ignores it".to_owned(),
+                ),
+                Some(_) => {}
+                None => self
+                    .mismatch("the HINT cannot be read, opening the store ignores it".to_owned()),
+            }
+        }
+        Ok(())
+    }
+
+    /// A legacy database has a metadata header with the number of records
+    /// and their total size, followed by records framed with a u16 length.
+    fn legacy(&mut self, path: &Path, salvage: &mut Option<Salvage>) -> Result<()> {
+        let file = path.file_name().map_or_else(
+            || LEGACY_DB_FILE.to_owned(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        self.report.files.push(file.clone());
+        let fd = File::open(path)?;
+        let len = fd.metadata()?.len();
+        let metadata = match legacy_metadata(&fd) {
+            Ok(metadata) => Some(metadata),
+            Err(e) => {
+                self.mismatch(format!("the metadata header cannot be read: {}", e));
+                None
+            }
+        };
+
+        let now = expiry::now();
+        let mut offset = LEGACY_HEADER_SIZE;
+        while offset < len {
+            if offset + U16_FRAME_HEADER_SIZE as u64 > len {
+                self.bad(&file, offset, "torn frame header".to_owned());
+                break;
+            }
+            let mut head = [0u8; U16_FRAME_HEADER_SIZE];
+            fd.read_exact_at(&mut head, offset)?;
+            let size = std::io::Cursor::new(&head).read_u16::<LittleEndian>()? as usize;
+            let end = offset + (U16_FRAME_HEADER_SIZE + size) as u64;
+            if end > len {
+                self.bad(&file, offset, "torn record".to_owned());
+                break;
+            }
+            let mut body = vec![0u8; size];
+            fd.read_exact_at(&mut body, offset + U16_FRAME_HEADER_SIZE as u64)?;
+            let ptr = LogPointer(0, offset, U16_FRAME_HEADER_SIZE + size);
+            self.record(&file, Codec::Cbor, ptr, body, now, salvage)?;
+            offset = end;
+        }
+
+        if let Some(metadata) = metadata {
+            let frames = self.report.records + self.report.bad_records.len();
+            if metadata.total_log != frames {
+                self.mismatch(format!(
+                    "total_log is {} but the file holds {} records",
+                    metadata.total_log, frames
+                ));
+            }
+            let bytes = len.saturating_sub(LEGACY_HEADER_SIZE);
+            if metadata.total_bytes as u64 != bytes {
+                self.mismatch(format!(
+                    "total_bytes is {} but the file holds {} bytes of records",
+                    metadata.total_bytes, bytes
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// replay the record at `ptr`, a record that does not decode is bad
+    fn record(
+        &mut self,
+        file: &str,
+        codec: Codec,
+        ptr: LogPointer,
+        body: Vec<u8>,
+        now: u64,
+        salvage: &mut Option<Salvage>,
+    ) -> Result<()> {
+        let op = match codec.decode::<LogEntry>(&body) {
+            Ok(LogEntry(_, op)) => op,
+            Err(e) => {
+                self.bad(file, ptr.1, e.to_string());
+                return Ok(());
+            }
+        };
+        self.report.records += 1;
+        self.entries += match &op {
+            LogOperation::Batch(ops) => ops.len(),
+            _ => 1,
+        };
+        apply(
+            &self.index,
+            &mut self.stale,
+            &mut self.expiring,
+            ptr,
+            op,
+            now,
+        );
+        if let Some(salvage) = salvage {
+            salvage.append(&body)?;
+        }
+        Ok(())
+    }
+
+    fn bad(&mut self, file: &str, offset: u64, reason: String) {
+        self.report.bad_records.push(BadRecord {
+            file: file.to_owned(),
+            offset,
+            reason,
+        });
+    }
+
+    fn mismatch(&mut self, mismatch: String) {
+        self.report.mismatches.push(mismatch);
+    }
+
+    fn finish(mut self) -> CheckReport {
+        self.report.live_entries = self.index.iter().count();
+        self.report.stale_entries = self.entries - self.report.live_entries;
+        self.report
+    }
+}
+
+/// A new store the readable records are copied to, in their order and with
+/// their encoding. The records are kept until the whole source is read and
+/// only then written, so a source that cannot be read leaves `out` as it was.
+/// It is only a store once its manifest is saved.
+struct Salvage {
+    dir: PathBuf,
+    manifest: Manifest,
+    records: Vec<Vec<u8>>,
+}
+
+impl Salvage {
+    /// refuse an `out` that is the source or holds store files of its own,
+    /// writing there could overwrite the records being salvaged
+    fn new(source: &Path, out: &Path) -> Result<Salvage> {
+        let source_dir = if source.is_file() {
+            source.parent().unwrap_or_else(|| Path::new("."))
+        } else {
+            source
+        };
+        let source_dir = fs::canonicalize(source_dir)?;
+        if fs::canonicalize(out).is_ok_and(|out| out == source_dir) {
+            return Err(KvErr::IOError(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} is the store being checked", out.display()),
+            )));
+        }
+        if out.join(MANIFEST_FILE).exists() || out.join(ENGINE_FILE).exists() || has_segments(out)?
+        {
+            return Err(KvErr::IOError(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} already holds a store", out.display()),
+            )));
+        }
+        Ok(Salvage {
+            dir: out.to_path_buf(),
+            manifest: Manifest::default(),
+            records: Vec::new(),
+        })
+    }
+
+    fn append(&mut self, body: &[u8]) -> Result<()> {
+        self.records.push(body.to_vec());
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let mut manifest = Manifest {
+            codec: self.manifest.codec,
+            compression: self.manifest.compression,
+            ..Manifest::new_in(&self.dir, self.manifest.codec)?
+        };
+        let mut segments = vec![Segment::create(&self.dir, manifest.allocate_segment_id())?];
+        for body in self.records.iter() {
+            if segments.last().expect("at least one segment").is_full() {
+                let id = manifest.allocate_segment_id();
+                segments.push(Segment::create(&self.dir, id)?);
+            }
+            segments
+                .last_mut()
+                .expect("at least one segment")
+                .append(body, manifest.compression)?;
+        }
+        for segment in segments.iter() {
+            segment.sync()?;
+        }
+        manifest.segments = segments.iter().map(|s| s.id).collect();
+        manifest.save(&self.dir)?;
+        Engine::Kvs.claim(&self.dir)
+    }
+}
@@ -0,0 +1,77 @@
+//! client talks to a kvs-server with the kvs protocol.
+//!
+use crate::error::KvErr;
+use crate::protocol::{read_message, write_message, ErrorKind, Request, Response};
+use crate::Result;
+use std::io::{BufReader, BufWriter};
+use std::net::{TcpStream, ToSocketAddrs};
+
+pub struct KvsClient {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl KvsClient {
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+        })
+    }
+
+    pub fn get(&mut self, key: String) -> Result<Option<String>> {
+        match self.request(Request::Get { key })? {
+            Response::Ok(value) => Ok(value),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.request(Request::Set { key, value }).map(|_| ())
+    }
+
+    pub fn remove(&mut self, key: String) -> Result<()> {
+        self.request(Request::Remove { key }).map(|_| ())
+    }
+
+    /// Set `key` to `new` if its value is `expected`, returns whether it was
+    /// set.
+    pub fn compare_and_swap(&mut self, key: String, expected: String, new: String) -> Result<bool> {
+        self.conditional(Request::CompareAndSwap { key, expected, new })
+    }
+
+    /// Set `key` if it does not exist, returns whether it was set.
+    pub fn set_if_absent(&mut self, key: String, value: String) -> Result<bool> {
+        self.conditional(Request::SetIfAbsent { key, value })
+    }
+
+    /// Remove `key` if its value is `expected`, returns whether it was removed.
+    pub fn remove_if_equals(&mut self, key: String, expected: String) -> Result<bool> {
+        self.conditional(Request::RemoveIfEquals { key, expected })
+    }
+
+    fn conditional(&mut self, request: Request) -> Result<bool> {
+        match self.request(request)? {
+            Response::Applied(applied) => Ok(applied),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// send `request` and read its response, an error response is an error
+    fn request(&mut self, request: Request) -> Result<Response> {
+        write_message(&mut self.writer, &request)?;
+        match read_message(&mut self.reader)? {
+            Some(Response::Err(ErrorKind::KeyNotFound, _)) => Err(KvErr::KeyNotExistError),
+            Some(Response::Err(_, msg)) => Err(KvErr::ServerError(msg)),
+            Some(response) => Ok(response),
+            None => Err(KvErr::ProtocolError(
+                "connection closed before the response".to_owned(),
+            )),
+        }
+    }
+}
+
+fn unexpected(response: Response) -> KvErr {
+    KvErr::ProtocolError(format!("unexpected response {:?}", response))
+}
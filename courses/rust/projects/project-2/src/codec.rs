@@ -0,0 +1,52 @@
+//! codec defines how a LogEntry is serialized into the body of a frame.
+//!
+//! The codec is chosen when a database is created and recorded in its
+//! manifest, every record of the database uses the same codec.
+use crate::Result;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec {
+    /// Concise Binary Object Representation, see RFC 7049
+    #[default]
+    Cbor,
+    Bincode,
+    /// one JSON document per record followed by a newline, for debugging
+    Json,
+}
+
+impl Codec {
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>> {
+        Ok(match self {
+            Codec::Cbor => serde_cbor::to_vec(value)?,
+            Codec::Bincode => bincode::serialize(value)?,
+            Codec::Json => {
+                let mut data = serde_json::to_vec(value)?;
+                data.push(b'\n');
+                data
+            }
+        })
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, data: &[u8]) -> Result<T> {
+        Ok(match self {
+            Codec::Cbor => serde_cbor::from_slice(data)?,
+            Codec::Bincode => bincode::deserialize(data)?,
+            Codec::Json => serde_json::from_slice(data)?,
+        })
+    }
+}
+
+impl std::str::FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "cbor" => Ok(Codec::Cbor),
+            "bincode" => Ok(Codec::Bincode),
+            "json" => Ok(Codec::Json),
+            _ => Err(format!("unknown codec {}", s)),
+        }
+    }
+}
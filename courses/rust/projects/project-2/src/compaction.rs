@@ -0,0 +1,141 @@
+//! compaction copies the live entries of the sealed segments into a new
+//! generation of segments.
+//!
+//! The sealed segments are never written again, so the copy can run on a
+//! background thread while the store keeps serving reads from the old
+//! generation. The new generation only replaces the old one once the store
+//! saves a manifest that references it, so a crash at any point leaves either
+//! the old or the new generation in place.
+use crate::hint::Hint;
+use crate::segment::Segment;
+use crate::txlog::{LogEntry, LogOperation, LogPointer};
+use crate::{Codec, Compression, Result};
+use serde_bytes::ByteBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+/// everything the compaction thread needs, taken from the store when it starts
+#[derive(Debug)]
+pub struct CompactionTask {
+    pub dir: PathBuf,
+    pub codec: Codec,
+    /// the copies are compressed again with it, whatever the compression of
+    /// the original records
+    pub compression: Compression,
+    /// the sealed segments being compacted, in replay order
+    pub inputs: Vec<u64>,
+    /// ids reserved for the new generation
+    pub output_ids: Vec<u64>,
+    /// the live entries that point into the inputs
+    pub live: Vec<(Vec<u8>, LogPointer)>,
+    /// expiration of the live keys set with a time to live
+    pub expiring: Vec<(Vec<u8>, u64)>,
+    /// the expired keys that point into the inputs, they are not copied
+    pub expired: Vec<(Vec<u8>, LogPointer)>,
+    /// larger than any log id in the inputs
+    pub next_log_id: usize,
+}
+
+#[derive(Debug)]
+pub struct CompactionResult {
+    pub inputs: Vec<u64>,
+    pub outputs: Vec<Segment>,
+    /// tuple (key, old pointer, new pointer)
+    pub moved: Vec<(Vec<u8>, LogPointer, LogPointer)>,
+    pub expired: Vec<(Vec<u8>, LogPointer)>,
+    /// the next log id when the compaction started, the versions below it
+    /// are gone from the outputs
+    pub next_log_id: usize,
+}
+
+impl CompactionTask {
+    pub fn run(mut self) -> Result<CompactionResult> {
+        let mut inputs = HashMap::new();
+        for id in self.inputs.iter() {
+            inputs.insert(*id, Segment::open(&self.dir, *id)?);
+        }
+
+        // read the inputs sequentially
+        self.live.sort_by_key(|(_, ptr)| (ptr.0, ptr.1));
+
+        let mut output_ids = self.output_ids.into_iter();
+        let first_id = output_ids.next().expect("at least one output id");
+        let mut outputs = vec![Segment::create(&self.dir, first_id)?];
+        let mut moved: Vec<(Vec<u8>, LogPointer, LogPointer)> = Vec::with_capacity(self.live.len());
+
+        let mut live = self.live.into_iter().peekable();
+        while let Some((key, old)) = live.next() {
+            // the keys of a batch share its record, it is copied once
+            let mut keys = vec![key];
+            while let Some((key, _)) = live.next_if(|(_, ptr)| *ptr == old) {
+                keys.push(key);
+            }
+            let mut output = outputs.last_mut().expect("at least one output");
+            if output.is_full() {
+                // out of reserved ids, keep appending to the last segment
+                if let Some(id) = output_ids.next() {
+                    outputs.push(Segment::create(&self.dir, id)?);
+                    output = outputs.last_mut().expect("at least one output");
+                }
+            }
+            // records are framed again, old segments may use an older framing
+            let mut body = inputs[&old.0].read_body(old.1, old.2)?;
+            if let LogEntry(log_id, LogOperation::Batch(ops)) = self.codec.decode(&body)? {
+                let ops = live_writes(ops, &keys);
+                body = self
+                    .codec
+                    .encode(&LogEntry(log_id, LogOperation::Batch(ops)))?;
+            }
+            let (offset, size) = output.append(&body, self.compression)?;
+            let new = LogPointer(output.id, offset, size);
+            moved.extend(keys.into_iter().map(|key| (key, old, new)));
+        }
+
+        for output in outputs.iter() {
+            output.sync()?;
+        }
+
+        // the hint is only trusted once a manifest starts with these outputs
+        Hint {
+            segments: outputs.iter().map(|s| (s.id, s.len())).collect(),
+            next_log_id: self.next_log_id,
+            entries: moved
+                .iter()
+                .map(|(key, _, new)| (ByteBuf::from(key.clone()), *new))
+                .collect(),
+            expiring: self
+                .expiring
+                .into_iter()
+                .map(|(key, at)| (ByteBuf::from(key), at))
+                .collect(),
+        }
+        .save(&self.dir)?;
+
+        Ok(CompactionResult {
+            inputs: self.inputs,
+            outputs,
+            moved,
+            expired: self.expired,
+            next_log_id: self.next_log_id,
+        })
+    }
+}
+
+/// Keep the last set of each of the live `keys` of a batch. The other writes
+/// were overwritten or removed since, and replaying them would bring back
+/// keys that were removed after the batch.
+fn live_writes(ops: Vec<LogOperation>, keys: &[Vec<u8>]) -> Vec<LogOperation> {
+    let mut pending: HashSet<&[u8]> = keys.iter().map(Vec::as_slice).collect();
+    let mut live: Vec<LogOperation> = ops
+        .into_iter()
+        .rev()
+        .filter(|op| match op {
+            LogOperation::Set(key, _) | LogOperation::SetExpiring(key, _, _) => {
+                pending.remove(key.as_slice())
+            }
+            _ => false,
+        })
+        .collect();
+    live.reverse();
+    live
+}
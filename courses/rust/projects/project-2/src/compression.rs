@@ -0,0 +1,172 @@
+//! compression defines how the body of a frame is compressed.
+//!
+//! Every frame records its own compression, so records compressed in any way
+//! or not at all can follow each other in a segment. The compression of the
+//! store is recorded in its manifest and only applies to the records written
+//! from then on, a compaction rewrites the older ones.
+use crate::error::KvErr;
+use crate::segment::{Segment, SEGMENT_HEADER_SIZE};
+use crate::Result;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// a compressed body starts with the size of the uncompressed one
+const RAW_SIZE_HEADER: usize = 4;
+const ZSTD_LEVEL: i32 = zstd::DEFAULT_COMPRESSION_LEVEL;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    /// fast, for values that compress well
+    Lz4,
+    /// slower than LZ4 but smaller
+    Zstd,
+}
+
+impl Compression {
+    /// the byte recorded in the frame header
+    pub(crate) fn flag(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz4 => 1,
+            Compression::Zstd => 2,
+        }
+    }
+
+    pub(crate) fn from_flag(flag: u8) -> Result<Compression> {
+        match flag {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Lz4),
+            2 => Ok(Compression::Zstd),
+            _ => Err(KvErr::CorruptedError(format!(
+                "unknown compression flag {}",
+                flag
+            ))),
+        }
+    }
+
+    /// Compress `body`, returns the compression actually used: a body that
+    /// does not get smaller is stored as it is.
+    pub(crate) fn compress(self, body: &[u8]) -> Result<(Compression, Cow<'_, [u8]>)> {
+        let compressed = match self {
+            Compression::None => return Ok((Compression::None, Cow::Borrowed(body))),
+            Compression::Lz4 => lz4_flex::block::compress(body),
+            Compression::Zstd => zstd::bulk::compress(body, ZSTD_LEVEL)?,
+        };
+        if compressed.len() + RAW_SIZE_HEADER >= body.len() {
+            return Ok((Compression::None, Cow::Borrowed(body)));
+        }
+        let mut stored = Vec::with_capacity(RAW_SIZE_HEADER + compressed.len());
+        stored.write_u32::<LittleEndian>(body.len() as u32)?;
+        stored.extend_from_slice(&compressed);
+        Ok((self, Cow::Owned(stored)))
+    }
+
+    pub(crate) fn decompress(self, stored: Vec<u8>) -> Result<Vec<u8>> {
+        if self == Compression::None {
+            return Ok(stored);
+        }
+        let size = self.raw_size(&stored)?;
+        let compressed = &stored[RAW_SIZE_HEADER..];
+        let body = match self {
+            Compression::Lz4 => lz4_flex::block::decompress(compressed, size)
+                .map_err(|e| KvErr::CorruptedError(e.to_string()))?,
+            Compression::Zstd => zstd::bulk::decompress(compressed, size)
+                .map_err(|e| KvErr::CorruptedError(e.to_string()))?,
+            Compression::None => unreachable!("returned above"),
+        };
+        if body.len() != size {
+            return Err(KvErr::CorruptedError(format!(
+                "record decompressed to {} bytes instead of {}",
+                body.len(),
+                size
+            )));
+        }
+        Ok(body)
+    }
+
+    /// the size of a stored body once decompressed, without decompressing it
+    fn raw_size(self, stored: &[u8]) -> Result<usize> {
+        if self == Compression::None {
+            return Ok(stored.len());
+        }
+        if stored.len() < RAW_SIZE_HEADER {
+            return Err(KvErr::CorruptedError(
+                "compressed record without its size".to_owned(),
+            ));
+        }
+        Ok(std::io::Cursor::new(stored).read_u32::<LittleEndian>()? as usize)
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "lz4" => Ok(Compression::Lz4),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(format!("unknown compression {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Compression::None => "none",
+            Compression::Lz4 => "lz4",
+            Compression::Zstd => "zstd",
+        })
+    }
+}
+
+/// How much the records of a store are compressed, returned by
+/// `KvStore::compression_stats`. Sizes are of the record bodies, without
+/// their frame headers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// the compression new records are written with
+    pub compression: Compression,
+    pub records: u64,
+    pub compressed_records: u64,
+    /// bytes the bodies take in the segments
+    pub stored_bytes: u64,
+    /// bytes the bodies take once decompressed
+    pub raw_bytes: u64,
+}
+
+impl CompressionStats {
+    /// raw bytes per stored byte, 1 for an empty store
+    pub fn ratio(&self) -> f64 {
+        match self.stored_bytes {
+            0 => 1.0,
+            stored => self.raw_bytes as f64 / stored as f64,
+        }
+    }
+
+    /// Walk the frames of `segments`, their bodies are not decompressed.
+    pub(crate) fn collect(compression: Compression, segments: &[Segment]) -> Result<Self> {
+        let mut stats = CompressionStats {
+            compression,
+            ..CompressionStats::default()
+        };
+        for segment in segments {
+            let mut offset = SEGMENT_HEADER_SIZE;
+            while let Some(size) = segment.frame_size_at(offset)? {
+                let (compression, stored) = segment.read_frame(offset, size)?;
+                stats.records += 1;
+                if compression != Compression::None {
+                    stats.compressed_records += 1;
+                }
+                stats.stored_bytes += stored.len() as u64;
+                stats.raw_bytes += compression.raw_size(&stored)? as u64;
+                offset += size as u64;
+            }
+        }
+        Ok(stats)
+    }
+}
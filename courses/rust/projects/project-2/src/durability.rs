@@ -0,0 +1,110 @@
+//! durability decides when the records a write appends are flushed to disk.
+//!
+//! With group commit a write is appended under the writer lock, but waits for
+//! its fsync after releasing it. The first writer that waits runs the fsync for
+//! every record appended so far, the others wait for it and find their record
+//! covered, so concurrent writers share fsyncs instead of queueing behind them.
+use crate::Result;
+use std::fs::File;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+/// When a write is flushed to disk before it returns, see
+/// `OpenOptions::durability`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /// fsync the log after every write. A write that returned survives a
+    /// crash of the machine.
+    Always,
+    /// Writes wait for an fsync that covers their record, one fsync covers
+    /// the writes of all the threads that were waiting for it. Gives the same
+    /// guarantee as `Always` with fewer fsyncs under concurrent writes.
+    Group,
+    /// Leave writes in the OS page cache. A write that returned survives a
+    /// crash of the process, but the last writes before a crash of the machine
+    /// can be lost. `KvStore::save` flushes them.
+    #[default]
+    Buffered,
+}
+
+/// Tracks which records are on disk for group commit. Records are counted
+/// in the order they are appended.
+#[derive(Debug)]
+pub struct GroupCommit {
+    state: Mutex<CommitState>,
+    synced: Condvar,
+}
+
+#[derive(Debug)]
+struct CommitState {
+    /// number of records appended
+    appended: usize,
+    /// number of records known to be on disk
+    synced: usize,
+    /// whether a waiter is running an fsync
+    syncing: bool,
+    /// the segment records are appended to
+    active: Arc<File>,
+    /// fsyncs of the active segment, shared with the writer
+    syncs: Arc<AtomicU64>,
+}
+
+impl GroupCommit {
+    /// `appended` records are already in the log, all of them on disk
+    pub fn new(active: File, appended: usize, syncs: Arc<AtomicU64>) -> Self {
+        Self {
+            state: Mutex::new(CommitState {
+                appended,
+                synced: appended,
+                syncing: false,
+                active: Arc::new(active),
+                syncs,
+            }),
+            synced: Condvar::new(),
+        }
+    }
+
+    /// the writer appended records up to `appended`
+    pub fn appended(&self, appended: usize) {
+        self.state.lock().unwrap().appended = appended;
+    }
+
+    /// The writer synced the previous active segment and moved on to
+    /// `active`, the records appended so far are on disk.
+    pub fn rolled(&self, active: File) {
+        let mut state = self.state.lock().unwrap();
+        state.synced = state.appended;
+        state.active = Arc::new(active);
+        self.synced.notify_all();
+    }
+
+    /// Wait until the first `appended` records are on disk, running the
+    /// fsync if no one else is.
+    pub fn wait(&self, appended: usize) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.synced >= appended {
+                return Ok(());
+            }
+            if !state.syncing {
+                break;
+            }
+            state = self.synced.wait(state).unwrap();
+        }
+
+        state.syncing = true;
+        let target = state.appended;
+        let active = state.active.clone();
+        state.syncs.fetch_add(1, Ordering::Relaxed);
+        drop(state);
+        let result = active.sync_all();
+
+        let mut state = self.state.lock().unwrap();
+        state.syncing = false;
+        if result.is_ok() {
+            state.synced = state.synced.max(target);
+        }
+        self.synced.notify_all();
+        Ok(result?)
+    }
+}
@@ -0,0 +1,173 @@
+//! engine defines the interface shared by the storage engines, and records
+//! which engine a data directory belongs to.
+//!
+use crate::error::KvErr;
+use crate::segment::{segment_id, MANIFEST_FILE};
+use crate::{KvStore, Result, LEGACY_DB_FILE};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// the file that records the engine of a data directory
+pub const ENGINE_FILE: &str = "ENGINE";
+/// files sled keeps at the top of its directory
+const SLED_FILES: [&str; 3] = ["conf", "db", "blobs"];
+
+/// A key value store engine. Clones of an engine share the same data and
+/// can be used from different threads at the same time.
+pub trait KvsEngine: Clone + Send + 'static {
+    /// Set the value of a string key to a string, overwriting any previous value.
+    fn set(&self, key: String, value: String) -> Result<()>;
+
+    /// Get the value of a string key, `None` if the key does not exist.
+    fn get(&self, key: String) -> Result<Option<String>>;
+
+    /// Remove a key, it is an error if the key does not exist.
+    fn remove(&self, key: String) -> Result<()>;
+
+    /// Set `key` to `new` if its value is `expected`, returns whether it was
+    /// set. The check and the write are atomic relative to other writers.
+    fn compare_and_swap(&self, key: String, expected: String, new: String) -> Result<bool>;
+
+    /// Set `key` if it does not exist, returns whether it was set.
+    fn set_if_absent(&self, key: String, value: String) -> Result<bool>;
+
+    /// Remove `key` if its value is `expected`, returns whether it was removed.
+    fn remove_if_equals(&self, key: String, expected: String) -> Result<bool>;
+}
+
+impl KvsEngine for KvStore {
+    fn set(&self, key: String, value: String) -> Result<()> {
+        KvStore::set(self, key, value)
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        KvStore::get(self, key)
+    }
+
+    fn remove(&self, key: String) -> Result<()> {
+        KvStore::remove(self, key)
+    }
+
+    fn compare_and_swap(&self, key: String, expected: String, new: String) -> Result<bool> {
+        KvStore::compare_and_swap(self, key, expected, new)
+    }
+
+    fn set_if_absent(&self, key: String, value: String) -> Result<bool> {
+        KvStore::set_if_absent(self, key, value)
+    }
+
+    fn remove_if_equals(&self, key: String, expected: String) -> Result<bool> {
+        KvStore::remove_if_equals(self, key, expected)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    Kvs,
+    Sled,
+}
+
+impl Engine {
+    /// the engine recorded in `dir`, if any
+    pub fn of(dir: &Path) -> Result<Option<Engine>> {
+        let path = dir.join(ENGINE_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let name = fs::read_to_string(path)?;
+        name.trim()
+            .parse()
+            .map(Some)
+            .map_err(KvErr::WrongEngineError)
+    }
+
+    /// The engine whose files are in `dir`, for the directories written
+    /// before the engine was recorded.
+    fn detect(dir: &Path) -> Result<Option<Engine>> {
+        let mut found = None;
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("");
+            if SLED_FILES.contains(&name) || name.starts_with("snap.") {
+                return Ok(Some(Engine::Sled));
+            }
+            if name == MANIFEST_FILE || name == LEGACY_DB_FILE || segment_id(&path).is_some() {
+                found = Some(Engine::Kvs);
+            }
+        }
+        Ok(found)
+    }
+
+    /// Fail unless `dir` can belong to this engine: its engine file names
+    /// it, or it has none and holds no files of another engine. Nothing is
+    /// written, a missing directory can belong to any engine.
+    pub fn verify(self, dir: &Path) -> Result<()> {
+        if !dir.exists() {
+            return Ok(());
+        }
+        if !dir.is_dir() {
+            return Err(KvErr::WrongEngineError(format!(
+                "{} is not a directory",
+                dir.display()
+            )));
+        }
+        let path = dir.join(ENGINE_FILE);
+        if path.exists() {
+            let found = fs::read_to_string(&path)?;
+            if found.trim() != self.to_string() {
+                return Err(KvErr::WrongEngineError(format!(
+                    "{} holds {} data, cannot open it with {}",
+                    dir.display(),
+                    found.trim(),
+                    self
+                )));
+            }
+        } else if let Some(found) = Engine::detect(dir)?.filter(|found| *found != self) {
+            return Err(KvErr::WrongEngineError(format!(
+                "{} holds {} data without an {} file, cannot open it with {}",
+                dir.display(),
+                found,
+                ENGINE_FILE,
+                self
+            )));
+        }
+        Ok(())
+    }
+
+    /// Make sure `dir` belongs to this engine, a directory without an engine
+    /// file is claimed by recording this engine in it.
+    pub fn claim(self, dir: &Path) -> Result<()> {
+        self.verify(dir)?;
+        let path = dir.join(ENGINE_FILE);
+        if !path.exists() {
+            fs::write(path, self.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Engine::Kvs => write!(f, "kvs"),
+            Engine::Sled => write!(f, "sled"),
+        }
+    }
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "kvs" => Ok(Engine::Kvs),
+            "sled" => Ok(Engine::Sled),
+            _ => Err(format!("unknown engine {}", s)),
+        }
+    }
+}
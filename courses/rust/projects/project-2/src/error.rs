@@ -1,11 +1,32 @@
 use std::convert::From;
 use std::fmt;
 
+type BoxedError = Box<dyn std::error::Error + Send + Sync>;
+
 #[derive(Debug)]
 pub enum KvErr {
     IOError(std::io::Error),
-    ParseError(serde_cbor::error::Error),
+    /// an entry or the metadata could not be serialized or deserialized
+    ParseError(BoxedError),
     KeyNotExistError,
+    CorruptedError(String),
+    EntryTooLargeError(usize),
+    /// the data directory belongs to another engine
+    WrongEngineError(String),
+    SledError(sled::Error),
+    Utf8Error(std::string::FromUtf8Error),
+    /// a message did not follow the kvs protocol
+    ProtocolError(String),
+    /// the server failed to serve a request
+    ServerError(String),
+    /// a thread pool could not be built
+    ThreadPoolError(String),
+    /// the store cannot be read at a version any more, or not yet
+    VersionError(String),
+    /// the path holds no store and it was not to be created
+    StoreNotExistError(String),
+    /// the thread of a background compaction panicked
+    CompactionError(String),
 }
 
 impl From<std::io::Error> for KvErr {
@@ -16,16 +37,53 @@ impl From<std::io::Error> for KvErr {
 
 impl From<serde_cbor::error::Error> for KvErr {
     fn from(err: serde_cbor::error::Error) -> Self {
+        KvErr::ParseError(Box::new(err))
+    }
+}
+
+impl From<bincode::Error> for KvErr {
+    fn from(err: bincode::Error) -> Self {
         KvErr::ParseError(err)
     }
 }
 
+impl From<serde_json::Error> for KvErr {
+    fn from(err: serde_json::Error) -> Self {
+        KvErr::ParseError(Box::new(err))
+    }
+}
+
+impl From<sled::Error> for KvErr {
+    fn from(err: sled::Error) -> Self {
+        KvErr::SledError(err)
+    }
+}
+
+impl From<std::string::FromUtf8Error> for KvErr {
+    fn from(err: std::string::FromUtf8Error) -> Self {
+        KvErr::Utf8Error(err)
+    }
+}
+
 impl fmt::Display for KvErr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             KvErr::IOError(e) => write!(f, "IOError: {}", e),
             KvErr::ParseError(e) => write!(f, "ParseError: {}", e),
             KvErr::KeyNotExistError => write!(f, "Key not found"),
+            KvErr::CorruptedError(e) => write!(f, "CorruptedError: {}", e),
+            KvErr::EntryTooLargeError(size) => {
+                write!(f, "EntryTooLargeError: entry of {} bytes", size)
+            }
+            KvErr::WrongEngineError(e) => write!(f, "WrongEngineError: {}", e),
+            KvErr::SledError(e) => write!(f, "SledError: {}", e),
+            KvErr::Utf8Error(e) => write!(f, "Utf8Error: {}", e),
+            KvErr::ProtocolError(e) => write!(f, "ProtocolError: {}", e),
+            KvErr::ServerError(e) => write!(f, "ServerError: {}", e),
+            KvErr::ThreadPoolError(e) => write!(f, "ThreadPoolError: {}", e),
+            KvErr::VersionError(e) => write!(f, "VersionError: {}", e),
+            KvErr::StoreNotExistError(e) => write!(f, "StoreNotExistError: {}", e),
+            KvErr::CompactionError(e) => write!(f, "CompactionError: {}", e),
         }
     }
 }
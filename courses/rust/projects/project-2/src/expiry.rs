@@ -0,0 +1,48 @@
+//! expiry keeps track of the keys that were set with a time to live.
+//!
+//! Expiration times are wall-clock milliseconds since the UNIX epoch, stored
+//! in the record, so a key expires at the same time whether it is read from a
+//! running store or after the log is replayed.
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// milliseconds since the UNIX epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// when a key set now with a time to live of `ttl` expires
+pub fn expires_at(ttl: Duration) -> u64 {
+    now().saturating_add(ttl.as_millis() as u64)
+}
+
+/// The expiration time of every live key set with a time to live. Only the
+/// writer changes it, readers check the time stored in the record instead.
+#[derive(Debug, Default)]
+pub struct Expiring {
+    at: HashMap<Vec<u8>, u64>,
+}
+
+impl Expiring {
+    /// record the expiration of `key`, None if it does not expire
+    pub fn set(&mut self, key: &[u8], expires_at: Option<u64>) {
+        match expires_at {
+            Some(at) => {
+                self.at.insert(key.to_owned(), at);
+            }
+            None => {
+                self.at.remove(key);
+            }
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<u64> {
+        self.at.get(key).copied()
+    }
+
+    pub fn is_expired(&self, key: &[u8], now: u64) -> bool {
+        self.get(key).is_some_and(|at| at <= now)
+    }
+}
@@ -0,0 +1,77 @@
+//! hint is a Bitcask style hint file written alongside compaction, so that
+//! opening a store does not need to replay the compacted segments.
+//!
+//! The hint maps every key of a compacted generation to its log pointer. It
+//! is only used if the segments it covers are still the first segments of
+//! the manifest, with the same length, otherwise the log is fully replayed.
+use crate::segment::Segment;
+use crate::txlog::LogPointer;
+use crate::Result;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+pub const HINT_FILE: &str = "HINT";
+const HINT_TMP_FILE: &str = "HINT.tmp";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Hint {
+    /// tuple (segment id, segment length) of the covered segments, in replay order
+    pub segments: Vec<(u64, u64)>,
+    /// larger than any log id in the covered segments
+    pub next_log_id: usize,
+    /// keys are byte strings, hints written when keys were strings still load
+    pub entries: Vec<(ByteBuf, LogPointer)>,
+    /// expiration of the entries set with a time to live, hints written
+    /// before keys could expire have none
+    #[serde(default)]
+    pub expiring: Vec<(ByteBuf, u64)>,
+}
+
+impl Hint {
+    /// Load the hint file, returns None if there is none or it is corrupted.
+    pub fn load(dir: &Path) -> Option<Hint> {
+        let data = fs::read(dir.join(HINT_FILE)).ok()?;
+        if data.len() < 4 {
+            return None;
+        }
+        let crc = std::io::Cursor::new(&data[..4])
+            .read_u32::<LittleEndian>()
+            .ok()?;
+        if crc != crc32fast::hash(&data[4..]) {
+            return None;
+        }
+        serde_cbor::from_slice(&data[4..]).ok()
+    }
+
+    /// write the hint to a temporary file and atomically rename it
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let body = serde_cbor::to_vec(self)?;
+        let mut data = Vec::with_capacity(body.len() + 4);
+        data.write_u32::<LittleEndian>(crc32fast::hash(&body))?;
+        data.extend_from_slice(&body);
+
+        let tmp = dir.join(HINT_TMP_FILE);
+        let mut fd = File::create(&tmp)?;
+        fd.write_all(&data)?;
+        fd.sync_all()?;
+        fs::rename(tmp, dir.join(HINT_FILE))?;
+        Ok(())
+    }
+
+    /// true if the covered segments are the first ones in `order` and have not changed
+    pub fn covers(&self, order: &[u64], segments: &HashMap<u64, Segment>) -> bool {
+        self.segments.len() <= order.len()
+            && self
+                .segments
+                .iter()
+                .zip(order)
+                .all(|((id, len), expected)| {
+                    id == expected && segments.get(id).map(|s| s.len()) == Some(*len)
+                })
+    }
+}
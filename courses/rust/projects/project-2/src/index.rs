@@ -0,0 +1,62 @@
+//! index maps every live key to the position of its latest record.
+//!
+use crate::txlog::LogPointer;
+use crossbeam_skiplist::SkipMap;
+use crossbeam_utils::atomic::AtomicCell;
+use std::ops::Bound;
+
+/// The index is read without locking by every clone of a store, and only
+/// changed by the writer. The pointer of an existing key is swapped in place,
+/// replacing its skiplist entry would hide the key from readers for a moment.
+#[derive(Debug, Default)]
+pub struct Index {
+    map: SkipMap<Vec<u8>, AtomicCell<LogPointer>>,
+}
+
+impl Index {
+    pub fn get(&self, key: &[u8]) -> Option<LogPointer> {
+        self.map.get(key).map(|entry| entry.value().load())
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// point `key` to `ptr`, returns the previous pointer
+    pub fn insert(&self, key: Vec<u8>, ptr: LogPointer) -> Option<LogPointer> {
+        match self.map.get(&key) {
+            Some(entry) => Some(entry.value().swap(ptr)),
+            None => {
+                self.map.insert(key, AtomicCell::new(ptr));
+                None
+            }
+        }
+    }
+
+    pub fn remove(&self, key: &[u8]) -> Option<LogPointer> {
+        self.map.remove(key).map(|entry| entry.value().load())
+    }
+
+    /// the first entry with a key within `lower` and `upper`
+    pub fn first_in(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Option<(Vec<u8>, LogPointer)> {
+        self.map
+            .range::<[u8], _>((lower, upper))
+            .next()
+            .map(|entry| (entry.key().clone(), entry.value().load()))
+    }
+
+    /// the entries in key order
+    pub fn iter(&self) -> impl Iterator<Item = (Vec<u8>, LogPointer)> + '_ {
+        self.map
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().load()))
+    }
+}
@@ -1,25 +1,96 @@
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt};
 use serde::{Deserialize, Serialize};
-use serde_cbor;
-use std::collections::HashMap;
-use std::io::{Read, Seek, SeekFrom, Write};
-use std::ops::Deref;
+use std::ops::{Bound, RangeBounds};
 use std::os::unix::fs::FileExt;
-use std::path::PathBuf;
-
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+mod async_engine;
+mod async_server;
+mod batch;
+mod check;
+mod client;
+mod codec;
+mod compaction;
+mod compression;
+mod durability;
+mod engine;
 pub mod error;
+mod expiry;
+mod hint;
+mod index;
+mod metrics;
+mod mvcc;
+mod options;
+pub mod protocol;
+mod reader;
+mod recovery;
+mod scan;
+mod segment;
+mod server;
+mod sled_engine;
+mod snapshot;
+mod stale;
+mod stats;
+pub mod thread_pool;
 mod txlog;
+mod writer;
+pub use async_engine::AsyncEngine;
+pub use async_server::AsyncKvsServer;
+pub use batch::WriteBatch;
+pub use check::{BadRecord, CheckReport};
+pub use client::KvsClient;
+pub use codec::Codec;
+pub use compression::{Compression, CompressionStats};
+pub use durability::Durability;
+use durability::GroupCommit;
+use engine::ENGINE_FILE;
+pub use engine::{Engine, KvsEngine};
 use error::KvErr;
+use index::Index;
+pub use metrics::MetricsServer;
+pub use mvcc::ReadTransaction;
+use mvcc::Versions;
+pub use options::OpenOptions;
+use reader::{KvStoreReader, LiveSegments};
+pub use recovery::{RecoveryReport, Truncation};
+pub use scan::{Scan, ScanBytes};
+use segment::{Manifest, Segment, MANIFEST_FILE, U16_FRAME_HEADER_SIZE};
+pub use server::KvsServer;
+pub use sled_engine::SledKvsEngine;
+pub use snapshot::Snapshot;
+use stats::Latencies;
+pub use stats::{Latency, Stats};
 use txlog::{LogEntry, LogOperation, LogPointer};
-
-#[derive(Debug)]
+use writer::KvStoreWriter;
+
+/// name of the single file database used before the log was split into segments
+pub const LEGACY_DB_FILE: &str = "test.db";
+const LEGACY_HEADER_SIZE: u64 = 1024;
+
+/// A log-structured key value store.
+///
+/// A KvStore is cheap to clone and every clone can be moved to another
+/// thread. Reads go through the lock-free index and file handles that no
+/// other thread uses at the same time, so they never wait for each other or
+/// for writes. Writes from all
+/// the clones go through a single writer and are serialized.
+#[derive(Debug, Clone)]
 pub struct KvStore {
-    fd: std::fs::File,
-    total_log: usize,
-    total_bytes: usize,
-    index: HashMap<String, LogPointer>,
+    codec: Codec,
+    index: Arc<Index>,
+    /// the older values of the keys, for reads at a version
+    versions: Arc<Versions>,
+    reader: KvStoreReader,
+    writer: Arc<Mutex<KvStoreWriter>>,
+    /// set with `Durability::Group`, writes wait on it for their fsync
+    commit: Option<Arc<GroupCommit>>,
+    recovery: Arc<RecoveryReport>,
+    latencies: Arc<Latencies>,
 }
 
+/// metadata header of the legacy single file database
 #[derive(Serialize, Deserialize, Debug, Default)]
 struct Metadata {
     total_log: usize,
@@ -27,211 +98,586 @@ struct Metadata {
 }
 
 pub type Result<T> = std::result::Result<T, KvErr>;
-type LogSize = usize;
-
-struct Log(LogSize, LogEntry);
 
 impl KvStore {
+    /// Open the store kept in directory `path`. If `path` is a legacy single
+    /// file database, its parent directory is used and the file is migrated.
     pub fn open(path: impl Into<PathBuf>) -> Result<KvStore> {
-        let pb = path.into();
-        let p = if pb.is_dir() {
-            let mut tmp = PathBuf::from(&pb);
-            tmp.push("test.db");
-            tmp
-        } else {
-            pb
-        };
-
-        let fd = std::fs::OpenOptions::new()
-            .read(true)
-            .create(true)
-            .write(true)
-            .open(p)?;
+        OpenOptions::new().open(path)
+    }
 
-        // the first 1Kb is used to store the metadata
-        // the first 4 bytes are used to identify the size of metadata structure
-        let mut buf = vec![0u8; 1024];
-        let mut metadata = Metadata::default();
+    /// Walk the store at `path` without opening it and report bad records,
+    /// live and stale entries and metadata that does not match the files,
+    /// see `CheckReport`. `path` is a directory or a legacy database file,
+    /// nothing in it is changed.
+    pub fn check(path: impl AsRef<Path>) -> Result<CheckReport> {
+        check::check(path.as_ref(), None)
+    }
 
-        if fd.metadata()?.len() < 1024 {
-            let mut head = &mut buf[0..4];
+    /// Check the store at `path` like `check`, and copy every readable record
+    /// to a new store in the directory `out`, which must not hold a store
+    /// already. The copy opens cleanly whatever was wrong with the original.
+    pub fn repair(path: impl AsRef<Path>, out: impl AsRef<Path>) -> Result<CheckReport> {
+        check::check(path.as_ref(), Some(out.as_ref()))
+    }
 
-            let md_vec = serde_cbor::to_vec(&metadata)?;
-            head.write_u32::<LittleEndian>(md_vec.len() as u32)?;
-            buf[4..md_vec.len() + 4].clone_from_slice(&md_vec);
-            fd.write_at(&buf, 0)?;
+    pub(crate) fn open_with_options(
+        path: impl Into<PathBuf>,
+        options: OpenOptions,
+    ) -> Result<KvStore> {
+        let path = path.into();
+        let (dir, legacy) = if path.is_file() {
+            // the only file a store can be opened from is a legacy database,
+            // which is removed once migrated
+            if !is_legacy_db(&path)? {
+                return Err(KvErr::WrongEngineError(format!(
+                    "{} is not a kvs database",
+                    path.display()
+                )));
+            }
+            let dir = match path.parent() {
+                Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+                _ => PathBuf::from("."),
+            };
+            (dir, path)
         } else {
-            fd.read_exact_at(&mut buf, 0)?;
-            let mut cursor = std::io::Cursor::new(&buf[0..4]);
-            let offset = (cursor.read_u32::<LittleEndian>()? + 4) as usize;
-            metadata = serde_cbor::from_reader(&buf[4..offset])?;
+            let legacy = path.join(LEGACY_DB_FILE);
+            (path, legacy)
+        };
+        // nothing is written before the path is known to be a kvs store or
+        // free to become one
+        Engine::Kvs.verify(&dir)?;
+        // only a directory that held a store already has leftovers of its own
+        let existed = dir.join(ENGINE_FILE).is_file() || dir.join(MANIFEST_FILE).is_file();
+        if !existed && !legacy.is_file() {
+            if !options.create {
+                return Err(KvErr::StoreNotExistError(format!(
+                    "{} holds no kvs store",
+                    dir.display()
+                )));
+            }
+            std::fs::create_dir_all(&dir)?;
         }
-        let mut kvstore = Self {
-            fd,
-            total_log: metadata.total_log,
-            total_bytes: metadata.total_bytes,
-            index: HashMap::new(),
+
+        let mut manifest = match Manifest::load(&dir)? {
+            Some(manifest) => {
+                // the migration finished but the legacy file was not removed yet
+                if is_legacy_db(&legacy)? {
+                    std::fs::remove_file(&legacy)?;
+                }
+                manifest
+            }
+            None if legacy.is_file() => migrate_legacy(&dir, &legacy)?,
+            // the manifest is the only list of the segments, without it they
+            // cannot be told from leftovers
+            None if existed => {
+                return Err(KvErr::CorruptedError(format!(
+                    "{} has no MANIFEST, run `kvs check --repair` to salvage its segments",
+                    dir.display()
+                )))
+            }
+            None => {
+                let mut manifest = Manifest::new_in(&dir, options.codec)?;
+                let id = manifest.allocate_segment_id();
+                Segment::create(&dir, id)?;
+                manifest.segments.push(id);
+                manifest.save(&dir)?;
+                manifest
+            }
         };
+        if existed {
+            manifest.remove_unreferenced(&dir)?;
+        }
+        if let Some(compression) = options.compression {
+            if compression != manifest.compression {
+                manifest.compression = compression;
+                manifest.save(&dir)?;
+            }
+        }
 
-        kvstore.replay_log()?;
+        let dir = Arc::new(dir);
+        let codec = manifest.codec;
+        let index = Arc::new(Index::default());
+        let versions = Arc::new(Versions::default());
+        let live = Arc::new(LiveSegments::default());
+        let (writer, recovery) = KvStoreWriter::open(
+            dir.clone(),
+            options,
+            manifest,
+            index.clone(),
+            versions.clone(),
+            live.clone(),
+        )?;
+        Engine::Kvs.claim(&dir)?;
+
+        Ok(Self {
+            reader: KvStoreReader::new(dir, live),
+            codec,
+            index,
+            versions,
+            commit: writer.group_commit(),
+            writer: Arc::new(Mutex::new(writer)),
+            recovery: Arc::new(recovery),
+            latencies: Arc::new(Latencies::default()),
+        })
+    }
 
-        Ok(kvstore)
+    /// what was discarded while replaying the log when the store was opened
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery
     }
 
-    fn save_metadata(&mut self) -> Result<()> {
-        let metadata = Metadata {
-            total_log: self.total_log,
-            total_bytes: self.total_bytes,
-        };
-        let md = serde_cbor::to_vec(&metadata)?;
-        let mut buf = vec![0u8; 1024];
-        let mut head = &mut buf[0..4];
-        head.write_u32::<LittleEndian>(md.len() as u32)?;
+    pub fn save(&self) -> Result<()> {
+        self.writer.lock().unwrap().sync()
+    }
 
-        buf[4..md.len() + 4].clone_from_slice(&md);
+    /// Get the value of a string key. It is a `Utf8Error` if the value was
+    /// set through the bytes API and is not valid UTF-8.
+    pub fn get(&self, key: String) -> Result<Option<String>> {
+        match self.get_bytes(key.as_bytes())? {
+            Some(value) => Ok(Some(String::from_utf8(value)?)),
+            None => Ok(None),
+        }
+    }
 
-        self.fd.write_all_at(&buf, 0)?;
-        Ok(())
+    /// Get the value of a key as raw bytes. Strings are stored as their
+    /// UTF-8 bytes, so this also reads the keys set through the string API.
+    pub fn get_bytes(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.latencies.reads.time(|| match self.index.get(key) {
+            Some(ptr) => self.read_value(key, ptr),
+            None => Ok(None),
+        })
     }
 
-    fn replay_log(&mut self) -> Result<()> {
-        let mut offset: usize = 1024;
+    /// The version of the store now: the number of records written since it
+    /// was created. Any later write gets a higher version.
+    pub fn version(&self) -> usize {
+        self.versions.current()
+    }
 
-        for _ in 0..self.total_log {
-            let Log(size, lp) = self.read_log(offset)?;
-            match lp.1 {
-                LogOperation::Set(key, _) => {
-                    self.index.insert(key, LogPointer(offset - 1024, size + 2));
+    /// Get the value `key` had at `version`, see `get_bytes_at`.
+    pub fn get_at(&self, key: String, version: usize) -> Result<Option<String>> {
+        match self.get_bytes_at(key.as_bytes(), version)? {
+            Some(value) => Ok(Some(String::from_utf8(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the value `key` had at `version`, a version returned by `version`
+    /// before. Versions are kept from when the store was opened until a
+    /// compaction starts after them, unless a read transaction still uses
+    /// them. Other versions are a `VersionError`. A time to live is checked
+    /// against the clock now, like in `get`.
+    ///
+    /// ```no_run
+    /// # fn main() -> kvs::Result<()> {
+    /// let store = kvs::KvStore::open("data")?;
+    /// store.set("key".to_owned(), "old".to_owned())?;
+    /// let version = store.version();
+    /// store.set("key".to_owned(), "new".to_owned())?;
+    /// assert_eq!(store.get_at("key".to_owned(), version)?, Some("old".to_owned()));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn get_bytes_at(&self, key: &[u8], version: usize) -> Result<Option<Vec<u8>>> {
+        self.latencies.reads.time(|| {
+            self.versions.check(version)?;
+            loop {
+                // the latest record, if it is older than the version
+                if let Some(ptr) = self.index.get(key) {
+                    match self.read_entry(&ptr)? {
+                        Some(LogEntry(id, op)) if id < version => return self.value(key, op),
+                        Some(_) => {}
+                        // compacted away since the lookup
+                        None => continue,
+                    }
                 }
-                LogOperation::Rm(key) => {
-                    self.index.remove(&key);
+                let ptr = match self.versions.superseded(key, version)? {
+                    Some(ptr) => ptr,
+                    None => return Ok(None),
+                };
+                match self.read_entry(&ptr)? {
+                    Some(LogEntry(id, op)) if id < version => return self.value(key, op),
+                    // the key was written for the first time after the version
+                    Some(_) => return Ok(None),
+                    None => continue,
                 }
             }
-            offset += size + 2;
+        })
+    }
+
+    /// Begin a read transaction at the version of the store now, see
+    /// `ReadTransaction`. Compactions keep what it can read until it is
+    /// dropped.
+    pub fn read_transaction(&self) -> ReadTransaction {
+        // a compaction cannot be installed while the version is pinned
+        let _writer = self.writer.lock().unwrap();
+        ReadTransaction::new(self.clone(), self.versions.pin())
+    }
+
+    /// the record `ptr` points to, None if its segment was compacted away
+    fn read_entry(&self, ptr: &LogPointer) -> Result<Option<LogEntry>> {
+        match self.reader.read_body(ptr)? {
+            Some(body) => Ok(Some(self.codec.decode(&body)?)),
+            None => Ok(None),
         }
+    }
 
-        Ok(())
+    fn value(&self, key: &[u8], op: LogOperation) -> Result<Option<Vec<u8>>> {
+        Ok(value_of(key, op, expiry::now())?.map(|(value, _)| value))
     }
 
-    pub fn save(&self) -> Result<()> {
-        self.fd.sync_all()?;
-        Ok(())
+    /// Iterate over the key value pairs with a key in `range`, in key order.
+    /// Values are read from the log as the iterator advances, and writes made
+    /// meanwhile may or may not be seen.
+    ///
+    /// ```no_run
+    /// # fn main() -> kvs::Result<()> {
+    /// let store = kvs::KvStore::open("data")?;
+    /// for pair in store.scan("a".to_owned().."c".to_owned()) {
+    ///     let (key, value) = pair?;
+    ///     println!("{} {}", key, value);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn scan<R: RangeBounds<String>>(&self, range: R) -> Scan {
+        Scan::new(self.scan_bytes((
+            range.start_bound().map(|key| key.as_bytes().to_vec()),
+            range.end_bound().map(|key| key.as_bytes().to_vec()),
+        )))
     }
 
-    pub fn get(&self, key: String) -> Result<Option<String>> {
-        if let Some(val) = self.index.get(&key) {
-            let entry = self.read_log_entry(val.0 + 1024)?;
-            match entry.1 {
-                LogOperation::Set(_, val) => Ok(Some(val)),
-                LogOperation::Rm(_) => Ok(None),
+    /// Iterate over the key value pairs whose key starts with `prefix`, in
+    /// key order, see `scan`.
+    pub fn scan_prefix(&self, prefix: &str) -> Scan {
+        Scan::new(self.scan_prefix_bytes(prefix.as_bytes()))
+    }
+
+    /// Iterate over the raw key value pairs with a key in `range`, in the
+    /// byte order of the keys, see `scan`. For UTF-8 keys this is the same
+    /// order as the string order.
+    pub fn scan_bytes<R: RangeBounds<Vec<u8>>>(&self, range: R) -> ScanBytes {
+        ScanBytes::new(
+            self.clone(),
+            range.start_bound().cloned(),
+            range.end_bound().cloned(),
+            None,
+        )
+    }
+
+    /// Iterate over the raw key value pairs whose key starts with `prefix`.
+    pub fn scan_prefix_bytes(&self, prefix: &[u8]) -> ScanBytes {
+        ScanBytes::new(
+            self.clone(),
+            Bound::Included(prefix.to_vec()),
+            Bound::Unbounded,
+            Some(prefix.to_vec()),
+        )
+    }
+
+    /// Read the value of `key` that `ptr` points to. Returns None if the key
+    /// was removed since `ptr` was looked up.
+    fn read_value(&self, key: &[u8], mut ptr: LogPointer) -> Result<Option<Vec<u8>>> {
+        loop {
+            // None if a compaction removed the segment after the lookup, the
+            // index already points to the new copy then
+            if let Some(body) = self.reader.read_body(&ptr)? {
+                let LogEntry(_, op) = self.codec.decode(&body)?;
+                return Ok(value_of(key, op, expiry::now())?.map(|(value, _)| value));
             }
-        } else {
-            Ok(None)
+            ptr = match self.index.get(key) {
+                Some(ptr) => ptr,
+                None => return Ok(None),
+            };
         }
     }
 
-    pub fn set(&mut self, key: String, val: String) -> Result<()> {
-        let entry = LogEntry(self.total_log, LogOperation::Set(key.clone(), val));
-        let entry_bytes = serde_cbor::to_vec(&entry)?;
+    pub fn set(&self, key: String, val: String) -> Result<()> {
+        self.set_bytes(key.into_bytes(), val.into_bytes())
+    }
 
-        let log_ptr = self.insert_log(entry_bytes)?;
-        self.index.insert(key, log_ptr);
+    /// Set a key to a value, both arbitrary bytes.
+    ///
+    /// ```no_run
+    /// # fn main() -> kvs::Result<()> {
+    /// let store = kvs::KvStore::open("data")?;
+    /// store.set_bytes(vec![0, 159, 146, 150], vec![0xff; 16])?;
+    /// assert_eq!(store.get_bytes(&[0, 159, 146, 150])?, Some(vec![0xff; 16]));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_bytes(&self, key: Vec<u8>, val: Vec<u8>) -> Result<()> {
+        self.latencies.writes.time(|| {
+            let appended = self.writer.lock().unwrap().set(key, val)?;
+            self.commit(appended)
+        })
+    }
 
-        self.compact()?;
-        Ok(())
+    /// Set a key that is gone once `ttl` has passed, as if it was removed.
+    /// The expiration is wall-clock time, it also applies to a store that is
+    /// closed and opened again.
+    pub fn set_with_ttl(&self, key: String, val: String, ttl: Duration) -> Result<()> {
+        self.set_bytes_with_ttl(key.into_bytes(), val.into_bytes(), ttl)
     }
 
-    pub fn remove(&mut self, key: String) -> Result<()> {
-        let entry = LogEntry(self.total_log, LogOperation::Rm(key.clone()));
-        let entry_bytes = serde_cbor::to_vec(&entry)?;
+    pub fn set_bytes_with_ttl(&self, key: Vec<u8>, val: Vec<u8>, ttl: Duration) -> Result<()> {
+        let expires_at = expiry::expires_at(ttl);
+        self.latencies.writes.time(|| {
+            let appended = self
+                .writer
+                .lock()
+                .unwrap()
+                .set_expiring(key, val, expires_at)?;
+            self.commit(appended)
+        })
+    }
 
-        self.insert_log(entry_bytes)?;
-        match self.index.remove(&key) {
-            Some(_) => Ok(()),
-            None => Err(KvErr::KeyNotExistError),
-        }
+    pub fn remove(&self, key: String) -> Result<()> {
+        self.remove_bytes(key.into_bytes())
     }
 
-    pub fn compact(&mut self) -> Result<()> {
-        let mut data = Vec::new();
-        self.fd.read_to_end(&mut data)?;
+    pub fn remove_bytes(&self, key: Vec<u8>) -> Result<()> {
+        self.latencies.writes.time(|| {
+            let appended = self.writer.lock().unwrap().remove(key)?;
+            self.commit(appended)
+        })
+    }
 
-        let mut new_index = HashMap::<String, LogPointer>::new();
-        let mut new_total_log = 0usize;
-        let mut new_total_byte = 0usize;
-        let mut logs = Vec::<u8>::new();
-        for (key, val) in self.index.iter() {
-            let LogPointer(old_offset, old_size) = val;
-            new_total_log += 1;
-            let mut buf = vec![0u8; *old_size];
+    /// Apply every write of `batch` at once, see `WriteBatch`. Fails without
+    /// writing anything if the batch removes a key that does not exist.
+    pub fn write(&self, batch: WriteBatch) -> Result<()> {
+        self.latencies.writes.time(|| {
+            let appended = self.writer.lock().unwrap().write(batch)?;
+            self.commit(appended)
+        })
+    }
+
+    /// Set `key` to `new` only if its value is `expected`, and return whether
+    /// it was set. No other write can come between the check and the write.
+    ///
+    /// ```no_run
+    /// # fn main() -> kvs::Result<()> {
+    /// let store = kvs::KvStore::open("data")?;
+    /// store.set_if_absent("counter".to_owned(), "0".to_owned())?;
+    /// loop {
+    ///     let current = store.get("counter".to_owned())?.unwrap();
+    ///     let next = (current.parse::<u64>().unwrap() + 1).to_string();
+    ///     if store.compare_and_swap("counter".to_owned(), current, next)? {
+    ///         break;
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn compare_and_swap(&self, key: String, expected: String, new: String) -> Result<bool> {
+        self.compare_and_swap_bytes(key.into_bytes(), expected.as_bytes(), new.into_bytes())
+    }
+
+    pub fn compare_and_swap_bytes(
+        &self,
+        key: Vec<u8>,
+        expected: &[u8],
+        new: Vec<u8>,
+    ) -> Result<bool> {
+        self.write_if(key, Some(expected), Some(new))
+    }
+
+    /// Set `key` only if it does not exist, and return whether it was set.
+    pub fn set_if_absent(&self, key: String, val: String) -> Result<bool> {
+        self.set_bytes_if_absent(key.into_bytes(), val.into_bytes())
+    }
+
+    pub fn set_bytes_if_absent(&self, key: Vec<u8>, val: Vec<u8>) -> Result<bool> {
+        self.write_if(key, None, Some(val))
+    }
+
+    /// Remove `key` only if its value is `expected`, and return whether it
+    /// was removed. A key that does not exist is not an error here.
+    pub fn remove_if_equals(&self, key: String, expected: String) -> Result<bool> {
+        self.remove_bytes_if_equals(key.into_bytes(), expected.as_bytes())
+    }
 
-            self.fd.read_exact_at(&mut buf, *old_offset as u64 + 1024)?;
-            new_index.insert(key.to_owned(), LogPointer(new_total_byte, *old_size));
-            logs.append(&mut buf);
-            new_total_byte += old_size;
+    pub fn remove_bytes_if_equals(&self, key: Vec<u8>, expected: &[u8]) -> Result<bool> {
+        self.write_if(key, Some(expected), None)
+    }
+
+    /// Set `key` to `new`, or remove it if `new` is None, if its current
+    /// value is `expected`. The value is read while holding the writer lock,
+    /// only the writer changes the index so it cannot change before the write.
+    fn write_if(
+        &self,
+        key: Vec<u8>,
+        expected: Option<&[u8]>,
+        new: Option<Vec<u8>>,
+    ) -> Result<bool> {
+        self.latencies.writes.time(|| {
+            let mut writer = self.writer.lock().unwrap();
+            let current = match self.index.get(&key) {
+                Some(ptr) => self.read_value(&key, ptr)?,
+                None => None,
+            };
+            if current.as_deref() != expected {
+                return Ok(false);
+            }
+            let appended = match new {
+                Some(val) => writer.set(key, val)?,
+                None => writer.remove(key)?,
+            };
+            drop(writer);
+            self.commit(appended)?;
+            Ok(true)
+        })
+    }
+
+    /// with group commit, wait until the first `appended` records are on
+    /// disk, the writer lock is released so other writes can join the fsync
+    fn commit(&self, appended: usize) -> Result<()> {
+        match &self.commit {
+            Some(commit) => commit.wait(appended),
+            None => Ok(()),
         }
-        self.index = new_index;
-        self.total_log = new_total_log;
-        self.total_bytes = new_total_byte;
+    }
+
+    /// Compact all the segments right away and wait until it is done.
+    pub fn compact(&self) -> Result<()> {
+        self.writer.lock().unwrap().compact()
+    }
+
+    /// The size of the store, its compaction debt and the latencies of the
+    /// reads and writes made through any clone since it was opened.
+    pub fn stats(&self) -> Result<Stats> {
+        let mut stats = self.writer.lock().unwrap().stats()?;
+        stats.reads = self.latencies.reads.load();
+        stats.writes = self.latencies.writes.load();
+        Ok(stats)
+    }
 
-        self.save_metadata()?;
-        self.fd.write_at(&logs, 1024)?;
-        self.fd.set_len(1024 + logs.len() as u64)?;
-        Ok(())
+    /// How much the records are compressed, see `CompressionStats`. Every
+    /// record is read, writes only wait while the segments are opened.
+    pub fn compression_stats(&self) -> Result<CompressionStats> {
+        let (compression, segments) = {
+            let writer = self.writer.lock().unwrap();
+            (writer.compression(), writer.open_segments()?)
+        };
+        CompressionStats::collect(compression, &segments)
     }
 
-    fn insert_log(&mut self, log_entry: Vec<u8>) -> Result<LogPointer> {
-        self.total_log += 1;
-        // use 2 bytes to store the entry size using u16
-        // if entry size is bigger than 65535, then we will panic
-        assert!(
-            log_entry.len() < 65535,
-            "do not support entry size bigger than 64Kb"
-        );
-        let offset = self.total_bytes + 1024;
-        let mut buf = vec![0; 0];
-        buf.write_u16::<LittleEndian>(log_entry.len() as u16)?;
-        self.fd.write_at(&buf, offset as u64)?;
-        let written_bytes = self.fd.write_at(&log_entry, offset as u64 + 2)? + 2;
-        self.total_bytes += written_bytes;
-        self.save_metadata()?;
-        Ok(LogPointer(offset - 1024, written_bytes))
+    /// A read-only view of the store as it is now, see `Snapshot`. Writes
+    /// wait while the index is copied, but not while the snapshot is in use.
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        self.writer.lock().unwrap().snapshot()
     }
 
-    fn read_log_pointer(&self, log_ptr: &LogPointer) -> Result<Log> {
-        let LogPointer(offset, size) = log_ptr;
-        let entry = self.read_log_entry_body(offset + 2, *size)?;
-        Ok(Log(*size, entry))
+    /// Write a compacted copy of the store as it is now to the directory
+    /// `path`, which must not hold a store already. Writes go on while the
+    /// copy is made and are not part of it.
+    ///
+    /// ```no_run
+    /// # fn main() -> kvs::Result<()> {
+    /// let store = kvs::KvStore::open("data")?;
+    /// store.backup_to("backup")?;
+    /// let backup = kvs::KvStore::open("backup")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn backup_to(&self, path: impl Into<PathBuf>) -> Result<()> {
+        self.snapshot()?.backup_to(path)
     }
 
-    fn read_log(&self, offset: usize) -> Result<Log> {
-        let size = self.read_log_entry_head(offset)? as usize;
-        let entry = self.read_log_entry_body(offset + 2, size)?;
-        Ok(Log(size, entry))
+    /// the codec used by the records of this store
+    pub fn codec(&self) -> Codec {
+        self.codec
     }
+}
 
-    fn read_log_entry(&self, offset: usize) -> Result<LogEntry> {
-        let size = self.read_log_entry_head(offset)? as usize;
-        Ok(self.read_log_entry_body(offset + 2, size)?)
+/// The value `op` gives to `key` and when it expires. None if `op` removes
+/// the key or the value expired by `now`.
+fn value_of(key: &[u8], op: LogOperation, now: u64) -> Result<Option<(Vec<u8>, Option<u64>)>> {
+    match op {
+        LogOperation::Set(_, val) => Ok(Some((val, None))),
+        LogOperation::SetExpiring(_, _, at) if at <= now => Ok(None),
+        LogOperation::SetExpiring(_, val, at) => Ok(Some((val, Some(at)))),
+        LogOperation::Rm(_) => Ok(None),
+        LogOperation::Batch(ops) => {
+            let last = ops.into_iter().rev().find(|op| op.key() == Some(key));
+            match last {
+                Some(op) => value_of(key, op, now),
+                None => Err(KvErr::CorruptedError(format!(
+                    "the batch the index points to does not hold key {}",
+                    String::from_utf8_lossy(key)
+                ))),
+            }
+        }
     }
+}
 
-    fn read_log_entry_head(&self, offset: usize) -> Result<u16> {
-        let mut buf = [0u8; 2];
-        self.fd.read_exact_at(&mut buf, offset as u64)?;
-        let mut cursor = std::io::Cursor::new(&buf);
-        Ok(cursor.read_u16::<LittleEndian>()?)
+/// Copy the records of a legacy single file database into a new segment.
+/// The records keep their CBOR encoding. The legacy file is removed once the
+/// manifest has been saved.
+fn migrate_legacy(dir: &Path, legacy: &Path) -> Result<Manifest> {
+    let fd = std::fs::File::open(legacy)?;
+    let mut manifest = Manifest::default();
+
+    // the file is removed once migrated, it had better be a database
+    if !is_legacy_db(legacy)? {
+        return Err(KvErr::WrongEngineError(format!(
+            "{} is not a kvs database",
+            legacy.display()
+        )));
     }
+    let metadata = legacy_metadata(&fd)?;
+
+    let mut segment = Segment::create(dir, manifest.allocate_segment_id())?;
+    manifest.segments.push(segment.id);
+    let mut offset = LEGACY_HEADER_SIZE;
+    for _ in 0..metadata.total_log {
+        let mut head = [0u8; U16_FRAME_HEADER_SIZE];
+        fd.read_exact_at(&mut head, offset)?;
+        let size = std::io::Cursor::new(&head).read_u16::<LittleEndian>()? as usize;
+        let mut body = vec![0u8; size];
+        fd.read_exact_at(&mut body, offset + U16_FRAME_HEADER_SIZE as u64)?;
+
+        if segment.is_full() {
+            segment.sync()?;
+            segment = Segment::create(dir, manifest.allocate_segment_id())?;
+            manifest.segments.push(segment.id);
+        }
+        segment.append(&body, Compression::None)?;
+        offset += (U16_FRAME_HEADER_SIZE + size) as u64;
+    }
+    segment.sync()?;
 
-    fn read_log_entry_body(&self, offset: usize, size: usize) -> Result<LogEntry> {
-        let mut data = vec![0u8; size];
-        self.fd.read_exact_at(&mut data, offset as u64)?;
-        Ok(serde_cbor::from_reader(&data[..])?)
+    manifest.save(dir)?;
+    std::fs::remove_file(legacy)?;
+    Ok(manifest)
+}
+
+/// true if `path` is a legacy database: a file named `LEGACY_DB_FILE` that
+/// is empty or starts with a readable metadata header
+fn is_legacy_db(path: &Path) -> Result<bool> {
+    if path.file_name() != Some(LEGACY_DB_FILE.as_ref()) || !path.is_file() {
+        return Ok(false);
     }
+    let fd = std::fs::File::open(path)?;
+    let len = fd.metadata()?.len();
+    Ok((len == 0 || len >= LEGACY_HEADER_SIZE) && legacy_metadata(&fd).is_ok())
 }
 
-impl Drop for KvStore {
-    fn drop(&mut self) {
-        // println!("Now running the compaction");
-        self.compact().expect("compact completed without error");
+/// Read the metadata header of a legacy database, a file too short to hold
+/// one is empty.
+fn legacy_metadata(fd: &std::fs::File) -> Result<Metadata> {
+    if fd.metadata()?.len() < LEGACY_HEADER_SIZE {
+        return Ok(Metadata::default());
+    }
+    let mut buf = vec![0u8; LEGACY_HEADER_SIZE as usize];
+    fd.read_exact_at(&mut buf, 0)?;
+    let mut cursor = std::io::Cursor::new(&buf[0..4]);
+    let end = (cursor.read_u32::<LittleEndian>()? + 4) as usize;
+    if end > buf.len() {
+        return Err(KvErr::CorruptedError(
+            "the metadata header is larger than its space".to_owned(),
+        ));
     }
+    Ok(serde_cbor::from_slice(&buf[4..end])?)
 }
@@ -0,0 +1,88 @@
+//! metrics serves the stats of a store over HTTP, for Prometheus to scrape.
+//!
+use crate::Result;
+use log::{debug, error};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// the path the metrics are served on, any other path is not found
+pub const METRICS_PATH: &str = "/metrics";
+/// how long a connection may wait for the request or the reply
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A minimal HTTP server that answers `GET /metrics` with the text rendered
+/// for every request. Every connection is served on a thread of its own, so
+/// an idle client cannot hold up a scrape, and is dropped once it stays idle
+/// for `IO_TIMEOUT`.
+pub struct MetricsServer {
+    listener: TcpListener,
+}
+
+impl MetricsServer {
+    /// Bind to `addr` now, so an address in use is reported before serving.
+    pub fn bind(addr: impl ToSocketAddrs) -> Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+        })
+    }
+
+    /// Serve every connection with the text `render` returns, in the
+    /// Prometheus text format.
+    pub fn run(self, render: impl Fn() -> Result<String> + Send + Sync + 'static) -> Result<()> {
+        let render = Arc::new(render);
+        for stream in self.listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let render = render.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = serve(render.as_ref(), stream) {
+                            error!("Error serving metrics: {}", e);
+                        }
+                    });
+                }
+                Err(e) => error!("Connection failed: {}", e),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn serve(render: &impl Fn() -> Result<String>, mut stream: TcpStream) -> Result<()> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // the headers are not needed, but are read so the client sees a reply
+    // to a complete request
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    debug!("Metrics request: {}", request_line.trim_end());
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(METRICS_PATH)) => match render() {
+            Ok(body) => ("200 OK", body),
+            Err(e) => ("500 Internal Server Error", format!("{}\n", e)),
+        },
+        (Some("GET"), _) => ("404 Not Found", "not found\n".to_owned()),
+        _ => (
+            "405 Method Not Allowed",
+            "only GET is supported\n".to_owned(),
+        ),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+    Ok(())
+}
@@ -0,0 +1,297 @@
+//! mvcc keeps the values keys had before they were overwritten, so reads can
+//! be made at an earlier version of the store.
+//!
+//! A version is a count of records: version `v` is the store once the records
+//! with a log id below `v` are applied. The index only holds the latest record
+//! of each key, and records know their own log id, so the latest value is the
+//! one at `v` if its id is below `v`. Otherwise the answer is in the history
+//! of the key, which the writer extends before it points the index to a new
+//! record, so a reader never misses a version.
+//!
+//! The history lives in memory and starts when the store is opened. A
+//! compaction drops what no reader can ask for any more and moves the
+//! horizon, the oldest version that can be read, up to where it started.
+use crate::error::KvErr;
+use crate::txlog::LogPointer;
+use crate::{KvStore, Result};
+use std::collections::BTreeMap;
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+
+/// The versions of a store that can still be read, shared by the store and
+/// its writer. Only the writer changes it.
+#[derive(Debug, Default)]
+pub struct Versions {
+    history: RwLock<History>,
+    /// the version with every record appended so far, published once the
+    /// index points to them
+    current: AtomicUsize,
+    /// versions of the open read transactions, and how many use each
+    readers: Mutex<BTreeMap<usize, usize>>,
+}
+
+#[derive(Debug, Default)]
+struct History {
+    /// the records keys pointed to before they were overwritten or removed,
+    /// in log order
+    keys: BTreeMap<Vec<u8>, Vec<Superseded>>,
+    /// versions below it are only kept for the open read transactions
+    horizon: usize,
+}
+
+/// a record that was the latest of its key until the record with log id `until`
+#[derive(Debug, Clone, Copy)]
+struct Superseded {
+    until: usize,
+    ptr: LogPointer,
+}
+
+impl Versions {
+    /// start the history at `version`, the store as it was opened
+    pub fn open_at(&self, version: usize) {
+        self.history.write().unwrap().horizon = version;
+        self.current.store(version, Ordering::SeqCst);
+    }
+
+    pub fn current(&self) -> usize {
+        self.current.load(Ordering::SeqCst)
+    }
+
+    /// called by the writer after the index points to the records below `version`
+    pub fn publish(&self, version: usize) {
+        self.current.store(version, Ordering::SeqCst);
+    }
+
+    /// `ptr` was the latest record of `key` until the record `until`, called
+    /// before the index points to that record
+    pub fn supersede(&self, key: &[u8], until: usize, ptr: LogPointer) {
+        self.history
+            .write()
+            .unwrap()
+            .keys
+            .entry(key.to_vec())
+            .or_default()
+            .push(Superseded { until, ptr });
+    }
+
+    /// Fail unless the store can be read at `version`.
+    pub fn check(&self, version: usize) -> Result<()> {
+        let horizon = self.history.read().unwrap().horizon;
+        self.check_against(version, horizon)
+    }
+
+    fn check_against(&self, version: usize, horizon: usize) -> Result<()> {
+        if version > self.current() {
+            return Err(KvErr::VersionError(format!(
+                "version {} is not written yet",
+                version
+            )));
+        }
+        if version < horizon && !self.readers.lock().unwrap().contains_key(&version) {
+            return Err(KvErr::VersionError(format!(
+                "version {} was compacted away, the oldest is {}",
+                version, horizon
+            )));
+        }
+        Ok(())
+    }
+
+    /// The record of `key` at `version` if the latest one is newer. It may
+    /// still be too new, the caller checks its log id. None if the key had
+    /// no value at `version`.
+    pub fn superseded(&self, key: &[u8], version: usize) -> Result<Option<LogPointer>> {
+        let history = self.history.read().unwrap();
+        // a compaction may have dropped the version since it was checked
+        self.check_against(version, history.horizon)?;
+        Ok(history.keys.get(key).and_then(|versions| {
+            versions
+                .iter()
+                .find(|superseded| superseded.until >= version)
+                .map(|superseded| superseded.ptr)
+        }))
+    }
+
+    /// the first key with a history within `lower` and `upper`
+    pub fn first_in(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Option<Vec<u8>> {
+        self.history
+            .read()
+            .unwrap()
+            .keys
+            .range::<[u8], _>((lower, upper))
+            .next()
+            .map(|(key, _)| key.clone())
+    }
+
+    /// keep the current version readable until `unpin` is called with it
+    pub fn pin(&self) -> usize {
+        let version = self.current();
+        *self.readers.lock().unwrap().entry(version).or_default() += 1;
+        version
+    }
+
+    pub fn unpin(&self, version: usize) {
+        let mut readers = self.readers.lock().unwrap();
+        if let Some(count) = readers.get_mut(&version) {
+            *count -= 1;
+            if *count == 0 {
+                readers.remove(&version);
+            }
+        }
+    }
+
+    /// the version of the oldest open read transaction
+    pub fn oldest_reader(&self) -> Option<usize> {
+        self.readers.lock().unwrap().keys().next().copied()
+    }
+
+    /// Install a compaction that started at version `horizon` and copied
+    /// `moved` out of the segments `inputs`. Versions still needed below the
+    /// horizon are the ones the open read transactions see, their records
+    /// are left where they are and the caller keeps those segments until
+    /// the transactions end.
+    pub fn compacted(
+        &self,
+        horizon: usize,
+        inputs: &[u64],
+        moved: &[(Vec<u8>, LogPointer, LogPointer)],
+    ) {
+        let mut history = self.history.write().unwrap();
+        if history.keys.is_empty() {
+            history.horizon = horizon;
+            return;
+        }
+        // the records overwritten while the compaction ran were copied
+        for (key, old, new) in moved {
+            if let Some(versions) = history.keys.get_mut(key) {
+                for superseded in versions.iter_mut().filter(|s| s.ptr == *old) {
+                    superseded.ptr = *new;
+                }
+            }
+        }
+        let readers: Vec<usize> = self
+            .readers
+            .lock()
+            .unwrap()
+            .keys()
+            .copied()
+            .filter(|version| *version < horizon)
+            .collect();
+        history.keys.retain(|_, versions| {
+            let mut keep = vec![false; versions.len()];
+            for (i, superseded) in versions.iter().enumerate() {
+                // an expired record is not copied, its value is gone anyway
+                keep[i] = superseded.until >= horizon && !inputs.contains(&superseded.ptr.0);
+            }
+            for reader in readers.iter() {
+                if let Some(i) = versions.iter().position(|s| s.until >= *reader) {
+                    keep[i] = true;
+                }
+            }
+            let mut keep = keep.into_iter();
+            versions.retain(|_| keep.next().unwrap());
+            !versions.is_empty()
+        });
+        history.horizon = horizon;
+    }
+}
+
+/// A consistent read-only view of a store at one version, returned by
+/// `KvStore::read_transaction`. Writes made after it began are not seen, and
+/// the values it sees are kept through compactions until it is dropped.
+///
+/// Unlike a `Snapshot` nothing is copied when it begins, but every value
+/// overwritten while it is open is kept in memory and on disk until it ends,
+/// so long transactions hold back the space compactions reclaim.
+///
+/// ```no_run
+/// # fn main() -> kvs::Result<()> {
+/// let store = kvs::KvStore::open("data")?;
+/// let tx = store.read_transaction();
+/// store.set("key".to_owned(), "new".to_owned())?;
+/// // the value from before the write
+/// println!("{:?}", tx.get("key".to_owned())?);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ReadTransaction {
+    store: KvStore,
+    version: usize,
+}
+
+impl ReadTransaction {
+    pub(crate) fn new(store: KvStore, version: usize) -> Self {
+        Self { store, version }
+    }
+
+    /// the version the transaction reads at, see `KvStore::get_at`
+    pub fn version(&self) -> usize {
+        self.version
+    }
+
+    pub fn get(&self, key: String) -> Result<Option<String>> {
+        self.store.get_at(key, self.version)
+    }
+
+    pub fn get_bytes(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.store.get_bytes_at(key, self.version)
+    }
+
+    /// Iterate over the key value pairs with a key in `range`, in key order.
+    pub fn scan<R: RangeBounds<String>>(
+        &self,
+        range: R,
+    ) -> impl Iterator<Item = Result<(String, String)>> + '_ {
+        let range = (
+            range.start_bound().map(|key| key.as_bytes().to_vec()),
+            range.end_bound().map(|key| key.as_bytes().to_vec()),
+        );
+        self.scan_bytes(range).map(|pair| {
+            let (key, value) = pair?;
+            Ok((String::from_utf8(key)?, String::from_utf8(value)?))
+        })
+    }
+
+    /// Iterate over the raw key value pairs with a key in `range`, in the
+    /// byte order of the keys. The keys are those in the index or with a
+    /// history, each is looked up at the version of the transaction.
+    pub fn scan_bytes<R: RangeBounds<Vec<u8>>>(
+        &self,
+        range: R,
+    ) -> impl Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + '_ {
+        let mut next = range.start_bound().cloned();
+        let end = range.end_bound().cloned();
+        let mut done = false;
+        std::iter::from_fn(move || {
+            while !done {
+                let lower = next.as_ref().map(Vec::as_slice);
+                let upper = end.as_ref().map(Vec::as_slice);
+                let latest = self.store.index.first_in(lower, upper).map(|(key, _)| key);
+                let older = self.store.versions.first_in(lower, upper);
+                let key = match (latest, older) {
+                    (Some(a), Some(b)) => a.min(b),
+                    (Some(key), None) | (None, Some(key)) => key,
+                    (None, None) => break,
+                };
+                next = Bound::Excluded(key.clone());
+                match self.get_bytes(&key) {
+                    Ok(Some(value)) => return Some(Ok((key, value))),
+                    Ok(None) => continue,
+                    Err(e) => {
+                        done = true;
+                        return Some(Err(e));
+                    }
+                }
+            }
+            done = true;
+            None
+        })
+    }
+}
+
+impl Drop for ReadTransaction {
+    fn drop(&mut self) {
+        self.store.versions.unpin(self.version);
+    }
+}
@@ -0,0 +1,84 @@
+//! options defines the knobs that can be set when opening a KvStore.
+//!
+use crate::{Codec, Compression, Durability, KvStore, Result};
+use std::path::PathBuf;
+
+/// compaction starts once this many bytes in the log are stale
+pub const DEFAULT_COMPACTION_THRESHOLD: u64 = 1024 * 1024;
+
+/// Options used to open a KvStore, in the same spirit as `std::fs::OpenOptions`.
+///
+/// ```no_run
+/// # fn main() -> kvs::Result<()> {
+/// let store = kvs::OpenOptions::new()
+///     .compaction_threshold(4 * 1024 * 1024)
+///     .durability(kvs::Durability::Group)
+///     .open("data")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct OpenOptions {
+    pub(crate) compaction_threshold: u64,
+    pub(crate) codec: Codec,
+    pub(crate) durability: Durability,
+    pub(crate) compression: Option<Compression>,
+    pub(crate) create: bool,
+}
+
+impl OpenOptions {
+    pub fn new() -> Self {
+        Self {
+            compaction_threshold: DEFAULT_COMPACTION_THRESHOLD,
+            codec: Codec::default(),
+            durability: Durability::default(),
+            compression: None,
+            create: true,
+        }
+    }
+
+    /// number of stale bytes that triggers a background compaction
+    pub fn compaction_threshold(&mut self, bytes: u64) -> &mut Self {
+        self.compaction_threshold = bytes;
+        self
+    }
+
+    /// codec of the records, only used when a new database is created, an
+    /// existing database keeps the codec recorded in its manifest
+    pub fn codec(&mut self, codec: Codec) -> &mut Self {
+        self.codec = codec;
+        self
+    }
+
+    /// when writes are flushed to disk, `Durability::Buffered` by default
+    pub fn durability(&mut self, durability: Durability) -> &mut Self {
+        self.durability = durability;
+        self
+    }
+
+    /// Compress the records written from now on, recorded in the manifest so
+    /// the store keeps it when opened again. Records already written keep
+    /// their compression until a compaction copies them. By default a store
+    /// keeps the compression it has, a new one is not compressed.
+    pub fn compression(&mut self, compression: Compression) -> &mut Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Create the store when the path holds none, the default. Otherwise
+    /// opening such a path is a `StoreNotExistError` and nothing is written.
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    pub fn open(&self, path: impl Into<PathBuf>) -> Result<KvStore> {
+        KvStore::open_with_options(path, self.clone())
+    }
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
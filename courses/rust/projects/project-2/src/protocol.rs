@@ -0,0 +1,192 @@
+//! protocol defines the messages exchanged between kvs-client and kvs-server.
+//!
+//! Every message, in both directions, is sent as one frame:
+//!
+//! ```text
+//! +----------------+-------------+------------+
+//! | length: u32 LE | version: u8 | body: CBOR |
+//! +----------------+-------------+------------+
+//! ```
+//!
+//! `length` counts the version byte and the body. The body of a request is a
+//! CBOR encoded `Request` and the body of a response a CBOR encoded
+//! `Response`. A client sends a request and reads exactly one response, it
+//! may send several requests over the same connection.
+//!
+//! Version 2 added the conditional writes, `Request::CompareAndSwap`,
+//! `SetIfAbsent` and `RemoveIfEquals` and `Response::Applied`.
+//!
+//! A server that receives a frame of another version answers with an
+//! `ErrorKind::UnsupportedVersion` error and closes the connection. The error
+//! is framed in the version of the client if it is an older one, `Response::Err`
+//! is encoded the same in every version so the client can read it, and in the
+//! version of the server otherwise.
+use crate::error::KvErr;
+use crate::Result;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+pub const PROTOCOL_VERSION: u8 = 2;
+/// frames larger than this are rejected before allocating a buffer for them
+pub const MAX_FRAME_SIZE: u32 = 64 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Get {
+        key: String,
+    },
+    Set {
+        key: String,
+        value: String,
+    },
+    Remove {
+        key: String,
+    },
+    CompareAndSwap {
+        key: String,
+        expected: String,
+        new: String,
+    },
+    SetIfAbsent {
+        key: String,
+        value: String,
+    },
+    RemoveIfEquals {
+        key: String,
+        expected: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    /// the request succeeded, with the value for a `Get`
+    Ok(Option<String>),
+    /// whether a conditional write was applied
+    Applied(bool),
+    Err(ErrorKind, String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    KeyNotFound,
+    UnsupportedVersion,
+    /// the request could not be decoded
+    BadRequest,
+    /// the engine failed to serve the request
+    Internal,
+}
+
+/// Write one frame holding `msg`.
+pub fn write_message<T: Serialize>(writer: &mut impl Write, msg: &T) -> Result<()> {
+    write_message_as(writer, PROTOCOL_VERSION, msg)
+}
+
+/// Write one frame holding `msg`, marked with `version`.
+pub fn write_message_as<T: Serialize>(writer: &mut impl Write, version: u8, msg: &T) -> Result<()> {
+    writer.write_all(&encode_frame(version, msg)?)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write one frame holding `msg` to an asynchronous writer.
+pub async fn write_message_async<T: Serialize>(
+    writer: &mut (impl AsyncWrite + Unpin),
+    msg: &T,
+) -> Result<()> {
+    write_message_as_async(writer, PROTOCOL_VERSION, msg).await
+}
+
+/// Write one frame holding `msg`, marked with `version`, to an asynchronous
+/// writer.
+pub async fn write_message_as_async<T: Serialize>(
+    writer: &mut (impl AsyncWrite + Unpin),
+    version: u8,
+    msg: &T,
+) -> Result<()> {
+    use tokio::io::AsyncWriteExt;
+    writer.write_all(&encode_frame(version, msg)?).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+fn encode_frame<T: Serialize>(version: u8, msg: &T) -> Result<Vec<u8>> {
+    let body = serde_cbor::to_vec(msg)?;
+    let mut buf = Vec::with_capacity(body.len() + 5);
+    buf.write_u32::<LittleEndian>(body.len() as u32 + 1)?;
+    buf.write_u8(version)?;
+    buf.extend_from_slice(&body);
+    Ok(buf)
+}
+
+/// Read one frame, returns its version and body. None if the peer closed the
+/// connection before the frame started.
+pub fn read_frame(reader: &mut impl Read) -> Result<Option<(u8, Vec<u8>)>> {
+    let len = match reader.read_u32::<LittleEndian>() {
+        Ok(len) => len,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    check_frame_len(len)?;
+    let version = reader.read_u8()?;
+    let mut body = vec![0u8; len as usize - 1];
+    reader.read_exact(&mut body)?;
+    Ok(Some((version, body)))
+}
+
+/// Read one frame from an asynchronous reader, see `read_frame`.
+pub async fn read_frame_async(
+    reader: &mut (impl AsyncRead + Unpin),
+) -> Result<Option<(u8, Vec<u8>)>> {
+    use tokio::io::AsyncReadExt;
+    let len = match reader.read_u32_le().await {
+        Ok(len) => len,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    check_frame_len(len)?;
+    let version = reader.read_u8().await?;
+    let mut body = vec![0u8; len as usize - 1];
+    reader.read_exact(&mut body).await?;
+    Ok(Some((version, body)))
+}
+
+fn check_frame_len(len: u32) -> Result<()> {
+    if len == 0 || len > MAX_FRAME_SIZE {
+        return Err(KvErr::ProtocolError(format!(
+            "invalid frame length {}",
+            len
+        )));
+    }
+    Ok(())
+}
+
+/// Read one frame of the current version and decode its body. A frame of an
+/// older version is decoded as well, it is how an older server answers that it
+/// does not speak this one.
+pub fn read_message<T: DeserializeOwned>(reader: &mut impl Read) -> Result<Option<T>> {
+    decode_frame(read_frame(reader)?)
+}
+
+/// Read one frame of the current version from an asynchronous reader and
+/// decode its body.
+pub async fn read_message_async<T: DeserializeOwned>(
+    reader: &mut (impl AsyncRead + Unpin),
+) -> Result<Option<T>> {
+    decode_frame(read_frame_async(reader).await?)
+}
+
+fn decode_frame<T: DeserializeOwned>(frame: Option<(u8, Vec<u8>)>) -> Result<Option<T>> {
+    match frame {
+        Some((version, body)) if (1..=PROTOCOL_VERSION).contains(&version) => {
+            Ok(Some(serde_cbor::from_slice(&body)?))
+        }
+        Some((version, _)) => Err(KvErr::ProtocolError(format!(
+            "unsupported protocol version {}",
+            version
+        ))),
+        None => Ok(None),
+    }
+}
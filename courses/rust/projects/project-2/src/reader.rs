@@ -0,0 +1,124 @@
+//! reader reads records without taking a lock.
+//!
+//! Each thread reading at a given time uses its own file handles, so reads
+//! never wait for each other. The writer publishes which segments are live, a
+//! reader only opens those and drops its handles to the segments removed by a
+//! compaction.
+use crate::error::KvErr;
+use crate::segment::Segment;
+use crate::txlog::LogPointer;
+use crate::Result;
+use crossbeam_queue::SegQueue;
+use crossbeam_skiplist::SkipSet;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// The segments a reader may open, shared between the writer and the readers.
+#[derive(Debug, Default)]
+pub struct LiveSegments {
+    ids: SkipSet<u64>,
+    /// bumped every time segments are removed
+    generation: AtomicU64,
+}
+
+impl LiveSegments {
+    /// publish a segment, its header must already be written
+    pub fn insert(&self, id: u64) {
+        self.ids.insert(id);
+    }
+
+    /// retire segments before their files are removed
+    pub fn remove(&self, ids: &[u64]) {
+        for id in ids {
+            self.ids.remove(id);
+        }
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn contains(&self, id: u64) -> bool {
+        self.ids.contains(&id)
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+}
+
+/// Reads records through file handles that are shared by the clones of a
+/// store but never by two threads at once: a read takes a set of idle handles
+/// from a lock-free queue and puts it back when done.
+#[derive(Debug, Clone)]
+pub struct KvStoreReader {
+    dir: Arc<PathBuf>,
+    live: Arc<LiveSegments>,
+    idle: Arc<SegQueue<Handles>>,
+}
+
+impl KvStoreReader {
+    pub fn new(dir: Arc<PathBuf>, live: Arc<LiveSegments>) -> Self {
+        Self {
+            dir,
+            live,
+            idle: Arc::new(SegQueue::new()),
+        }
+    }
+
+    /// Read the record body `ptr` points to. Returns None if the segment was
+    /// compacted away, the caller should look the key up again since the
+    /// index is always updated before a segment is removed.
+    pub fn read_body(&self, ptr: &LogPointer) -> Result<Option<Vec<u8>>> {
+        let mut handles = self.idle.pop().unwrap_or_default();
+        let body = handles.read_body(&self.dir, &self.live, ptr);
+        self.idle.push(handles);
+        body
+    }
+}
+
+/// open segments, used by one read at a time
+#[derive(Debug, Default)]
+struct Handles {
+    /// the generation of the live segments the handles were last checked against
+    generation: u64,
+    segments: HashMap<u64, Segment>,
+}
+
+impl Handles {
+    fn read_body(
+        &mut self,
+        dir: &Path,
+        live: &LiveSegments,
+        ptr: &LogPointer,
+    ) -> Result<Option<Vec<u8>>> {
+        self.close_removed(live);
+        let LogPointer(id, offset, size) = *ptr;
+        let segment = match self.segments.entry(id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                if !live.contains(id) {
+                    return Ok(None);
+                }
+                match Segment::open(dir, id) {
+                    Ok(segment) => entry.insert(segment),
+                    // removed between the check above and the open
+                    Err(KvErr::IOError(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                        return Ok(None)
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        };
+        segment.read_body(offset, size).map(Some)
+    }
+
+    /// drop the handles of segments that are no longer live
+    fn close_removed(&mut self, live: &LiveSegments) {
+        let generation = live.generation();
+        if self.generation != generation {
+            self.segments.retain(|id, _| live.contains(*id));
+            self.generation = generation;
+        }
+    }
+}
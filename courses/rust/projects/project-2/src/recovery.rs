@@ -0,0 +1,35 @@
+//! recovery describes how the log was replayed on open, and what was discarded.
+//!
+
+/// A damaged range at the end of the active segment that was cut off on open.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Truncation {
+    pub segment: u64,
+    /// offset of the first bad record, the segment now ends here
+    pub offset: u64,
+    pub discarded_bytes: u64,
+    pub reason: String,
+}
+
+/// Replay stops at the first bad record of the active segment, a torn write
+/// or a record whose checksum does not match, and truncates the rest of it.
+/// An active segment without its header is recreated, truncated at offset 0.
+/// A bad record in a sealed segment fails the open instead.
+#[derive(Debug, Clone, Default)]
+pub struct RecoveryReport {
+    pub truncated: Vec<Truncation>,
+    /// true if the index was loaded from the hint file, and only the segments
+    /// written after it were replayed
+    pub used_hint: bool,
+}
+
+impl RecoveryReport {
+    /// true if the log was replayed without discarding anything
+    pub fn is_clean(&self) -> bool {
+        self.truncated.is_empty()
+    }
+
+    pub fn discarded_bytes(&self) -> u64 {
+        self.truncated.iter().map(|t| t.discarded_bytes).sum()
+    }
+}
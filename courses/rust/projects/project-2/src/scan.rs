@@ -0,0 +1,95 @@
+//! scan iterates over a range of keys of a KvStore in key order.
+//!
+use crate::{KvStore, Result};
+use std::ops::Bound;
+
+/// An iterator over raw key value pairs, returned by `KvStore::scan_bytes`
+/// and `KvStore::scan_prefix_bytes`.
+///
+/// The iterator does not hold on to the index between two calls to `next`,
+/// every step looks up the first key after the last one returned. It keeps
+/// working while other threads write, compact, or drop the store it came
+/// from.
+pub struct ScanBytes {
+    store: KvStore,
+    /// lower bound of the keys still to be returned
+    next: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    prefix: Option<Vec<u8>>,
+    done: bool,
+}
+
+impl ScanBytes {
+    pub(crate) fn new(
+        store: KvStore,
+        start: Bound<Vec<u8>>,
+        end: Bound<Vec<u8>>,
+        prefix: Option<Vec<u8>>,
+    ) -> Self {
+        Self {
+            store,
+            next: start,
+            end,
+            prefix,
+            done: false,
+        }
+    }
+}
+
+impl Iterator for ScanBytes {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let next = self.next.as_ref().map(Vec::as_slice);
+            let end = self.end.as_ref().map(Vec::as_slice);
+            let (key, ptr) = match self.store.index.first_in(next, end) {
+                Some(entry) => entry,
+                None => break,
+            };
+            if let Some(prefix) = &self.prefix {
+                if !key.starts_with(prefix) {
+                    break;
+                }
+            }
+            self.next = Bound::Excluded(key.clone());
+            match self.store.read_value(&key, ptr) {
+                Ok(Some(value)) => return Some(Ok((key, value))),
+                // removed after the lookup
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        self.done = true;
+        None
+    }
+}
+
+/// An iterator over key value pairs, returned by `KvStore::scan` and
+/// `KvStore::scan_prefix`, see `ScanBytes`. A pair that is not valid UTF-8
+/// is returned as a `Utf8Error`.
+pub struct Scan {
+    inner: ScanBytes,
+}
+
+impl Scan {
+    pub(crate) fn new(inner: ScanBytes) -> Self {
+        Self { inner }
+    }
+}
+
+impl Iterator for Scan {
+    type Item = Result<(String, String)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = match self.inner.next()? {
+            Ok(pair) => pair,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(
+            String::from_utf8(key)
+                .and_then(|key| Ok((key, String::from_utf8(value)?)))
+                .map_err(Into::into),
+        )
+    }
+}
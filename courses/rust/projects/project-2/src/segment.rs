@@ -0,0 +1,414 @@
+//! segment defines the on-disk layout of the log: a directory of numbered,
+//! size-bounded segment files plus a manifest listing them in replay order.
+//!
+use crate::codec::Codec;
+use crate::compression::Compression;
+use crate::error::KvErr;
+use crate::Result;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+/// every segment file starts with this magic followed by a u32 format version
+pub const SEGMENT_MAGIC: [u8; 4] = *b"KVSL";
+pub const SEGMENT_HEADER_SIZE: u64 = 8;
+/// version 1 framed records with a u16 length, version 2 with a u32 length,
+/// version 3 adds a crc32 of the entry after the length, version 4 adds the
+/// compression of the entry after the crc32
+pub const SEGMENT_VERSION: u32 = 4;
+const SEGMENT_VERSION_U16_FRAMES: u32 = 1;
+const SEGMENT_VERSION_U32_FRAMES: u32 = 2;
+const SEGMENT_VERSION_CRC_FRAMES: u32 = 3;
+/// a new segment is started once the active one grows past this size
+pub const SEGMENT_SIZE_LIMIT: u64 = 1024 * 1024;
+pub const SEGMENT_EXT: &str = "log";
+
+pub const MANIFEST_FILE: &str = "MANIFEST";
+const MANIFEST_TMP_FILE: &str = "MANIFEST.tmp";
+
+/// size of the frame header in front of every log entry
+pub const FRAME_HEADER_SIZE: usize = 9;
+/// size of the frame header used by version 1 segments and the legacy database
+pub const U16_FRAME_HEADER_SIZE: usize = 2;
+/// size of the frame header used by version 2 segments
+const U32_FRAME_HEADER_SIZE: usize = 4;
+/// size of the frame header used by version 3 segments
+const CRC_FRAME_HEADER_SIZE: usize = 8;
+/// the largest entry a frame can hold
+pub const MAX_ENTRY_SIZE: usize = u32::MAX as usize;
+
+pub fn segment_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{}.{}", id, SEGMENT_EXT))
+}
+
+/// returns the segment id if `path` looks like a segment file
+pub fn segment_id(path: &Path) -> Option<u64> {
+    if path.extension()? != SEGMENT_EXT {
+        return None;
+    }
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+/// A single segment file. Records are only ever appended to the end of the
+/// file, the header is written once when the segment is created.
+#[derive(Debug)]
+pub struct Segment {
+    pub id: u64,
+    version: u32,
+    fd: File,
+    len: u64,
+}
+
+impl Segment {
+    pub fn create(dir: &Path, id: u64) -> Result<Segment> {
+        let fd = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(segment_path(dir, id))?;
+
+        let mut header = Vec::with_capacity(SEGMENT_HEADER_SIZE as usize);
+        header.write_all(&SEGMENT_MAGIC)?;
+        header.write_u32::<LittleEndian>(SEGMENT_VERSION)?;
+        fd.write_all_at(&header, 0)?;
+        // the segment may be made live by a manifest saved right after, its
+        // header and directory entry have to be on disk before
+        fd.sync_all()?;
+        File::open(dir)?.sync_all()?;
+
+        Ok(Segment {
+            id,
+            version: SEGMENT_VERSION,
+            fd,
+            len: SEGMENT_HEADER_SIZE,
+        })
+    }
+
+    pub fn open(dir: &Path, id: u64) -> Result<Segment> {
+        let fd = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(segment_path(dir, id))?;
+        let len = fd.metadata()?.len();
+
+        let mut header = [0u8; SEGMENT_HEADER_SIZE as usize];
+        if len < SEGMENT_HEADER_SIZE || fd.read_exact_at(&mut header, 0).is_err() {
+            return Err(KvErr::CorruptedError(format!(
+                "segment {} has no header",
+                id
+            )));
+        }
+        let mut cursor = std::io::Cursor::new(&header[4..]);
+        let version = cursor.read_u32::<LittleEndian>()?;
+        if header[0..4] != SEGMENT_MAGIC
+            || !(SEGMENT_VERSION_U16_FRAMES..=SEGMENT_VERSION).contains(&version)
+        {
+            return Err(KvErr::CorruptedError(format!(
+                "segment {} has an unknown header",
+                id
+            )));
+        }
+
+        Ok(Segment {
+            id,
+            version,
+            fd,
+            len,
+        })
+    }
+
+    /// true if the segment file is shorter than a header or holds only
+    /// zeros, a segment whose creation did not reach the disk
+    pub fn has_torn_header(dir: &Path, id: u64) -> Result<bool> {
+        let data = fs::read(segment_path(dir, id))?;
+        Ok(data.len() < SEGMENT_HEADER_SIZE as usize || data.iter().all(|b| *b == 0))
+    }
+
+    fn frame_header_size(&self) -> usize {
+        match self.version {
+            SEGMENT_VERSION_U16_FRAMES => U16_FRAME_HEADER_SIZE,
+            SEGMENT_VERSION_U32_FRAMES => U32_FRAME_HEADER_SIZE,
+            SEGMENT_VERSION_CRC_FRAMES => CRC_FRAME_HEADER_SIZE,
+            _ => FRAME_HEADER_SIZE,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// whether new records can be appended with the current framing
+    pub fn is_current_version(&self) -> bool {
+        self.version == SEGMENT_VERSION
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len >= SEGMENT_SIZE_LIMIT
+    }
+
+    /// Append one framed record compressed with `compression`, unless it
+    /// does not get smaller. Returns (frame offset, frame size).
+    pub fn append(&mut self, body: &[u8], compression: Compression) -> Result<(u64, usize)> {
+        // new records are always written to a segment of the current version,
+        // which stores the entry size using u32 followed by its checksum and
+        // its compression
+        if body.len() > MAX_ENTRY_SIZE {
+            return Err(KvErr::EntryTooLargeError(body.len()));
+        }
+        let (compression, body) = compression.compress(body)?;
+        let flag = compression.flag();
+        let mut buf = Vec::with_capacity(FRAME_HEADER_SIZE + body.len());
+        buf.write_u32::<LittleEndian>(body.len() as u32)?;
+        buf.write_u32::<LittleEndian>(frame_crc(flag, &body))?;
+        buf.push(flag);
+        buf.extend_from_slice(&body);
+
+        let offset = self.len;
+        self.fd.write_all_at(&buf, offset)?;
+        self.len += buf.len() as u64;
+        Ok((offset, buf.len()))
+    }
+
+    /// read the body of the frame starting at `offset`, verify its checksum
+    /// and decompress it
+    pub fn read_body(&self, offset: u64, size: usize) -> Result<Vec<u8>> {
+        let (compression, body) = self.read_frame(offset, size)?;
+        compression.decompress(body).map_err(|e| match e {
+            KvErr::CorruptedError(reason) => KvErr::CorruptedError(format!(
+                "{} in segment {} at offset {}",
+                reason, self.id, offset
+            )),
+            e => e,
+        })
+    }
+
+    /// read the body of the frame starting at `offset` as it is stored, and
+    /// how it is compressed
+    pub fn read_frame(&self, offset: u64, size: usize) -> Result<(Compression, Vec<u8>)> {
+        let header_size = self.frame_header_size();
+        let mut buf = vec![0u8; size];
+        self.fd.read_exact_at(&mut buf, offset)?;
+        let body = buf.split_off(header_size);
+
+        let flag = match self.version {
+            SEGMENT_VERSION_U16_FRAMES | SEGMENT_VERSION_U32_FRAMES => {
+                return Ok((Compression::None, body))
+            }
+            SEGMENT_VERSION_CRC_FRAMES => None,
+            _ => Some(buf[8]),
+        };
+        let crc = std::io::Cursor::new(&buf[4..8]).read_u32::<LittleEndian>()?;
+        let expected = match flag {
+            Some(flag) => frame_crc(flag, &body),
+            None => crc32fast::hash(&body),
+        };
+        if crc != expected {
+            return Err(KvErr::CorruptedError(format!(
+                "checksum mismatch in segment {} at offset {}",
+                self.id, offset
+            )));
+        }
+        let compression = match flag {
+            Some(flag) => Compression::from_flag(flag)?,
+            None => Compression::None,
+        };
+        Ok((compression, body))
+    }
+
+    /// Read the frame size stored at `offset`, returns None at the end of the
+    /// segment. A frame that does not fit in the segment is reported as corrupted.
+    pub fn frame_size_at(&self, offset: u64) -> Result<Option<usize>> {
+        if offset >= self.len {
+            return Ok(None);
+        }
+        let header_size = self.frame_header_size();
+        if offset + header_size as u64 > self.len {
+            return Err(KvErr::CorruptedError(format!(
+                "torn frame header in segment {} at offset {}",
+                self.id, offset
+            )));
+        }
+        let mut buf = [0u8; FRAME_HEADER_SIZE];
+        self.fd.read_exact_at(&mut buf[..header_size], offset)?;
+        let mut cursor = std::io::Cursor::new(&buf[..header_size]);
+        let body_size = if header_size == U16_FRAME_HEADER_SIZE {
+            cursor.read_u16::<LittleEndian>()? as usize
+        } else {
+            cursor.read_u32::<LittleEndian>()? as usize
+        };
+        let size = body_size + header_size;
+        if offset + size as u64 > self.len {
+            return Err(KvErr::CorruptedError(format!(
+                "torn record in segment {} at offset {}",
+                self.id, offset
+            )));
+        }
+        Ok(Some(size))
+    }
+
+    /// drop everything from `offset` to the end of the segment
+    pub fn truncate(&mut self, offset: u64) -> Result<()> {
+        self.fd.set_len(offset)?;
+        self.len = offset;
+        Ok(())
+    }
+
+    /// iterate over all the frames in this segment as (offset, size, body)
+    pub fn frames(&self) -> Frames<'_> {
+        Frames {
+            segment: self,
+            offset: SEGMENT_HEADER_SIZE,
+            done: false,
+        }
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        self.fd.sync_all()?;
+        Ok(())
+    }
+
+    /// another handle to the segment file, to sync it without the segment
+    pub fn try_clone_file(&self) -> Result<File> {
+        Ok(self.fd.try_clone()?)
+    }
+}
+
+/// the checksum of a version 4 frame covers its compression flag and body
+fn frame_crc(flag: u8, body: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&[flag]);
+    hasher.update(body);
+    hasher.finalize()
+}
+
+/// Iterator over the frames of a segment, it stops after the first error.
+pub struct Frames<'a> {
+    segment: &'a Segment,
+    offset: u64,
+    done: bool,
+}
+
+impl<'a> Frames<'a> {
+    /// offset of the next frame, or of the frame that failed to be read
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = Result<(u64, usize, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let offset = self.offset;
+        let frame = self
+            .segment
+            .frame_size_at(offset)
+            .and_then(|size| match size {
+                Some(size) => self
+                    .segment
+                    .read_body(offset, size)
+                    .map(|body| Some((offset, size, body))),
+                None => Ok(None),
+            });
+        match frame {
+            Ok(Some(frame)) => {
+                self.offset += frame.1 as u64;
+                Some(Ok(frame))
+            }
+            Ok(None) => None,
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// The manifest lists the live segments in the order they must be replayed.
+/// It is only rewritten when the set of segments changes, never on append.
+///
+/// The manifest itself is always CBOR, whatever the codec of the records is.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Manifest {
+    pub segments: Vec<u64>,
+    pub next_segment_id: u64,
+    /// manifests written before the codec was recorded are CBOR
+    #[serde(default)]
+    pub codec: Codec,
+    /// the compression of new records, the ones already written keep theirs
+    #[serde(default)]
+    pub compression: Compression,
+}
+
+impl Manifest {
+    pub fn load(dir: &Path) -> Result<Option<Manifest>> {
+        let path = dir.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read(path)?;
+        Ok(Some(serde_cbor::from_slice(&data)?))
+    }
+
+    /// write the manifest to a temporary file and atomically rename it
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let tmp = dir.join(MANIFEST_TMP_FILE);
+        let mut fd = File::create(&tmp)?;
+        fd.write_all(&serde_cbor::to_vec(self)?)?;
+        fd.sync_all()?;
+        fs::rename(tmp, dir.join(MANIFEST_FILE))?;
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    }
+
+    pub fn allocate_segment_id(&mut self) -> u64 {
+        let id = self.next_segment_id;
+        self.next_segment_id += 1;
+        id
+    }
+
+    /// A manifest for a new store in `dir`, its segment ids start above the
+    /// numbered log files already there so none of them is overwritten.
+    pub fn new_in(dir: &Path, codec: Codec) -> Result<Manifest> {
+        let mut next_segment_id = 0;
+        for entry in fs::read_dir(dir)? {
+            if let Some(id) = segment_id(&entry?.path()) {
+                next_segment_id = next_segment_id.max(id + 1);
+            }
+        }
+        Ok(Manifest {
+            next_segment_id,
+            codec,
+            ..Manifest::default()
+        })
+    }
+
+    /// Remove the segments left behind by a compaction or a crash. Only the
+    /// files the store wrote are removed: a segment header and an id the
+    /// manifest already handed out.
+    pub fn remove_unreferenced(&self, dir: &Path) -> Result<()> {
+        let tmp = dir.join(MANIFEST_TMP_FILE);
+        if tmp.exists() {
+            fs::remove_file(tmp)?;
+        }
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if let Some(id) = segment_id(&path) {
+                if id < self.next_segment_id
+                    && !self.segments.contains(&id)
+                    && Segment::open(dir, id).is_ok()
+                {
+                    fs::remove_file(path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
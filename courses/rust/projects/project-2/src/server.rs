@@ -0,0 +1,113 @@
+//! server serves a KvsEngine over TCP with the kvs protocol.
+//!
+use crate::error::KvErr;
+use crate::protocol::{
+    read_frame, write_message, write_message_as, ErrorKind, Request, Response, PROTOCOL_VERSION,
+};
+use crate::thread_pool::ThreadPool;
+use crate::{KvsEngine, Result};
+use log::{debug, error};
+use std::io::{BufReader, BufWriter};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+pub struct KvsServer<E: KvsEngine, P: ThreadPool> {
+    engine: E,
+    pool: P,
+}
+
+impl<E: KvsEngine, P: ThreadPool> KvsServer<E, P> {
+    pub fn new(engine: E, pool: P) -> Self {
+        Self { engine, pool }
+    }
+
+    /// Listen on `addr` and serve every connection on a thread of the pool.
+    pub fn run(self, addr: impl ToSocketAddrs) -> Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let engine = self.engine.clone();
+                    self.pool.spawn(move || {
+                        if let Err(e) = serve(&engine, stream) {
+                            error!("Error serving client: {}", e);
+                        }
+                    });
+                }
+                Err(e) => error!("Connection failed: {}", e),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn serve<E: KvsEngine>(engine: &E, stream: TcpStream) -> Result<()> {
+    let peer = stream.peer_addr()?;
+    let mut reader = BufReader::new(&stream);
+    let mut writer = BufWriter::new(&stream);
+
+    while let Some((version, body)) = read_frame(&mut reader)? {
+        if version != PROTOCOL_VERSION {
+            write_message_as(
+                &mut writer,
+                reply_version(version),
+                &unsupported_version(version),
+            )?;
+            return Ok(());
+        }
+
+        let response = match serde_cbor::from_slice::<Request>(&body) {
+            Ok(request) => {
+                debug!("Request from {}: {:?}", peer, request);
+                handle(engine, request)
+            }
+            Err(e) => Response::Err(ErrorKind::BadRequest, e.to_string()),
+        };
+        debug!("Response to {}: {:?}", peer, response);
+        write_message(&mut writer, &response)?;
+    }
+    Ok(())
+}
+
+fn handle<E: KvsEngine>(engine: &E, request: Request) -> Response {
+    response(match request {
+        Request::Get { key } => engine.get(key).map(Response::Ok),
+        Request::Set { key, value } => engine.set(key, value).map(|_| Response::Ok(None)),
+        Request::Remove { key } => engine.remove(key).map(|_| Response::Ok(None)),
+        Request::CompareAndSwap { key, expected, new } => engine
+            .compare_and_swap(key, expected, new)
+            .map(Response::Applied),
+        Request::SetIfAbsent { key, value } => {
+            engine.set_if_absent(key, value).map(Response::Applied)
+        }
+        Request::RemoveIfEquals { key, expected } => engine
+            .remove_if_equals(key, expected)
+            .map(Response::Applied),
+    })
+}
+
+/// the response to a request that returned `result`
+pub(crate) fn response(result: Result<Response>) -> Response {
+    match result {
+        Ok(response) => response,
+        Err(e @ KvErr::KeyNotExistError) => Response::Err(ErrorKind::KeyNotFound, e.to_string()),
+        Err(e) => Response::Err(ErrorKind::Internal, e.to_string()),
+    }
+}
+
+/// the version to answer a frame of an unsupported `version` in, the client
+/// can read an error of its own version if it is an older one
+pub(crate) fn reply_version(version: u8) -> u8 {
+    if (1..PROTOCOL_VERSION).contains(&version) {
+        version
+    } else {
+        PROTOCOL_VERSION
+    }
+}
+
+pub(crate) fn unsupported_version(version: u8) -> Response {
+    let msg = format!(
+        "unsupported protocol version {}, the server speaks version {}",
+        version, PROTOCOL_VERSION
+    );
+    Response::Err(ErrorKind::UnsupportedVersion, msg)
+}
@@ -0,0 +1,72 @@
+//! sled_engine adapts the sled embedded database to the KvsEngine interface.
+//!
+use crate::engine::{Engine, KvsEngine};
+use crate::error::KvErr;
+use crate::Result;
+use std::path::PathBuf;
+
+/// A KvsEngine backed by sled. Every write is flushed before returning, like
+/// the writes of the log-structured KvStore reach the file before returning.
+#[derive(Debug, Clone)]
+pub struct SledKvsEngine {
+    db: sled::Db,
+}
+
+impl SledKvsEngine {
+    pub fn open(path: impl Into<PathBuf>) -> Result<SledKvsEngine> {
+        let dir = path.into();
+        Engine::Sled.verify(&dir)?;
+        let db = sled::open(&dir)?;
+        Engine::Sled.claim(&dir)?;
+        Ok(Self { db })
+    }
+
+    /// sled's own compare and swap, None stands for a missing key
+    fn swap(&self, key: String, old: Option<String>, new: Option<String>) -> Result<bool> {
+        let swapped = self
+            .db
+            .compare_and_swap(
+                key,
+                old.map(String::into_bytes),
+                new.map(String::into_bytes),
+            )?
+            .is_ok();
+        if swapped {
+            self.db.flush()?;
+        }
+        Ok(swapped)
+    }
+}
+
+impl KvsEngine for SledKvsEngine {
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.db.insert(key, value.into_bytes())?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        match self.db.get(key)? {
+            Some(val) => Ok(Some(String::from_utf8(val.to_vec())?)),
+            None => Ok(None),
+        }
+    }
+
+    fn remove(&self, key: String) -> Result<()> {
+        self.db.remove(key)?.ok_or(KvErr::KeyNotExistError)?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn compare_and_swap(&self, key: String, expected: String, new: String) -> Result<bool> {
+        self.swap(key, Some(expected), Some(new))
+    }
+
+    fn set_if_absent(&self, key: String, value: String) -> Result<bool> {
+        self.swap(key, None, Some(value))
+    }
+
+    fn remove_if_equals(&self, key: String, expected: String) -> Result<bool> {
+        self.swap(key, Some(expected), None)
+    }
+}
@@ -0,0 +1,199 @@
+//! snapshot is a read-only view of a KvStore frozen at the time it was taken,
+//! and the online backup built on it.
+//!
+//! Records are never changed once written, so a snapshot only needs a copy of
+//! the index and handles to the segments it points into. A compaction can
+//! remove those segment files meanwhile, the open handles keep them readable
+//! until the snapshot is dropped.
+use crate::engine::Engine;
+use crate::error::KvErr;
+use crate::expiry;
+use crate::hint::Hint;
+use crate::segment::{Manifest, Segment};
+use crate::txlog::{LogEntry, LogOperation, LogPointer};
+use crate::{value_of, Codec, Compression, Result};
+use serde_bytes::ByteBuf;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::RangeBounds;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// A read-only view of a store at a point of its log, returned by
+/// `KvStore::snapshot`. Writes made to the store after the snapshot was
+/// taken are not seen. A snapshot is cheap to clone and can be sent to
+/// another thread.
+///
+/// The snapshot holds a copy of the index and keeps the segment files it
+/// needs open, so it costs memory and file handles until it is dropped.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    codec: Codec,
+    compression: Compression,
+    index: Arc<BTreeMap<Vec<u8>, LogPointer>>,
+    segments: Arc<HashMap<u64, Segment>>,
+}
+
+impl Snapshot {
+    pub(crate) fn new(
+        codec: Codec,
+        compression: Compression,
+        index: BTreeMap<Vec<u8>, LogPointer>,
+        segments: HashMap<u64, Segment>,
+    ) -> Self {
+        Self {
+            codec,
+            compression,
+            index: Arc::new(index),
+            segments: Arc::new(segments),
+        }
+    }
+
+    /// Get the value of `key` when the snapshot was taken. A key set with a
+    /// time to live is gone once it expires, like in the store.
+    pub fn get(&self, key: String) -> Result<Option<String>> {
+        match self.get_bytes(key.as_bytes())? {
+            Some(value) => Ok(Some(String::from_utf8(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_bytes(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.index.get(key) {
+            Some(ptr) => Ok(self.read_value(key, ptr)?.map(|(value, _)| value)),
+            None => Ok(None),
+        }
+    }
+
+    /// number of keys in the snapshot when it was taken
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Iterate over the key value pairs with a key in `range`, in key order.
+    pub fn scan<R: RangeBounds<String>>(
+        &self,
+        range: R,
+    ) -> impl Iterator<Item = Result<(String, String)>> + '_ {
+        let range = (
+            range.start_bound().map(|key| key.as_bytes().to_vec()),
+            range.end_bound().map(|key| key.as_bytes().to_vec()),
+        );
+        self.scan_bytes(range).map(into_strings)
+    }
+
+    /// Iterate over the key value pairs whose key starts with `prefix`, in
+    /// key order.
+    pub fn scan_prefix<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> impl Iterator<Item = Result<(String, String)>> + 'a {
+        self.scan_prefix_bytes(prefix.as_bytes()).map(into_strings)
+    }
+
+    /// Iterate over the raw key value pairs with a key in `range`, in the
+    /// byte order of the keys.
+    pub fn scan_bytes<R: RangeBounds<Vec<u8>>>(
+        &self,
+        range: R,
+    ) -> impl Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + '_ {
+        self.index
+            .range(range)
+            .filter_map(move |(key, ptr)| self.read_pair(key, ptr).transpose())
+    }
+
+    /// Iterate over the raw key value pairs whose key starts with `prefix`.
+    pub fn scan_prefix_bytes<'a>(
+        &'a self,
+        prefix: &'a [u8],
+    ) -> impl Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a {
+        self.index
+            .range(prefix.to_vec()..)
+            .take_while(move |(key, _)| key.starts_with(prefix))
+            .filter_map(move |(key, ptr)| self.read_pair(key, ptr).transpose())
+    }
+
+    /// Write the snapshot to `path` as a new compacted store: one record per
+    /// key, and a hint file so it opens without replaying them. `path` must
+    /// not hold a store already.
+    pub fn backup_to(&self, path: impl Into<PathBuf>) -> Result<()> {
+        let dir = path.into();
+        std::fs::create_dir_all(&dir)?;
+        if Manifest::load(&dir)?.is_some() {
+            return Err(KvErr::IOError(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} already holds a store", dir.display()),
+            )));
+        }
+        Engine::Kvs.claim(&dir)?;
+
+        // numbered after any log file already there, none is overwritten
+        let mut manifest = Manifest {
+            compression: self.compression,
+            ..Manifest::new_in(&dir, self.codec)?
+        };
+        let mut segments = vec![Segment::create(&dir, manifest.allocate_segment_id())?];
+        let mut entries = Vec::with_capacity(self.index.len());
+        let mut expiring = Vec::new();
+        for (key, ptr) in self.index.iter() {
+            let op = match self.read_value(key, ptr)? {
+                Some((value, None)) => LogOperation::Set(key.clone(), value),
+                Some((value, Some(at))) => {
+                    expiring.push((ByteBuf::from(key.clone()), at));
+                    LogOperation::SetExpiring(key.clone(), value, at)
+                }
+                None => continue,
+            };
+            let mut segment = segments.last_mut().expect("at least one segment");
+            if segment.is_full() {
+                segments.push(Segment::create(&dir, manifest.allocate_segment_id())?);
+                segment = segments.last_mut().expect("at least one segment");
+            }
+            let body = self.codec.encode(&LogEntry(entries.len(), op))?;
+            let (offset, size) = segment.append(&body, self.compression)?;
+            entries.push((
+                ByteBuf::from(key.clone()),
+                LogPointer(segment.id, offset, size),
+            ));
+        }
+        for segment in segments.iter() {
+            segment.sync()?;
+        }
+
+        Hint {
+            segments: segments.iter().map(|s| (s.id, s.len())).collect(),
+            next_log_id: entries.len(),
+            entries,
+            expiring,
+        }
+        .save(&dir)?;
+        // the backup is only a store once its manifest is in place
+        manifest.segments = segments.iter().map(|s| s.id).collect();
+        manifest.save(&dir)
+    }
+
+    /// the pair of `key`, None if it expired
+    fn read_pair(&self, key: &[u8], ptr: &LogPointer) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        Ok(self
+            .read_value(key, ptr)?
+            .map(|(value, _)| (key.to_vec(), value)))
+    }
+
+    /// the value of `key` and when it expires, None if it expired
+    fn read_value(&self, key: &[u8], ptr: &LogPointer) -> Result<Option<(Vec<u8>, Option<u64>)>> {
+        let segment = self.segments.get(&ptr.0).ok_or_else(|| {
+            KvErr::CorruptedError(format!("segment {} is not in the snapshot", ptr.0))
+        })?;
+        let body = segment.read_body(ptr.1, ptr.2)?;
+        let LogEntry(_, op) = self.codec.decode(&body)?;
+        value_of(key, op, expiry::now())
+    }
+}
+
+fn into_strings(pair: Result<(Vec<u8>, Vec<u8>)>) -> Result<(String, String)> {
+    let (key, value) = pair?;
+    Ok((String::from_utf8(key)?, String::from_utf8(value)?))
+}
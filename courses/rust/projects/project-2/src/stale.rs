@@ -0,0 +1,51 @@
+//! stale keeps track of the bytes in the log that are no longer needed.
+//!
+use crate::txlog::LogPointer;
+use std::collections::HashMap;
+
+/// Bytes of the records that no key points to anymore, per segment. The
+/// record of a write batch can be pointed to by several keys, it only becomes
+/// stale once the last of them has moved away.
+#[derive(Debug, Default)]
+pub struct StaleBytes {
+    per_segment: HashMap<u64, u64>,
+    /// number of keys pointing to the records that more than one key points to
+    shared: HashMap<LogPointer, usize>,
+}
+
+impl StaleBytes {
+    pub fn total(&self) -> u64 {
+        self.per_segment.values().sum()
+    }
+
+    /// `keys` keys now point to the record at `ptr`
+    pub fn share(&mut self, ptr: LogPointer, keys: usize) {
+        match keys {
+            0 => self.release(ptr),
+            1 => {}
+            n => {
+                self.shared.insert(ptr, n);
+            }
+        }
+    }
+
+    /// one key no longer points to the record at `ptr`
+    pub fn release(&mut self, ptr: LogPointer) {
+        if let Some(keys) = self.shared.get_mut(&ptr) {
+            *keys -= 1;
+            if *keys == 1 {
+                self.shared.remove(&ptr);
+            }
+            return;
+        }
+        *self.per_segment.entry(ptr.0).or_default() += ptr.2 as u64;
+    }
+
+    /// forget the segments removed by a compaction
+    pub fn remove_segments(&mut self, ids: &[u64]) {
+        for id in ids {
+            self.per_segment.remove(id);
+        }
+        self.shared.retain(|ptr, _| !ids.contains(&ptr.0));
+    }
+}
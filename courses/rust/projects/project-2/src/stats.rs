@@ -0,0 +1,164 @@
+//! stats collects the counters of a store, for `KvStore::stats` and the
+//! metrics endpoint of kvs-server.
+//!
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// The state of a store and what it has done since it was opened, returned by
+/// `KvStore::stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// keys in the index, a key that expired is counted until it is next
+    /// written or compacted away
+    pub live_keys: usize,
+    pub segments: usize,
+    /// size of all the segment files
+    pub log_bytes: u64,
+    /// bytes of the records no key points to, reclaimed by a compaction
+    pub stale_bytes: u64,
+    /// a compaction starts once `stale_bytes` reaches it
+    pub compaction_threshold: u64,
+    /// compactions installed since the store was opened
+    pub compactions: u64,
+    /// fsyncs of the log since the store was opened, one per write with
+    /// `Durability::Always`, shared by concurrent writes with `Group`
+    pub log_syncs: u64,
+    /// `get` calls, including the ones of the string API
+    pub reads: Latency,
+    /// writes of any kind, including the wait for their fsync
+    pub writes: Latency,
+}
+
+/// How long the calls of one kind took.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Latency {
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl Latency {
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            n => Duration::from_nanos((self.total.as_nanos() / n as u128) as u64),
+        }
+    }
+}
+
+impl Stats {
+    /// The stats in the Prometheus text exposition format. Latencies are
+    /// summaries without quantiles, in seconds.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let gauges = [
+            ("kvs_live_keys", "Keys in the index.", self.live_keys as u64),
+            (
+                "kvs_segments",
+                "Segment files of the log.",
+                self.segments as u64,
+            ),
+            (
+                "kvs_log_bytes",
+                "Size of the segment files.",
+                self.log_bytes,
+            ),
+            (
+                "kvs_stale_bytes",
+                "Bytes of records no key points to.",
+                self.stale_bytes,
+            ),
+            (
+                "kvs_compaction_threshold_bytes",
+                "Stale bytes that start a compaction.",
+                self.compaction_threshold,
+            ),
+        ];
+        for (name, help, value) in gauges {
+            metric(&mut out, name, help, "gauge");
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+        metric(
+            &mut out,
+            "kvs_compactions_total",
+            "Compactions installed since the store was opened.",
+            "counter",
+        );
+        let _ = writeln!(out, "kvs_compactions_total {}", self.compactions);
+        metric(
+            &mut out,
+            "kvs_log_syncs_total",
+            "Fsyncs of the log since the store was opened.",
+            "counter",
+        );
+        let _ = writeln!(out, "kvs_log_syncs_total {}", self.log_syncs);
+        summary(
+            &mut out,
+            "kvs_read_duration_seconds",
+            "Duration of reads.",
+            &self.reads,
+        );
+        summary(
+            &mut out,
+            "kvs_write_duration_seconds",
+            "Duration of writes.",
+            &self.writes,
+        );
+        out
+    }
+}
+
+fn metric(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn summary(out: &mut String, name: &str, help: &str, latency: &Latency) {
+    metric(out, name, help, "summary");
+    let _ = writeln!(out, "{}_sum {}", name, latency.total.as_secs_f64());
+    let _ = writeln!(out, "{}_count {}", name, latency.count);
+    let max = format!("{}_max", name);
+    metric(
+        out,
+        &max,
+        "Longest call since the store was opened.",
+        "gauge",
+    );
+    let _ = writeln!(out, "{} {}", max, latency.max.as_secs_f64());
+}
+
+/// Latencies shared by every clone of a store, recorded without locking.
+#[derive(Debug, Default)]
+pub struct Latencies {
+    pub reads: LatencyCounter,
+    pub writes: LatencyCounter,
+}
+
+#[derive(Debug, Default)]
+pub struct LatencyCounter {
+    count: AtomicU64,
+    total_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+}
+
+impl LatencyCounter {
+    /// run `call` and record how long it took
+    pub fn time<T>(&self, call: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = call();
+        let nanos = start.elapsed().as_nanos() as u64;
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+        result
+    }
+
+    pub fn load(&self) -> Latency {
+        Latency {
+            count: self.count.load(Ordering::Relaxed),
+            total: Duration::from_nanos(self.total_nanos.load(Ordering::Relaxed)),
+            max: Duration::from_nanos(self.max_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
@@ -0,0 +1,25 @@
+//! thread_pool runs jobs on a set of threads. The server uses a pool to
+//! serve several connections at the same time.
+//!
+use crate::Result;
+
+mod naive;
+mod rayon;
+mod shared_queue;
+pub use self::naive::NaiveThreadPool;
+pub use self::rayon::RayonThreadPool;
+pub use self::shared_queue::SharedQueueThreadPool;
+
+pub trait ThreadPool {
+    /// Create a pool of `threads` threads, spawned right away. Fails if any
+    /// thread cannot be spawned.
+    fn new(threads: u32) -> Result<Self>
+    where
+        Self: Sized;
+
+    /// Run `job` on a thread of the pool. A job that panics does not reduce
+    /// the number of threads of the pool.
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static;
+}
@@ -0,0 +1,19 @@
+use super::ThreadPool;
+use crate::Result;
+use std::thread;
+
+/// Not really a pool, every job runs on a new thread.
+pub struct NaiveThreadPool;
+
+impl ThreadPool for NaiveThreadPool {
+    fn new(_threads: u32) -> Result<Self> {
+        Ok(NaiveThreadPool)
+    }
+
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        thread::spawn(job);
+    }
+}
@@ -0,0 +1,25 @@
+use super::ThreadPool;
+use crate::error::KvErr;
+use crate::Result;
+
+/// A pool backed by a rayon thread pool.
+pub struct RayonThreadPool {
+    pool: rayon::ThreadPool,
+}
+
+impl ThreadPool for RayonThreadPool {
+    fn new(threads: u32) -> Result<Self> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads as usize)
+            .build()
+            .map_err(|e| KvErr::ThreadPoolError(e.to_string()))?;
+        Ok(Self { pool })
+    }
+
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.pool.spawn(job)
+    }
+}
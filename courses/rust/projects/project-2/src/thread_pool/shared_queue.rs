@@ -0,0 +1,56 @@
+use super::ThreadPool;
+use crate::Result;
+use crossbeam_channel::{Receiver, Sender};
+use log::error;
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed number of threads taking jobs from a shared queue. The threads
+/// exit once the pool is dropped and the queue is drained.
+pub struct SharedQueueThreadPool {
+    tx: Sender<Job>,
+}
+
+impl ThreadPool for SharedQueueThreadPool {
+    fn new(threads: u32) -> Result<Self> {
+        let (tx, rx) = crossbeam_channel::unbounded::<Job>();
+        for _ in 0..threads {
+            spawn_worker(rx.clone())?;
+        }
+        Ok(Self { tx })
+    }
+
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.tx
+            .send(Box::new(job))
+            .expect("the pool threads hold the queue open");
+    }
+}
+
+/// The receiving end of the queue owned by one thread. When a job panics the
+/// thread unwinds, and dropping its worker starts a thread to replace it.
+struct Worker(Receiver<Job>);
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            if let Err(e) = spawn_worker(self.0.clone()) {
+                error!("Failed to replace a panicked pool thread: {}", e);
+            }
+        }
+    }
+}
+
+fn spawn_worker(rx: Receiver<Job>) -> Result<()> {
+    let worker = Worker(rx);
+    thread::Builder::new().spawn(move || {
+        while let Ok(job) = worker.0.recv() {
+            job();
+        }
+    })?;
+    Ok(())
+}
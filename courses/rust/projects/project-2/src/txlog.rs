@@ -5,18 +5,60 @@ use serde::{Deserialize, Serialize};
 /// tuple(logid, log_type)
 pub struct LogEntry(pub usize, pub LogOperation);
 
-#[derive(Serialize, Deserialize, Debug)]
-/// tuple (file offset, entry size)
-pub struct LogPointer(pub usize, pub usize);
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// tuple (segment id, frame offset, frame size)
+pub struct LogPointer(pub u64, pub u64, pub usize);
 
-impl LogPointer {
-    pub fn new() -> Self {
-        Self(0, 0)
+/// Keys and values are raw bytes, see `raw`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum LogOperation {
+    Set(
+        #[serde(with = "raw")] Vec<u8>,
+        #[serde(with = "raw")] Vec<u8>,
+    ),
+    Rm(#[serde(with = "raw")] Vec<u8>),
+    /// `Set` and `Rm` operations written as one record, replay applies all
+    /// of them or none
+    Batch(Vec<LogOperation>),
+    /// tuple (key, value, expiration in milliseconds since the UNIX epoch),
+    /// the key is gone once the expiration has passed
+    SetExpiring(
+        #[serde(with = "raw")] Vec<u8>,
+        #[serde(with = "raw")] Vec<u8>,
+        u64,
+    ),
+}
+
+impl LogOperation {
+    /// the key written by the operation, None for a batch
+    pub fn key(&self) -> Option<&[u8]> {
+        match self {
+            LogOperation::Set(key, _)
+            | LogOperation::SetExpiring(key, _, _)
+            | LogOperation::Rm(key) => Some(key),
+            LogOperation::Batch(_) => None,
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub enum LogOperation {
-    Set(String, String),
-    Rm(String),
+/// How the keys and values of a record are serialized.
+///
+/// Binary codecs store them as byte strings, without any per byte overhead.
+/// Human readable codecs store valid UTF-8 as a string, so JSON records stay
+/// readable, and anything else as an array of bytes. Records written when
+/// keys and values were strings are read back as their UTF-8 bytes.
+mod raw {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde_bytes::ByteBuf;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        match std::str::from_utf8(bytes) {
+            Ok(text) if serializer.is_human_readable() => serializer.serialize_str(text),
+            _ => serializer.serialize_bytes(bytes),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        ByteBuf::deserialize(deserializer).map(ByteBuf::into_vec)
+    }
 }
@@ -0,0 +1,668 @@
+//! writer owns everything that changes the log: appending records, keeping
+//! the stale byte counts, rolling segments and installing compactions.
+//!
+//! There is a single writer per store, shared by every clone behind a mutex,
+//! so writes are serialized. It is the only one that changes the index, so it
+//! can look an entry up and replace it without racing with other writers.
+use crate::compaction::{CompactionResult, CompactionTask};
+use crate::durability::GroupCommit;
+use crate::error::KvErr;
+use crate::expiry::{self, Expiring};
+use crate::hint::Hint;
+use crate::index::Index;
+use crate::mvcc::Versions;
+use crate::reader::LiveSegments;
+use crate::segment::{self, Manifest, Segment, SEGMENT_HEADER_SIZE};
+use crate::snapshot::Snapshot;
+use crate::stale::StaleBytes;
+use crate::stats::Stats;
+use crate::txlog::{LogEntry, LogOperation, LogPointer};
+use crate::{Compression, Durability, OpenOptions, RecoveryReport, Result, Truncation, WriteBatch};
+use log::error;
+use serde_bytes::ByteBuf;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+#[derive(Debug)]
+pub struct KvStoreWriter {
+    dir: Arc<PathBuf>,
+    options: OpenOptions,
+    manifest: Manifest,
+    /// the segment records are appended to, always the last one of the manifest
+    active: Segment,
+    next_log_id: usize,
+    index: Arc<Index>,
+    versions: Arc<Versions>,
+    live: Arc<LiveSegments>,
+    /// compacted segments still read by transactions older than the
+    /// compaction, tuple (next log id when it started, segment ids)
+    retired: Vec<(usize, Vec<u64>)>,
+    stale: StaleBytes,
+    /// the live keys set with a time to live
+    expiring: Expiring,
+    compaction: Option<JoinHandle<Result<CompactionResult>>>,
+    /// why the last background compaction failed, returned by the next
+    /// explicit `compact`
+    compaction_error: Option<KvErr>,
+    /// compactions installed since the store was opened
+    compactions: u64,
+    /// set with `Durability::Group`
+    commit: Option<Arc<GroupCommit>>,
+    /// fsyncs of the active segment, shared with the group commit
+    syncs: Arc<AtomicU64>,
+}
+
+impl KvStoreWriter {
+    /// Open the segments of `manifest` and rebuild `index` from them.
+    pub fn open(
+        dir: Arc<PathBuf>,
+        options: OpenOptions,
+        manifest: Manifest,
+        index: Arc<Index>,
+        versions: Arc<Versions>,
+        live: Arc<LiveSegments>,
+    ) -> Result<(KvStoreWriter, RecoveryReport)> {
+        let mut segments = HashMap::new();
+        let mut torn = None;
+        for id in manifest.segments.iter() {
+            let segment = match Segment::open(&dir, *id) {
+                // the newest segment may have been made live by the manifest
+                // before its header reached the disk, it holds no record
+                Err(KvErr::CorruptedError(reason))
+                    if Some(id) == manifest.segments.last()
+                        && Segment::has_torn_header(&dir, *id)? =>
+                {
+                    let discarded_bytes =
+                        std::fs::metadata(segment::segment_path(&dir, *id))?.len();
+                    torn = Some(Truncation {
+                        segment: *id,
+                        offset: 0,
+                        discarded_bytes,
+                        reason,
+                    });
+                    Segment::create(&dir, *id)?
+                }
+                segment => segment?,
+            };
+            segments.insert(*id, segment);
+            live.insert(*id);
+        }
+        let mut replay = replay_log(&dir, &manifest, &mut segments, &index)?;
+        replay.recovery.truncated.extend(torn);
+        let active_id = manifest.segments.last().expect("at least one segment");
+        versions.open_at(replay.next_log_id);
+
+        let mut writer = Self {
+            active: segments.remove(active_id).expect("active segment is open"),
+            dir,
+            options,
+            manifest,
+            next_log_id: replay.next_log_id,
+            index,
+            versions,
+            live,
+            retired: Vec::new(),
+            stale: replay.stale,
+            expiring: replay.expiring,
+            compaction: None,
+            compaction_error: None,
+            compactions: 0,
+            commit: None,
+            syncs: Arc::default(),
+        };
+        if writer.options.durability == Durability::Group {
+            let active = writer.active.try_clone_file()?;
+            writer.commit = Some(Arc::new(GroupCommit::new(
+                active,
+                writer.next_log_id,
+                writer.syncs.clone(),
+            )));
+        }
+        if !writer.active.is_current_version() {
+            writer.roll_segment()?;
+        }
+        Ok((writer, replay.recovery))
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        self.syncs.fetch_add(1, Ordering::Relaxed);
+        self.active.sync()
+    }
+
+    /// The stats the writer keeps, the latencies are filled in by the store.
+    pub fn stats(&self) -> Result<Stats> {
+        let mut log_bytes = self.active.len();
+        for id in self
+            .manifest
+            .segments
+            .iter()
+            .filter(|id| **id != self.active.id)
+        {
+            log_bytes += std::fs::metadata(segment::segment_path(&self.dir, *id))?.len();
+        }
+        Ok(Stats {
+            live_keys: self.index.len(),
+            segments: self.manifest.segments.len(),
+            log_bytes,
+            stale_bytes: self.stale.total(),
+            compaction_threshold: self.options.compaction_threshold,
+            compactions: self.compactions,
+            log_syncs: self.syncs.load(Ordering::Relaxed),
+            ..Stats::default()
+        })
+    }
+
+    /// the group commit writes wait on after releasing the writer
+    pub fn group_commit(&self) -> Option<Arc<GroupCommit>> {
+        self.commit.clone()
+    }
+
+    // The writes return the number of records appended once their record is
+    // in, with group commit it is only on disk once that many are committed.
+
+    pub fn set(&mut self, key: Vec<u8>, val: Vec<u8>) -> Result<usize> {
+        self.append(LogOperation::Set(key, val))
+    }
+
+    pub fn set_expiring(&mut self, key: Vec<u8>, val: Vec<u8>, expires_at: u64) -> Result<usize> {
+        self.append(LogOperation::SetExpiring(key, val, expires_at))
+    }
+
+    pub fn remove(&mut self, key: Vec<u8>) -> Result<usize> {
+        if !self.exists(&key, expiry::now()) {
+            return Err(KvErr::KeyNotExistError);
+        }
+        self.append(LogOperation::Rm(key))
+    }
+
+    /// whether `key` is in the index and has not expired by `now`
+    fn exists(&self, key: &[u8], now: u64) -> bool {
+        self.index.contains_key(key) && !self.expiring.is_expired(key, now)
+    }
+
+    /// Write all the operations of `batch` as one record. Nothing is written
+    /// if the batch removes a key that does not exist at that point.
+    pub fn write(&mut self, batch: WriteBatch) -> Result<usize> {
+        let ops = batch.into_ops();
+        if ops.is_empty() {
+            return Ok(self.next_log_id);
+        }
+        let now = expiry::now();
+        let mut exists = HashMap::new();
+        for op in ops.iter() {
+            match op {
+                LogOperation::Set(key, _) => {
+                    exists.insert(key.as_slice(), true);
+                }
+                LogOperation::SetExpiring(key, _, at) => {
+                    exists.insert(key.as_slice(), *at > now);
+                }
+                LogOperation::Rm(key) => {
+                    let found = exists
+                        .insert(key.as_slice(), false)
+                        .unwrap_or_else(|| self.exists(key, now));
+                    if !found {
+                        return Err(KvErr::KeyNotExistError);
+                    }
+                }
+                LogOperation::Batch(_) => unreachable!("batches are not nested"),
+            }
+        }
+        self.append(LogOperation::Batch(ops))
+    }
+
+    /// append the record of `op` and point the index to it
+    fn append(&mut self, op: LogOperation) -> Result<usize> {
+        let log_id = self.next_log_id;
+        let entry = LogEntry(log_id, op);
+        let entry_bytes = self.manifest.codec.encode(&entry)?;
+
+        let log_ptr = self.insert_log(entry_bytes)?;
+        match self.options.durability {
+            Durability::Always => self.sync()?,
+            Durability::Group => self
+                .commit
+                .as_ref()
+                .expect("group commit is set up on open")
+                .appended(self.next_log_id),
+            Durability::Buffered => {}
+        }
+        self.supersede(&entry.1, log_id);
+        apply(
+            &self.index,
+            &mut self.stale,
+            &mut self.expiring,
+            log_ptr,
+            entry.1,
+            expiry::now(),
+        );
+        self.versions.publish(self.next_log_id);
+
+        // the record is written and applied, a failed compaction is not a
+        // failed write
+        if let Err(e) = self.maybe_compact() {
+            error!("background compaction failed: {}", e);
+            self.compaction_error = Some(e);
+        }
+        Ok(self.next_log_id)
+    }
+
+    /// Add the records `op` replaces to the history of their keys. It is done
+    /// before the index changes, so a read at an older version that misses
+    /// the index entry finds its record in the history.
+    fn supersede(&self, op: &LogOperation, log_id: usize) {
+        let ops = match op {
+            LogOperation::Batch(ops) => ops.as_slice(),
+            op => std::slice::from_ref(op),
+        };
+        let mut seen = HashSet::new();
+        for key in ops.iter().filter_map(LogOperation::key) {
+            if !seen.insert(key) {
+                continue;
+            }
+            if let Some(old) = self.index.get(key) {
+                self.versions.supersede(key, log_id, old);
+            }
+        }
+    }
+
+    /// Copy the index and open every segment. No write or compaction can
+    /// land halfway through, since they all go through the writer.
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        let now = expiry::now();
+        let index: BTreeMap<Vec<u8>, LogPointer> = self
+            .index
+            .iter()
+            .filter(|(key, _)| !self.expiring.is_expired(key, now))
+            .collect();
+        let mut segments = HashMap::new();
+        for id in self.manifest.segments.iter() {
+            segments.insert(*id, Segment::open(&self.dir, *id)?);
+        }
+        Ok(Snapshot::new(
+            self.manifest.codec,
+            self.manifest.compression,
+            index,
+            segments,
+        ))
+    }
+
+    /// open every segment of the manifest, to read them without the writer
+    pub fn open_segments(&self) -> Result<Vec<Segment>> {
+        self.manifest
+            .segments
+            .iter()
+            .map(|id| Segment::open(&self.dir, *id))
+            .collect()
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.manifest.compression
+    }
+
+    /// Compact all the segments right away and wait until it is done. If a
+    /// background compaction failed since the last call, its error is
+    /// returned instead and the next call compacts.
+    pub fn compact(&mut self) -> Result<()> {
+        if let Some(e) = self.compaction_error.take() {
+            return Err(e);
+        }
+        self.wait_for_compaction()?;
+        if let Some(task) = self.prepare_compaction()? {
+            let result = task.run()?;
+            self.install_compaction(result)?;
+        }
+        Ok(())
+    }
+
+    /// Install a finished background compaction, and start a new one once
+    /// enough stale bytes have piled up.
+    fn maybe_compact(&mut self) -> Result<()> {
+        self.remove_retired()?;
+        match &self.compaction {
+            Some(handle) if !handle.is_finished() => return Ok(()),
+            Some(_) => self.wait_for_compaction()?,
+            None => {}
+        }
+        if self.stale.total() >= self.options.compaction_threshold {
+            if let Some(task) = self.prepare_compaction()? {
+                self.compaction = Some(std::thread::spawn(move || task.run()));
+            }
+        }
+        Ok(())
+    }
+
+    fn wait_for_compaction(&mut self) -> Result<()> {
+        if let Some(handle) = self.compaction.take() {
+            let result = handle.join().map_err(|panic| {
+                let msg = panic
+                    .downcast_ref::<&str>()
+                    .map(|msg| msg.to_string())
+                    .or_else(|| panic.downcast_ref::<String>().cloned())
+                    .unwrap_or_default();
+                KvErr::CompactionError(format!("the compaction thread panicked: {}", msg))
+            })??;
+            self.install_compaction(result)?;
+        }
+        Ok(())
+    }
+
+    /// Seal the active segment and collect the live entries of every sealed
+    /// segment. Returns None when there is nothing to compact.
+    fn prepare_compaction(&mut self) -> Result<Option<CompactionTask>> {
+        if self.active.len() > SEGMENT_HEADER_SIZE {
+            self.roll_segment()?;
+        }
+        let (active, sealed) = self
+            .manifest
+            .segments
+            .split_last()
+            .expect("at least one segment");
+        if sealed.is_empty() {
+            return Ok(None);
+        }
+        let inputs = sealed.to_vec();
+        // expired keys are dropped instead of copied
+        let now = expiry::now();
+        let (expired, live): (Vec<_>, Vec<_>) = self
+            .index
+            .iter()
+            .filter(|(_, ptr)| ptr.0 != *active)
+            .partition(|(key, _)| self.expiring.is_expired(key, now));
+        let expiring = live
+            .iter()
+            .filter_map(|(key, _)| self.expiring.get(key).map(|at| (key.clone(), at)))
+            .collect();
+        let output_ids = inputs
+            .iter()
+            .map(|_| self.manifest.allocate_segment_id())
+            .collect();
+        // persist the reserved ids, so that a hint file written for them can
+        // never match segments created after a crash
+        self.manifest.save(&self.dir)?;
+
+        Ok(Some(CompactionTask {
+            dir: self.dir.as_ref().clone(),
+            codec: self.manifest.codec,
+            compression: self.manifest.compression,
+            inputs,
+            output_ids,
+            live,
+            expiring,
+            expired,
+            next_log_id: self.next_log_id,
+        }))
+    }
+
+    /// Swap the new generation in place of the compacted segments. Entries
+    /// that were overwritten while the compaction was running are stale in
+    /// the new generation.
+    ///
+    /// Readers may still hold pointers into the compacted segments, so the
+    /// index is updated before the old segments are retired, and their files
+    /// are only removed after that. A reader that misses a segment looks its
+    /// key up again and finds the new pointer.
+    fn install_compaction(&mut self, result: CompactionResult) -> Result<()> {
+        let CompactionResult {
+            inputs,
+            outputs,
+            moved,
+            expired,
+            next_log_id,
+        } = result;
+
+        for output in outputs.iter() {
+            self.live.insert(output.id);
+        }
+        self.stale.remove_segments(&inputs);
+        self.versions.compacted(next_log_id, &inputs, &moved);
+        // keys moved to each copied record, the records of batches are
+        // copied once for all their keys
+        let mut copies: HashMap<LogPointer, usize> = HashMap::new();
+        for (key, old, new) in moved {
+            let keys = copies.entry(new).or_default();
+            if self.index.get(&key) == Some(old) {
+                self.index.insert(key, new);
+                *keys += 1;
+            }
+        }
+        for (new, keys) in copies {
+            self.stale.share(new, keys);
+        }
+        for (key, old) in expired {
+            if self.index.get(&key) == Some(old) {
+                self.index.remove(&key);
+                self.expiring.set(&key, None);
+            }
+        }
+
+        let mut segments: Vec<u64> = outputs.iter().map(|s| s.id).collect();
+        segments.extend(
+            self.manifest
+                .segments
+                .iter()
+                .filter(|id| !inputs.contains(id)),
+        );
+        self.manifest.segments = segments;
+        self.manifest.save(&self.dir)?;
+        self.compactions += 1;
+
+        self.retired.push((next_log_id, inputs));
+        self.remove_retired()
+    }
+
+    /// Remove the compacted segments no open read transaction can read from,
+    /// the ones of a transaction older than the compaction wait until it ends.
+    fn remove_retired(&mut self) -> Result<()> {
+        if self.retired.is_empty() {
+            return Ok(());
+        }
+        let oldest = self.versions.oldest_reader();
+        let (removable, kept) = std::mem::take(&mut self.retired)
+            .into_iter()
+            .partition(|(next_log_id, _)| oldest.is_none_or(|reader| reader >= *next_log_id));
+        self.retired = kept;
+        for (_, inputs) in removable {
+            self.live.remove(&inputs);
+            for id in inputs {
+                std::fs::remove_file(segment::segment_path(&self.dir, id))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// seal the active segment and start a new one
+    fn roll_segment(&mut self) -> Result<()> {
+        // the records of a sealed segment are flushed before any record of
+        // the new one, so that a synced record never follows a lost one and
+        // only the active segment can have a torn tail
+        self.sync()?;
+        let segment = Segment::create(&self.dir, self.manifest.allocate_segment_id())?;
+        self.manifest.segments.push(segment.id);
+        self.manifest.save(&self.dir)?;
+        self.live.insert(segment.id);
+        self.active = segment;
+        if let Some(commit) = &self.commit {
+            commit.rolled(self.active.try_clone_file()?);
+        }
+        Ok(())
+    }
+
+    /// append a record to the active segment, starting a new one when it is full
+    fn insert_log(&mut self, log_entry: Vec<u8>) -> Result<LogPointer> {
+        if self.active.is_full() {
+            self.roll_segment()?;
+        }
+        let (offset, size) = self.active.append(&log_entry, self.manifest.compression)?;
+        self.next_log_id += 1;
+        Ok(LogPointer(self.active.id, offset, size))
+    }
+}
+
+impl Drop for KvStoreWriter {
+    fn drop(&mut self) {
+        // let a running compaction finish so its work is not thrown away, an
+        // error here leaves the old generation in place
+        let _ = self.wait_for_compaction();
+        // every transaction holds a clone of the store, none is left
+        let _ = self.remove_retired();
+    }
+}
+
+/// what replaying the log found besides the index
+struct Replay {
+    next_log_id: usize,
+    stale: StaleBytes,
+    expiring: Expiring,
+    recovery: RecoveryReport,
+}
+
+/// Rebuild `index` by replaying every segment in manifest order, starting
+/// after the segments covered by the hint file if it is still valid. The
+/// active segment is replayed up to its first bad record, the rest of it is
+/// a torn tail that is truncated and recorded in the recovery report. Sealed
+/// segments were synced whole, a bad record in one fails the open and is left
+/// for `KvStore::repair`.
+fn replay_log(
+    dir: &Path,
+    manifest: &Manifest,
+    segments: &mut HashMap<u64, Segment>,
+    index: &Index,
+) -> Result<Replay> {
+    let mut replay = Replay {
+        next_log_id: 0,
+        stale: StaleBytes::default(),
+        expiring: Expiring::default(),
+        recovery: RecoveryReport::default(),
+    };
+    let now = expiry::now();
+    let mut skip = 0;
+    if let Some(hint) = Hint::load(dir) {
+        if hint.covers(&manifest.segments, segments) {
+            skip = hint.segments.len();
+            replay.next_log_id = hint.next_log_id;
+            let expiring: HashMap<ByteBuf, u64> = hint.expiring.into_iter().collect();
+            let mut keys: HashMap<LogPointer, usize> = HashMap::new();
+            for (key, ptr) in hint.entries {
+                let live = keys.entry(ptr).or_default();
+                let expires_at = expiring.get(&key).copied();
+                if expires_at.is_some_and(|at| at <= now) {
+                    continue;
+                }
+                replay.expiring.set(&key, expires_at);
+                index.insert(key.into_vec(), ptr);
+                *live += 1;
+            }
+            for (ptr, keys) in keys {
+                replay.stale.share(ptr, keys);
+            }
+            replay.recovery.used_hint = true;
+        }
+    }
+
+    for id in manifest.segments[skip..].iter().copied() {
+        let mut frames = segments[&id].frames();
+        let mut bad = None;
+        for frame in &mut frames {
+            let (offset, size, body) = match frame {
+                Ok(frame) => frame,
+                Err(KvErr::CorruptedError(reason)) => {
+                    bad = Some((frames.offset(), reason));
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+            let LogEntry(log_id, op) = match manifest.codec.decode(&body) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    bad = Some((offset, e.to_string()));
+                    break;
+                }
+            };
+            apply(
+                index,
+                &mut replay.stale,
+                &mut replay.expiring,
+                LogPointer(id, offset, size),
+                op,
+                now,
+            );
+            replay.next_log_id = replay.next_log_id.max(log_id + 1);
+        }
+
+        if let Some((offset, reason)) = bad {
+            if Some(&id) != manifest.segments.last() {
+                return Err(KvErr::CorruptedError(format!(
+                    "sealed segment {} is damaged at offset {}: {}",
+                    id, offset, reason
+                )));
+            }
+            let segment = segments.get_mut(&id).expect("segment is open");
+            let discarded_bytes = segment.len() - offset;
+            segment.truncate(offset)?;
+            replay.recovery.truncated.push(Truncation {
+                segment: id,
+                offset,
+                discarded_bytes,
+                reason,
+            });
+        }
+    }
+    Ok(replay)
+}
+
+/// Point the index to the record at `ptr` holding `op`, and release the
+/// records it replaces. A record no key points to, like a removal, is stale
+/// right away. A key that expired by `now` is removed.
+pub fn apply(
+    index: &Index,
+    stale: &mut StaleBytes,
+    expiring: &mut Expiring,
+    ptr: LogPointer,
+    op: LogOperation,
+    now: u64,
+) {
+    let ops = match op {
+        LogOperation::Batch(ops) => ops,
+        op => vec![op],
+    };
+    let mut keys = Vec::with_capacity(ops.len());
+    for op in ops {
+        let old = match op {
+            LogOperation::Set(key, _) => {
+                expiring.set(&key, None);
+                let old = index.insert(key.clone(), ptr);
+                keys.push(key);
+                old
+            }
+            LogOperation::SetExpiring(key, _, at) if at > now => {
+                expiring.set(&key, Some(at));
+                let old = index.insert(key.clone(), ptr);
+                keys.push(key);
+                old
+            }
+            LogOperation::SetExpiring(key, _, _) | LogOperation::Rm(key) => {
+                expiring.set(&key, None);
+                index.remove(&key)
+            }
+            // batches are not nested
+            LogOperation::Batch(_) => None,
+        };
+        match old {
+            // set twice in the same batch
+            Some(old) if old == ptr => {}
+            Some(old) => stale.release(old),
+            None => {}
+        }
+    }
+    keys.sort();
+    keys.dedup();
+    let live = keys
+        .iter()
+        .filter(|key| index.get(key) == Some(ptr))
+        .count();
+    stale.share(ptr, live);
+}
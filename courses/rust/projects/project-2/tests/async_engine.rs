@@ -0,0 +1,77 @@
+use kvs::error::KvErr;
+use kvs::thread_pool::{RayonThreadPool, SharedQueueThreadPool, ThreadPool};
+use kvs::{AsyncEngine, KvStore, Result, SledKvsEngine};
+use tempfile::TempDir;
+use tokio::runtime::Runtime;
+
+#[test]
+fn async_get_set_remove() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engine = AsyncEngine::new(
+        KvStore::open(temp_dir.path())?,
+        SharedQueueThreadPool::new(2)?,
+    );
+    let runtime = Runtime::new()?;
+
+    runtime.block_on(async {
+        engine.set("key1".to_owned(), "value1".to_owned()).await?;
+        assert_eq!(
+            engine.get("key1".to_owned()).await?,
+            Some("value1".to_owned())
+        );
+        engine.remove("key1".to_owned()).await?;
+        assert_eq!(engine.get("key1".to_owned()).await?, None);
+        match engine.remove("key1".to_owned()).await {
+            Err(KvErr::KeyNotExistError) => Ok(()),
+            other => panic!("expected KeyNotExistError, got {:?}", other),
+        }
+    })
+}
+
+#[test]
+fn async_concurrent_set_and_get() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engine = AsyncEngine::new(KvStore::open(temp_dir.path())?, RayonThreadPool::new(8)?);
+    let runtime = Runtime::new()?;
+
+    runtime.block_on(async {
+        let sets: Vec<_> = (0..1000)
+            .map(|i| engine.set(format!("key{}", i), format!("value{}", i)))
+            .collect();
+        for set in sets {
+            set.await?;
+        }
+        let gets: Vec<_> = (0..1000).map(|i| engine.get(format!("key{}", i))).collect();
+        for (i, get) in gets.into_iter().enumerate() {
+            assert_eq!(get.await?, Some(format!("value{}", i)));
+        }
+        Ok::<_, KvErr>(())
+    })?;
+
+    // the writes reached the log
+    drop(engine);
+    let store = KvStore::open(temp_dir.path())?;
+    for i in 0..1000 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
+    }
+    Ok(())
+}
+
+#[test]
+fn async_sled_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engine = AsyncEngine::new(
+        SledKvsEngine::open(temp_dir.path())?,
+        SharedQueueThreadPool::new(2)?,
+    );
+    let runtime = Runtime::new()?;
+
+    runtime.block_on(async {
+        engine.set("key1".to_owned(), "value1".to_owned()).await?;
+        assert_eq!(
+            engine.get("key1".to_owned()).await?,
+            Some("value1".to_owned())
+        );
+        Ok(())
+    })
+}
@@ -0,0 +1,201 @@
+use assert_cmd::prelude::*;
+use kvs::error::KvErr;
+use kvs::{KvStore, OpenOptions, Result, WriteBatch};
+use predicates::ord::eq;
+use predicates::str::PredicateStrExt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+mod common;
+use common::kvs;
+
+fn last_segment(dir: &Path) -> PathBuf {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map(|ext| ext == "log").unwrap_or(false))
+        .max_by_key(|path| {
+            path.file_stem()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .parse::<u64>()
+                .unwrap()
+        })
+        .unwrap()
+}
+
+fn transfer() -> WriteBatch {
+    let mut batch = WriteBatch::new();
+    batch
+        .set("from".to_owned(), "90".to_owned())
+        .set("to".to_owned(), "110".to_owned())
+        .remove("pending".to_owned());
+    batch
+}
+
+fn setup(store: &KvStore) -> Result<()> {
+    store.set("from".to_owned(), "100".to_owned())?;
+    store.set("to".to_owned(), "100".to_owned())?;
+    store.set("pending".to_owned(), "10".to_owned())
+}
+
+#[test]
+fn batch_applies_every_write() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    setup(&store)?;
+    store.write(transfer())?;
+
+    assert_eq!(store.get("from".to_owned())?, Some("90".to_owned()));
+    assert_eq!(store.get("to".to_owned())?, Some("110".to_owned()));
+    assert_eq!(store.get("pending".to_owned())?, None);
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("from".to_owned())?, Some("90".to_owned()));
+    assert_eq!(store.get("to".to_owned())?, Some("110".to_owned()));
+    assert_eq!(store.get("pending".to_owned())?, None);
+    Ok(())
+}
+
+#[test]
+fn later_writes_in_a_batch_win() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let mut batch = WriteBatch::new();
+    batch
+        .set("key1".to_owned(), "value1".to_owned())
+        .set("key1".to_owned(), "value2".to_owned())
+        .set("key2".to_owned(), "value3".to_owned())
+        .remove("key2".to_owned());
+    store.write(batch)?;
+
+    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    Ok(())
+}
+
+#[test]
+fn batch_removing_missing_key_writes_nothing() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("from".to_owned(), "100".to_owned())?;
+
+    match store.write(transfer()) {
+        Err(KvErr::KeyNotExistError) => {}
+        other => panic!("expected KeyNotExistError, got {:?}", other),
+    }
+    assert_eq!(store.get("from".to_owned())?, Some("100".to_owned()));
+    assert_eq!(store.get("to".to_owned())?, None);
+    Ok(())
+}
+
+// A batch cut short by a crash is dropped as a whole on open.
+#[test]
+fn torn_batch_is_not_applied() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    setup(&store)?;
+    store.write(transfer())?;
+    drop(store);
+
+    let segment = last_segment(temp_dir.path());
+    let len = fs::metadata(&segment).unwrap().len();
+    let fd = fs::OpenOptions::new().write(true).open(&segment).unwrap();
+    fd.set_len(len - 3).unwrap();
+    drop(fd);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.recovery_report().truncated.len(), 1);
+    assert_eq!(store.get("from".to_owned())?, Some("100".to_owned()));
+    assert_eq!(store.get("to".to_owned())?, Some("100".to_owned()));
+    assert_eq!(store.get("pending".to_owned())?, Some("10".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn batch_survives_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = OpenOptions::new()
+        .compaction_threshold(u64::MAX)
+        .open(temp_dir.path())?;
+    setup(&store)?;
+    store.write(transfer())?;
+    store.set("to".to_owned(), "120".to_owned())?;
+    store.compact()?;
+
+    assert_eq!(store.get("from".to_owned())?, Some("90".to_owned()));
+    assert_eq!(store.get("to".to_owned())?, Some("120".to_owned()));
+    drop(store);
+
+    // opened from the hint written by the compaction
+    let store = KvStore::open(temp_dir.path())?;
+    assert!(store.recovery_report().used_hint);
+    assert_eq!(store.get("from".to_owned())?, Some("90".to_owned()));
+    assert_eq!(store.get("to".to_owned())?, Some("120".to_owned()));
+    assert_eq!(store.get("pending".to_owned())?, None);
+    Ok(())
+}
+
+// Only the live writes of a batch are copied, so a full replay of the
+// compacted log does not bring back a key removed after the batch.
+#[test]
+fn compaction_drops_dead_batch_writes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = OpenOptions::new()
+        .compaction_threshold(u64::MAX)
+        .open(temp_dir.path())?;
+    setup(&store)?;
+    store.write(transfer())?;
+    store.remove("from".to_owned())?;
+    store.compact()?;
+    drop(store);
+
+    fs::remove_file(temp_dir.path().join("HINT")).unwrap();
+    let store = KvStore::open(temp_dir.path())?;
+    assert!(!store.recovery_report().used_hint);
+    assert_eq!(store.get("from".to_owned())?, None);
+    assert_eq!(store.get("to".to_owned())?, Some("110".to_owned()));
+    assert_eq!(store.get("pending".to_owned())?, None);
+    Ok(())
+}
+
+#[test]
+fn cli_batch() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    setup(&store)?;
+    drop(store);
+
+    kvs(&temp_dir)
+        .args(["batch"])
+        .with_stdin()
+        .buffer("# move 10\nset from 90\nset to 110\n\nrm pending\nset note two words\n")
+        .assert()
+        .success();
+    kvs(&temp_dir)
+        .args(["get", "note"])
+        .assert()
+        .success()
+        .stdout(eq("two words").trim());
+
+    // a malformed line fails the whole batch
+    kvs(&temp_dir)
+        .args(["batch"])
+        .with_stdin()
+        .buffer("set from 0\nrm\n")
+        .assert()
+        .failure();
+    kvs(&temp_dir)
+        .args(["get", "from"])
+        .assert()
+        .success()
+        .stdout(eq("90").trim());
+    Ok(())
+}
@@ -0,0 +1,139 @@
+use assert_cmd::prelude::*;
+use kvs::error::KvErr;
+use kvs::{Codec, KvStore, OpenOptions, Result, WriteBatch};
+use predicates::ord::eq;
+use predicates::str::PredicateStrExt;
+use std::fs;
+use tempfile::TempDir;
+
+mod common;
+use common::kvs;
+
+fn round_trip(codec: Codec) -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = OpenOptions::new().codec(codec).open(temp_dir.path())?;
+    let binary: Vec<u8> = (0..=255).collect();
+    store.set_bytes(vec![0xff, 0x00], binary.clone())?;
+    store.set_bytes(vec![], vec![])?;
+    store.set_bytes(b"quoted".to_vec(), b"\"a\"\n\\".to_vec())?;
+    store.set_bytes(vec![0xc3], b"removed".to_vec())?;
+    store.remove_bytes(vec![0xc3])?;
+    store.compact()?;
+    store.set_bytes(vec![0x80], vec![0x81])?;
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get_bytes(&[0xff, 0x00])?, Some(binary));
+    assert_eq!(store.get_bytes(&[])?, Some(vec![]));
+    assert_eq!(store.get_bytes(b"quoted")?, Some(b"\"a\"\n\\".to_vec()));
+    assert_eq!(store.get_bytes(&[0xc3])?, None);
+    assert_eq!(store.get_bytes(&[0x80])?, Some(vec![0x81]));
+    Ok(())
+}
+
+#[test]
+fn cbor_bytes_round_trip() -> Result<()> {
+    round_trip(Codec::Cbor)
+}
+
+#[test]
+fn bincode_bytes_round_trip() -> Result<()> {
+    round_trip(Codec::Bincode)
+}
+
+#[test]
+fn json_bytes_round_trip() -> Result<()> {
+    round_trip(Codec::Json)
+}
+
+#[test]
+fn strings_are_utf8_bytes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "välue".to_owned())?;
+    assert_eq!(store.get_bytes(b"key1")?, Some("välue".as_bytes().to_vec()));
+
+    store.set_bytes(b"key2".to_vec(), vec![0xff, 0xfe])?;
+    match store.get("key2".to_owned()) {
+        Err(KvErr::Utf8Error(_)) => {}
+        other => panic!("expected Utf8Error, got {:?}", other),
+    }
+    store.remove("key2".to_owned())?;
+    assert_eq!(store.get_bytes(b"key2")?, None);
+    Ok(())
+}
+
+// raw bytes are stored as they are, not as a list of numbers
+#[test]
+fn values_are_stored_raw() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set_bytes(b"key".to_vec(), vec![0xff; 10_000])?;
+    drop(store);
+
+    let len = fs::metadata(temp_dir.path().join("0.log")).unwrap().len();
+    assert!(len < 10_100, "segment is {} bytes", len);
+    Ok(())
+}
+
+#[test]
+fn scan_bytes_in_byte_order() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let mut batch = WriteBatch::new();
+    batch
+        .set_bytes(vec![0x01, 0xff], vec![1])
+        .set_bytes(vec![0x01], vec![2])
+        .set_bytes(vec![0x02], vec![3])
+        .set_bytes(vec![0xff], vec![4]);
+    store.write(batch)?;
+
+    let keys = store
+        .scan_bytes(vec![0x01]..vec![0xff])
+        .map(|pair| pair.map(|(key, _)| key))
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(keys, vec![vec![0x01], vec![0x01, 0xff], vec![0x02]]);
+    let pairs = store
+        .scan_prefix_bytes(&[0x01])
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(
+        pairs,
+        vec![(vec![0x01], vec![2]), (vec![0x01, 0xff], vec![1])]
+    );
+
+    let snapshot = store.snapshot()?;
+    assert_eq!(snapshot.get_bytes(&[0xff])?, Some(vec![4]));
+    assert_eq!(snapshot.scan_bytes(..).count(), 4);
+    Ok(())
+}
+
+#[test]
+fn cli_encodings() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    kvs(&temp_dir)
+        .args(["set", "00ff", "c328", "--encoding", "hex"])
+        .assert()
+        .success();
+    kvs(&temp_dir)
+        .args(["get", "--encoding", "hex", "00ff"])
+        .assert()
+        .success()
+        .stdout(eq("c328").trim());
+    kvs(&temp_dir)
+        .args(["get", "AP8=", "--encoding", "base64"])
+        .assert()
+        .success()
+        .stdout(eq("wyg=").trim());
+    kvs(&temp_dir)
+        .args(["scan", "--encoding", "hex"])
+        .assert()
+        .success()
+        .stdout(eq("00ff\tc328").trim());
+
+    // not UTF-8, so it cannot be printed as text
+    kvs(&temp_dir).args(["scan"]).assert().failure();
+    kvs(&temp_dir)
+        .args(["get", "zz", "--encoding", "hex"])
+        .assert()
+        .failure();
+}
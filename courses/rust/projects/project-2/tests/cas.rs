@@ -0,0 +1,210 @@
+use kvs::{KvStore, KvsClient, KvsEngine, Result, SledKvsEngine};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+mod common;
+use common::start_server;
+
+const THREADS: usize = 8;
+const INCREMENTS: usize = 100;
+
+fn conditional_writes(engine: impl KvsEngine) -> Result<()> {
+    assert!(engine.set_if_absent("key1".to_owned(), "value1".to_owned())?);
+    assert!(!engine.set_if_absent("key1".to_owned(), "value2".to_owned())?);
+    assert_eq!(engine.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    assert!(!engine.compare_and_swap(
+        "key1".to_owned(),
+        "value2".to_owned(),
+        "value3".to_owned()
+    )?);
+    assert!(engine.compare_and_swap(
+        "key1".to_owned(),
+        "value1".to_owned(),
+        "value3".to_owned()
+    )?);
+    assert_eq!(engine.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert!(!engine.compare_and_swap(
+        "key2".to_owned(),
+        "value1".to_owned(),
+        "value2".to_owned()
+    )?);
+    assert_eq!(engine.get("key2".to_owned())?, None);
+
+    assert!(!engine.remove_if_equals("key1".to_owned(), "value1".to_owned())?);
+    assert!(!engine.remove_if_equals("key2".to_owned(), "value1".to_owned())?);
+    assert!(engine.remove_if_equals("key1".to_owned(), "value3".to_owned())?);
+    assert_eq!(engine.get("key1".to_owned())?, None);
+    assert!(engine.set_if_absent("key1".to_owned(), "value4".to_owned())?);
+    Ok(())
+}
+
+#[test]
+fn kvs_conditional_writes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    conditional_writes(KvStore::open(temp_dir.path())?)?;
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value4".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn sled_conditional_writes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    conditional_writes(SledKvsEngine::open(temp_dir.path())?)
+}
+
+#[test]
+fn bytes_conditional_writes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    assert!(store.set_bytes_if_absent(vec![0xff], vec![0x00])?);
+    assert!(store.compare_and_swap_bytes(vec![0xff], &[0x00], vec![0x01])?);
+    assert!(!store.remove_bytes_if_equals(vec![0xff], &[0x00])?);
+    assert!(store.remove_bytes_if_equals(vec![0xff], &[0x01])?);
+    assert_eq!(store.get_bytes(&[0xff])?, None);
+    Ok(())
+}
+
+// an expired key is absent, and a swap clears its time to live like a set
+#[test]
+fn conditional_writes_see_expiration() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let ttl = Duration::from_millis(300);
+    store.set_with_ttl("key1".to_owned(), "value1".to_owned(), ttl)?;
+    store.set_with_ttl("key2".to_owned(), "value1".to_owned(), ttl)?;
+    assert!(store.compare_and_swap("key2".to_owned(), "value1".to_owned(), "value2".to_owned())?);
+
+    thread::sleep(ttl * 2);
+    assert!(store.set_if_absent("key1".to_owned(), "value3".to_owned())?);
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    Ok(())
+}
+
+/// add one to the counter read by `get`, retrying until `swap` succeeds
+fn increment(
+    mut get: impl FnMut() -> Result<String>,
+    mut swap: impl FnMut(String, String) -> Result<bool>,
+) -> Result<()> {
+    loop {
+        let current = get()?;
+        let next = (current.parse::<usize>().unwrap() + 1).to_string();
+        if swap(current, next)? {
+            return Ok(());
+        }
+    }
+}
+
+#[test]
+fn concurrent_increments_are_not_lost() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("counter".to_owned(), "0".to_owned())?;
+
+    let handles: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let store = store.clone();
+            thread::spawn(move || {
+                for _ in 0..INCREMENTS {
+                    increment(
+                        || Ok(store.get("counter".to_owned())?.unwrap()),
+                        |current, next| store.compare_and_swap("counter".to_owned(), current, next),
+                    )
+                    .unwrap();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(
+        store.get("counter".to_owned())?,
+        Some((THREADS * INCREMENTS).to_string())
+    );
+    Ok(())
+}
+
+#[test]
+fn concurrent_set_if_absent_has_one_winner() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let handles: Vec<_> = (0..THREADS)
+        .map(|thread_id| {
+            let store = store.clone();
+            thread::spawn(move || {
+                store
+                    .set_if_absent("lease".to_owned(), thread_id.to_string())
+                    .unwrap()
+            })
+        })
+        .collect();
+    let winners = handles
+        .into_iter()
+        .map(|handle| handle.join().unwrap())
+        .filter(|won| *won)
+        .count();
+    assert_eq!(winners, 1);
+    Ok(())
+}
+
+fn concurrent_clients(engine: &str, addr: &'static str) {
+    let temp_dir = TempDir::new().unwrap();
+    // every client below keeps its connections open, the async server does
+    // not run out of threads for them
+    let _server = start_server(&temp_dir, &["--engine", engine, "--addr", addr, "--async"]);
+    let mut client = KvsClient::connect(addr).unwrap();
+    assert!(client
+        .set_if_absent("counter".to_owned(), "0".to_owned())
+        .unwrap());
+    assert!(!client
+        .set_if_absent("counter".to_owned(), "1".to_owned())
+        .unwrap());
+
+    let handles: Vec<_> = (0..THREADS)
+        .map(|_| {
+            thread::spawn(move || {
+                let mut reader = KvsClient::connect(addr).unwrap();
+                let mut writer = KvsClient::connect(addr).unwrap();
+                for _ in 0..INCREMENTS {
+                    increment(
+                        || Ok(reader.get("counter".to_owned())?.unwrap()),
+                        |current, next| {
+                            writer.compare_and_swap("counter".to_owned(), current, next)
+                        },
+                    )
+                    .unwrap();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let total = (THREADS * INCREMENTS).to_string();
+    assert_eq!(
+        client.get("counter".to_owned()).unwrap(),
+        Some(total.clone())
+    );
+    assert!(!client
+        .remove_if_equals("counter".to_owned(), "0".to_owned())
+        .unwrap());
+    assert!(client
+        .remove_if_equals("counter".to_owned(), total)
+        .unwrap());
+    assert_eq!(client.get("counter".to_owned()).unwrap(), None);
+}
+
+#[test]
+fn concurrent_clients_kvs_engine() {
+    concurrent_clients("kvs", "127.0.0.1:4113");
+}
+
+#[test]
+fn concurrent_clients_sled_engine() {
+    concurrent_clients("sled", "127.0.0.1:4114");
+}
@@ -0,0 +1,269 @@
+use assert_cmd::prelude::*;
+use byteorder::{LittleEndian, WriteBytesExt};
+use kvs::{KvStore, Result};
+use predicates::prelude::*;
+use predicates::str::contains;
+use serde::Serialize;
+use std::fs;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+mod common;
+use common::kvs;
+
+// mirrors of the legacy on-disk types, used to build a single file database
+#[derive(Serialize)]
+struct Metadata {
+    total_log: usize,
+    total_bytes: usize,
+}
+
+#[derive(Serialize)]
+struct LogEntry(usize, LogOperation);
+
+#[derive(Serialize)]
+enum LogOperation {
+    Set(String, String),
+}
+
+// a legacy database whose header claims `total_log` records, followed by
+// `garbage` bytes that are not a record
+fn write_legacy_db(path: &Path, ops: Vec<LogOperation>, total_log: usize, garbage: &[u8]) {
+    let mut logs = Vec::new();
+    for (id, op) in ops.into_iter().enumerate() {
+        let entry = serde_cbor::to_vec(&LogEntry(id, op)).unwrap();
+        logs.write_u16::<LittleEndian>(entry.len() as u16).unwrap();
+        logs.extend_from_slice(&entry);
+    }
+    let metadata = Metadata {
+        total_log,
+        total_bytes: logs.len(),
+    };
+    logs.extend_from_slice(garbage);
+
+    let md = serde_cbor::to_vec(&metadata).unwrap();
+    let mut head = vec![0u8; 1024];
+    (&mut head[0..4])
+        .write_u32::<LittleEndian>(md.len() as u32)
+        .unwrap();
+    head[4..md.len() + 4].clone_from_slice(&md);
+    let fd = fs::File::create(path).unwrap();
+    fd.write_all_at(&head, 0).unwrap();
+    fd.write_all_at(&logs, 1024).unwrap();
+}
+
+fn first_segment(dir: &Path) -> PathBuf {
+    dir.join("0.log")
+}
+
+fn write_keys(dir: &Path) -> Result<()> {
+    let store = KvStore::open(dir)?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    store.set("key1".to_owned(), "value4".to_owned())?;
+    store.remove("key3".to_owned())?;
+    store.set("key3".to_owned(), "value5".to_owned())?;
+    Ok(())
+}
+
+// flip a bit of `value` in the segment, its record fails the checksum
+fn corrupt(segment: &Path, value: &[u8]) {
+    let mut data = fs::read(segment).unwrap();
+    let pos = data
+        .windows(value.len())
+        .position(|w| w == value)
+        .expect("the value is in the segment");
+    data[pos] ^= 0x01;
+    fs::write(segment, data).unwrap();
+}
+
+#[test]
+fn clean_store() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    write_keys(temp_dir.path())?;
+
+    let report = KvStore::check(temp_dir.path())?;
+    assert!(report.is_clean(), "{:?}", report);
+    assert_eq!(report.files, vec!["0.log".to_owned()]);
+    assert_eq!(report.records, 6);
+    assert_eq!(report.live_entries, 3);
+    assert_eq!(report.stale_entries, 3);
+    Ok(())
+}
+
+// every bad record is reported, the records after it are still checked
+#[test]
+fn bad_records_are_reported_without_changing_the_store() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    write_keys(temp_dir.path())?;
+    let segment = first_segment(temp_dir.path());
+    corrupt(&segment, b"value2");
+    corrupt(&segment, b"value4");
+    let data = fs::read(&segment).unwrap();
+
+    let report = KvStore::check(temp_dir.path())?;
+    assert_eq!(report.bad_records.len(), 2);
+    assert!(report.bad_records[0].reason.contains("checksum"));
+    assert!(report.bad_records[0].offset < report.bad_records[1].offset);
+    assert_eq!(report.bad_records[1].file, "0.log");
+    assert_eq!(report.records, 4);
+    assert_eq!(report.live_entries, 2);
+    assert_eq!(fs::read(&segment).unwrap(), data);
+    Ok(())
+}
+
+#[test]
+fn repair_salvages_readable_records() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let out_dir = TempDir::new().expect("unable to create temporary working directory");
+    write_keys(temp_dir.path())?;
+    corrupt(&first_segment(temp_dir.path()), b"value2");
+
+    let report = KvStore::repair(temp_dir.path(), out_dir.path())?;
+    assert_eq!(report.bad_records.len(), 1);
+    let store = KvStore::open(out_dir.path())?;
+    assert!(store.recovery_report().is_clean());
+    assert_eq!(store.get("key1".to_owned())?, Some("value4".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    assert_eq!(store.get("key3".to_owned())?, Some("value5".to_owned()));
+    assert!(KvStore::check(out_dir.path())?.is_clean());
+
+    // the copy is not written over a store
+    assert!(KvStore::repair(temp_dir.path(), out_dir.path()).is_err());
+    Ok(())
+}
+
+// the copy never writes over the records it is made from, or over files
+// already in the target
+#[test]
+fn repair_refuses_the_source_and_used_targets() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    write_keys(temp_dir.path())?;
+    fs::remove_file(temp_dir.path().join("MANIFEST")).unwrap();
+    let segment = first_segment(temp_dir.path());
+    let data = fs::read(&segment).unwrap();
+    assert!(KvStore::repair(temp_dir.path(), temp_dir.path()).is_err());
+    assert_eq!(fs::read(&segment).unwrap(), data);
+
+    for file in ["0.log", "ENGINE"] {
+        let out_dir = TempDir::new().expect("unable to create temporary working directory");
+        fs::write(out_dir.path().join(file), "kept").unwrap();
+        assert!(KvStore::repair(temp_dir.path(), out_dir.path()).is_err());
+        assert_eq!(fs::read(out_dir.path().join(file)).unwrap(), b"kept");
+        assert_eq!(fs::read_dir(out_dir.path()).unwrap().count(), 1);
+    }
+
+    // a missing source leaves nothing behind
+    let out_dir = TempDir::new().expect("unable to create temporary working directory");
+    let missing = temp_dir.path().join("missing");
+    assert!(KvStore::repair(&missing, out_dir.path()).is_err());
+    assert_eq!(fs::read_dir(out_dir.path()).unwrap().count(), 0);
+
+    // other files in the target are left alone
+    fs::write(out_dir.path().join("notes.txt"), "notes").unwrap();
+    let report = KvStore::repair(temp_dir.path(), out_dir.path())?;
+    assert_eq!(report.records, 6);
+    let store = KvStore::open(out_dir.path())?;
+    assert_eq!(store.get("key3".to_owned())?, Some("value5".to_owned()));
+    assert!(out_dir.path().join("notes.txt").is_file());
+    Ok(())
+}
+
+#[test]
+fn metadata_mismatches() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    write_keys(temp_dir.path())?;
+    fs::copy(
+        first_segment(temp_dir.path()),
+        temp_dir.path().join("7.log"),
+    )
+    .unwrap();
+
+    let report = KvStore::check(temp_dir.path())?;
+    assert!(report.bad_records.is_empty());
+    assert_eq!(report.mismatches.len(), 1);
+    assert!(report.mismatches[0].contains("7.log"));
+    assert!(temp_dir.path().join("7.log").exists());
+
+    fs::remove_file(temp_dir.path().join("0.log")).unwrap();
+    let report = KvStore::check(temp_dir.path())?;
+    assert!(report
+        .mismatches
+        .iter()
+        .any(|m| m.contains("0.log") && m.contains("does not exist")));
+    Ok(())
+}
+
+#[test]
+fn legacy_database() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let out_dir = TempDir::new().expect("unable to create temporary working directory");
+    let legacy = temp_dir.path().join("test.db");
+    write_legacy_db(
+        &legacy,
+        vec![
+            LogOperation::Set("key1".to_owned(), "value1".to_owned()),
+            LogOperation::Set("key2".to_owned(), "value2".to_owned()),
+            LogOperation::Set("key1".to_owned(), "value3".to_owned()),
+        ],
+        5,
+        &[4, 0, 0xff, 0xff, 0xff, 0xff],
+    );
+
+    let report = KvStore::check(&legacy)?;
+    assert_eq!(report.files, vec!["test.db".to_owned()]);
+    assert_eq!(report.records, 3);
+    assert_eq!(report.live_entries, 2);
+    assert_eq!(report.stale_entries, 1);
+    assert_eq!(report.bad_records.len(), 1);
+    assert!(report.mismatches.iter().any(|m| m.contains("total_log")));
+    assert!(report.mismatches.iter().any(|m| m.contains("total_bytes")));
+    // the directory is checked the same way, and nothing is migrated
+    assert_eq!(KvStore::check(temp_dir.path())?.records, 3);
+    assert!(legacy.exists());
+
+    KvStore::repair(&legacy, out_dir.path())?;
+    let store = KvStore::open(out_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn no_store() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    assert!(KvStore::check(temp_dir.path()).is_err());
+}
+
+#[test]
+fn cli_check_and_repair() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let out_dir = TempDir::new().expect("unable to create temporary working directory");
+    write_keys(temp_dir.path())?;
+    kvs(&temp_dir)
+        .args(["check"])
+        .assert()
+        .success()
+        .stdout(contains("live entries: 3").and(contains("ok")));
+
+    corrupt(&first_segment(temp_dir.path()), b"value2");
+    kvs(&temp_dir)
+        .args(["check"])
+        .assert()
+        .failure()
+        .stdout(contains("bad record: 0.log offset"));
+    kvs(&temp_dir)
+        .arg("check")
+        .arg(temp_dir.path())
+        .arg("--repair")
+        .arg(out_dir.path())
+        .assert()
+        .success()
+        .stdout(contains("salvaged 5 records"));
+
+    let store = KvStore::open(out_dir.path())?;
+    assert_eq!(store.get("key3".to_owned())?, Some("value5".to_owned()));
+    Ok(())
+}
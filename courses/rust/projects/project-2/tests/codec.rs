@@ -0,0 +1,65 @@
+use kvs::{Codec, KvStore, OpenOptions, Result};
+use std::fs;
+use tempfile::TempDir;
+
+fn round_trip(codec: Codec) -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = OpenOptions::new().codec(codec).open(temp_dir.path())?;
+    assert_eq!(store.codec(), codec);
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.set("key1".to_owned(), "value3".to_owned())?;
+    store.remove("key2".to_owned())?;
+    store.compact()?;
+    store.set("key4".to_owned(), "value4".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+
+    // the codec is recorded in the database, not taken from the options
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.codec(), codec);
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    assert_eq!(store.get("key4".to_owned())?, Some("value4".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn cbor_round_trip() -> Result<()> {
+    round_trip(Codec::Cbor)
+}
+
+#[test]
+fn bincode_round_trip() -> Result<()> {
+    round_trip(Codec::Bincode)
+}
+
+#[test]
+fn json_round_trip() -> Result<()> {
+    round_trip(Codec::Json)
+}
+
+#[test]
+fn default_codec_is_cbor() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.codec(), Codec::Cbor);
+    Ok(())
+}
+
+// JSON records are readable when looking at the segment file.
+#[test]
+fn json_records_are_human_readable() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = OpenOptions::new()
+        .codec(Codec::Json)
+        .open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+
+    let segment = fs::read(temp_dir.path().join("0.log")).unwrap();
+    let text = String::from_utf8_lossy(&segment);
+    assert!(text.contains(r#"[0,{"Set":["key1","value1"]}]"#));
+    Ok(())
+}
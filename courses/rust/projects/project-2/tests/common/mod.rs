@@ -0,0 +1,83 @@
+// helpers shared by the test suites, each suite uses only some of them
+#![allow(dead_code)]
+
+use assert_cmd::prelude::*;
+use kvs::{KvStore, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+use walkdir::WalkDir;
+
+/// a running kvs-server, killed when dropped
+pub struct Server(pub Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.0.kill().expect("server exited before killed");
+        self.0.wait().unwrap();
+    }
+}
+
+/// start kvs-server in `dir` and wait until the address given with `--addr`
+/// accepts connections
+pub fn start_server(dir: &TempDir, args: &[&str]) -> Server {
+    let addr = args[args.iter().position(|arg| *arg == "--addr").unwrap() + 1];
+    let child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(args)
+        .current_dir(dir)
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let server = Server(child);
+    for _ in 0..50 {
+        if TcpStream::connect(addr).is_ok() {
+            return server;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    panic!("kvs-server did not start");
+}
+
+pub fn dir_size(dir: &Path) -> u64 {
+    WalkDir::new(dir)
+        .into_iter()
+        .map(|entry| entry.unwrap().metadata().unwrap().len())
+        .sum()
+}
+
+pub fn segment_files(dir: &Path) -> HashSet<PathBuf> {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map(|ext| ext == "log").unwrap_or(false))
+        .collect()
+}
+
+/// write every one of 100 keys 20 times, then remove the first 10
+pub fn fill(store: &KvStore) -> Result<()> {
+    for iter in 0..20 {
+        for key_id in 0..100 {
+            store.set(format!("key{}", key_id), format!("{}", iter))?;
+        }
+    }
+    for key_id in 0..10 {
+        store.remove(format!("key{}", key_id))?;
+    }
+    Ok(())
+}
+
+/// kvs run in `dir`, which is its home directory as well, with KVS_DB unset so
+/// that neither picks the store of the user running the tests
+pub fn kvs(dir: impl AsRef<Path>) -> Command {
+    let mut cmd = Command::cargo_bin("kvs").unwrap();
+    cmd.current_dir(&dir)
+        .env("HOME", dir.as_ref())
+        .env_remove("KVS_DB");
+    cmd
+}
@@ -0,0 +1,168 @@
+use kvs::{KvStore, OpenOptions, Result};
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+mod common;
+use common::{dir_size, fill, segment_files};
+
+fn copy_dir(from: &Path, to: &Path) {
+    for entry in fs::read_dir(from).unwrap() {
+        let path = entry.unwrap().path();
+        fs::copy(&path, to.join(path.file_name().unwrap())).unwrap();
+    }
+}
+
+fn check(store: &KvStore) -> Result<()> {
+    for key_id in 0..10 {
+        assert_eq!(store.get(format!("key{}", key_id))?, None);
+    }
+    for key_id in 10..100 {
+        assert_eq!(store.get(format!("key{}", key_id))?, Some("19".to_owned()));
+    }
+    Ok(())
+}
+
+/// Returns the directory before and after a compaction of the same data.
+fn compacted_pair() -> Result<(TempDir, TempDir)> {
+    let before = TempDir::new().expect("unable to create temporary working directory");
+    let after = TempDir::new().expect("unable to create temporary working directory");
+
+    let store = OpenOptions::new()
+        .compaction_threshold(u64::MAX)
+        .open(after.path())?;
+    fill(&store)?;
+    copy_dir(after.path(), before.path());
+    store.compact()?;
+    drop(store);
+
+    Ok((before, after))
+}
+
+// Writes below the threshold never trigger a compaction.
+#[test]
+fn no_compaction_below_threshold() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = OpenOptions::new()
+        .compaction_threshold(u64::MAX)
+        .open(temp_dir.path())?;
+
+    let mut current_size = dir_size(temp_dir.path());
+    for iter in 0..20 {
+        store.set("key".to_owned(), format!("{}", iter))?;
+        let new_size = dir_size(temp_dir.path());
+        assert!(new_size > current_size);
+        current_size = new_size;
+    }
+    Ok(())
+}
+
+// The background compaction keeps the log bounded while writes and reads continue.
+#[test]
+fn background_compaction_bounds_log_size() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = OpenOptions::new()
+        .compaction_threshold(64 * 1024)
+        .open(temp_dir.path())?;
+
+    let value = "x".repeat(1000);
+    for iter in 0..2000 {
+        let key = format!("key{}", iter % 10);
+        store.set(key.clone(), format!("{}{}", value, iter))?;
+        assert_eq!(store.get(key)?, Some(format!("{}{}", value, iter)));
+    }
+    assert!(dir_size(temp_dir.path()) < 2 * 1024 * 1024);
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    for key_id in 0..10 {
+        assert_eq!(
+            store.get(format!("key{}", key_id))?,
+            Some(format!("{}{}", value, 1990 + key_id))
+        );
+    }
+    Ok(())
+}
+
+// A failed background compaction does not fail the writes that started it,
+// its error is returned by the next explicit compaction.
+#[test]
+fn failed_background_compaction_does_not_fail_writes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = OpenOptions::new()
+        .compaction_threshold(1)
+        .open(temp_dir.path())?;
+    store.set("key0".to_owned(), "value0".to_owned())?;
+    store.compact()?;
+    // every later compaction fails to open the segment key0 was copied to
+    let compacted = segment_files(temp_dir.path()).into_iter().max().unwrap();
+    fs::remove_file(compacted).unwrap();
+
+    for iter in 0..50 {
+        store.set("key1".to_owned(), format!("{}", iter))?;
+        std::thread::sleep(std::time::Duration::from_millis(5));
+    }
+    assert!(store.compact().is_err());
+    store.set("key1".to_owned(), "last".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("last".to_owned()));
+    Ok(())
+}
+
+// Crash after the new generation was written but before the manifest swap:
+// the new segments are not referenced yet and the old generation is used.
+#[test]
+fn crash_before_manifest_swap() -> Result<()> {
+    let (before, after) = compacted_pair()?;
+    let old = segment_files(before.path());
+    for path in segment_files(after.path()) {
+        let name = path.file_name().unwrap();
+        if !old.contains(&before.path().join(name)) {
+            fs::copy(&path, before.path().join(name)).unwrap();
+        }
+    }
+    fs::write(before.path().join("MANIFEST.tmp"), b"half written").unwrap();
+
+    let store = KvStore::open(before.path())?;
+    check(&store)?;
+    drop(store);
+    assert!(!before.path().join("MANIFEST.tmp").exists());
+    Ok(())
+}
+
+// Crash while the new generation was being written: a half written segment
+// that is not in the manifest must not be read.
+#[test]
+fn crash_while_writing_new_generation() -> Result<()> {
+    let (before, after) = compacted_pair()?;
+    let old = segment_files(before.path());
+    for path in segment_files(after.path()) {
+        let target = before.path().join(path.file_name().unwrap());
+        if !old.contains(&target) {
+            let data = fs::read(&path).unwrap();
+            fs::write(&target, &data[..data.len() / 2]).unwrap();
+        }
+    }
+
+    let store = KvStore::open(before.path())?;
+    check(&store)?;
+    Ok(())
+}
+
+// Crash after the manifest swap but before the old segments were removed.
+#[test]
+fn crash_before_old_generation_removed() -> Result<()> {
+    let (before, after) = compacted_pair()?;
+    let new = segment_files(after.path());
+    for path in segment_files(before.path()) {
+        let target = after.path().join(path.file_name().unwrap());
+        if !new.contains(&target) {
+            fs::copy(&path, &target).unwrap();
+        }
+    }
+
+    let store = KvStore::open(after.path())?;
+    check(&store)?;
+    drop(store);
+    assert_eq!(segment_files(after.path()), new);
+    Ok(())
+}
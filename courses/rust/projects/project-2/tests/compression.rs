@@ -0,0 +1,130 @@
+use assert_cmd::prelude::*;
+use kvs::{Compression, KvStore, OpenOptions, Result};
+use predicates::prelude::*;
+use predicates::str::contains;
+use tempfile::TempDir;
+
+mod common;
+use common::kvs;
+
+// a JSON document that compresses well
+fn document(id: usize) -> String {
+    let items: Vec<String> = (0..50)
+        .map(|i| format!(r#"{{"id":{},"name":"item {}","tags":["a","b"]}}"#, i, i))
+        .collect();
+    format!(r#"{{"id":{},"items":[{}]}}"#, id, items.join(","))
+}
+
+#[test]
+fn compressed_values_round_trip() -> Result<()> {
+    for compression in [Compression::Lz4, Compression::Zstd] {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let store = OpenOptions::new()
+            .compression(compression)
+            .open(temp_dir.path())?;
+        for id in 0..20 {
+            store.set(format!("key{}", id), document(id))?;
+        }
+        assert_eq!(store.get("key3".to_owned())?, Some(document(3)));
+
+        let stats = store.compression_stats()?;
+        assert_eq!(stats.compression, compression);
+        assert_eq!(stats.records, 20);
+        assert_eq!(stats.compressed_records, 20);
+        assert!(stats.ratio() > 2.0, "{:?}", stats);
+
+        // the compression is kept in the manifest
+        drop(store);
+        let store = KvStore::open(temp_dir.path())?;
+        assert_eq!(store.get("key7".to_owned())?, Some(document(7)));
+        store.set("key20".to_owned(), document(20))?;
+        assert_eq!(store.compression_stats()?.compressed_records, 21);
+    }
+    Ok(())
+}
+
+// a record that does not get smaller is stored as it is
+#[test]
+fn small_values_are_not_compressed() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = OpenOptions::new()
+        .compression(Compression::Lz4)
+        .open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    let stats = store.compression_stats()?;
+    assert_eq!(stats.records, 1);
+    assert_eq!(stats.compressed_records, 0);
+    assert_eq!(stats.ratio(), 1.0);
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
+
+// records written without compression and with it share the log, and a
+// compaction rewrites the old ones with the compression of the store
+#[test]
+fn compaction_recompresses_old_records() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    for id in 0..10 {
+        store.set(format!("key{}", id), document(id))?;
+    }
+    drop(store);
+
+    let store = OpenOptions::new()
+        .compression(Compression::Zstd)
+        .open(temp_dir.path())?;
+    for id in 10..20 {
+        store.set(format!("key{}", id), document(id))?;
+    }
+    let before = store.compression_stats()?;
+    assert_eq!(before.records, 20);
+    assert_eq!(before.compressed_records, 10);
+    for id in 0..20 {
+        assert_eq!(store.get(format!("key{}", id))?, Some(document(id)));
+    }
+
+    store.compact()?;
+    let after = store.compression_stats()?;
+    assert_eq!(after.records, 20);
+    assert_eq!(after.compressed_records, 20);
+    assert!(after.stored_bytes < before.stored_bytes);
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    for id in 0..20 {
+        assert_eq!(store.get(format!("key{}", id))?, Some(document(id)));
+    }
+    assert!(KvStore::check(temp_dir.path())?.is_clean());
+    Ok(())
+}
+
+#[test]
+fn cli_compact_with_compression() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    for id in 0..5 {
+        kvs(&temp_dir)
+            .args(["set", &format!("key{}", id), &document(id)])
+            .assert()
+            .success();
+    }
+    kvs(&temp_dir)
+        .args(["stats"])
+        .assert()
+        .success()
+        .stdout(contains("compression: none").and(contains("compression ratio: 1.00")));
+
+    kvs(&temp_dir)
+        .args(["compact", "--compression", "lz4"])
+        .assert()
+        .success();
+    kvs(&temp_dir)
+        .args(["stats"])
+        .assert()
+        .success()
+        .stdout(contains("compression: lz4").and(contains("(5 of 5 records compressed)")));
+    kvs(&temp_dir)
+        .args(["get", "key2"])
+        .assert()
+        .success()
+        .stdout(format!("{}\n", document(2)));
+}
@@ -0,0 +1,107 @@
+use kvs::{KvStore, OpenOptions, Result};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use tempfile::TempDir;
+
+#[test]
+fn concurrent_set() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let barrier = Arc::new(Barrier::new(1001));
+    for i in 0..1000 {
+        let store = store.clone();
+        let barrier = barrier.clone();
+        thread::spawn(move || {
+            store
+                .set(format!("key{}", i), format!("value{}", i))
+                .unwrap();
+            barrier.wait();
+        });
+    }
+    barrier.wait();
+
+    for i in 0..1000 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
+    }
+
+    // Open from disk again and check persistent data
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    for i in 0..1000 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
+    }
+    Ok(())
+}
+
+#[test]
+fn concurrent_get() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    for i in 0..100 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+
+    let mut handles = Vec::new();
+    for thread_id in 0..100 {
+        let store = store.clone();
+        handles.push(thread::spawn(move || {
+            for i in 0..100 {
+                let key_id = (i + thread_id) % 100;
+                assert_eq!(
+                    store.get(format!("key{}", key_id)).unwrap(),
+                    Some(format!("value{}", key_id))
+                );
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    Ok(())
+}
+
+// Readers keep seeing every key while a writer overwrites them and the
+// overwrites trigger compactions that remove the segments being read.
+#[test]
+fn get_during_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = OpenOptions::new()
+        .compaction_threshold(64 * 1024)
+        .open(temp_dir.path())?;
+    for key_id in 0..200 {
+        store.set(format!("key{}", key_id), "0".to_owned())?;
+    }
+
+    let readers: Vec<_> = (0..4)
+        .map(|_| {
+            let store = store.clone();
+            thread::spawn(move || {
+                for i in 0..5000 {
+                    let value = store.get(format!("key{}", i % 200)).unwrap();
+                    assert!(value.is_some());
+                }
+            })
+        })
+        .collect();
+
+    let writer = store.clone();
+    let writer = thread::spawn(move || {
+        for iter in 1..50 {
+            for key_id in 0..200 {
+                writer
+                    .set(format!("key{}", key_id), iter.to_string())
+                    .unwrap();
+            }
+        }
+    });
+
+    for reader in readers {
+        reader.join().unwrap();
+    }
+    writer.join().unwrap();
+    store.compact()?;
+    for key_id in 0..200 {
+        assert_eq!(store.get(format!("key{}", key_id))?, Some("49".to_owned()));
+    }
+    Ok(())
+}
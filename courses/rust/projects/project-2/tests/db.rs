@@ -0,0 +1,272 @@
+use assert_cmd::prelude::*;
+use predicates::ord::eq;
+use predicates::prelude::*;
+use predicates::str::{contains, PredicateStrExt};
+use std::process::Command;
+use tempfile::TempDir;
+
+// kvs run in `cwd` with `home` as its home directory and KVS_DB unset
+fn kvs(cwd: &TempDir, home: &TempDir) -> Command {
+    let mut cmd = Command::cargo_bin("kvs").unwrap();
+    cmd.current_dir(cwd)
+        .env("HOME", home.path())
+        .env_remove("KVS_DB");
+    cmd
+}
+
+#[test]
+fn db_flag_and_env() {
+    let cwd = TempDir::new().expect("unable to create temporary working directory");
+    let home = TempDir::new().unwrap();
+    let store = TempDir::new().unwrap();
+    kvs(&cwd, &home)
+        .args([
+            "--db",
+            store.path().to_str().unwrap(),
+            "set",
+            "key1",
+            "value1",
+        ])
+        .assert()
+        .success();
+    kvs(&cwd, &home)
+        .args(["get", "key1", "--db", store.path().to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(eq("value1").trim());
+    kvs(&cwd, &home)
+        .args(["get", "key1"])
+        .env("KVS_DB", store.path())
+        .assert()
+        .success()
+        .stdout(eq("value1").trim());
+    // the current directory is a store of its own
+    kvs(&cwd, &home)
+        .args(["get", "key1"])
+        .assert()
+        .success()
+        .stdout(eq("Key not found").trim());
+}
+
+#[test]
+fn open_chooses_the_store() {
+    let cwd = TempDir::new().expect("unable to create temporary working directory");
+    let home = TempDir::new().unwrap();
+    let store = TempDir::new().unwrap();
+    let other = TempDir::new().unwrap();
+    let path = store.path().canonicalize().unwrap();
+
+    kvs(&cwd, &home)
+        .args(["open", "--create", store.path().to_str().unwrap()])
+        .assert()
+        .success();
+    kvs(&cwd, &home)
+        .arg("open")
+        .assert()
+        .success()
+        .stdout(eq(path.to_str().unwrap()).trim());
+    kvs(&cwd, &home)
+        .args(["set", "key1", "value1"])
+        .assert()
+        .success();
+    kvs(&cwd, &home).arg("check").assert().success();
+    kvs(&cwd, &home)
+        .args(["get", "key1", "--db", store.path().to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(eq("value1").trim());
+
+    // --db and KVS_DB come before the chosen store
+    kvs(&cwd, &home)
+        .args(["get", "key1"])
+        .env("KVS_DB", other.path())
+        .assert()
+        .success()
+        .stdout(eq("Key not found").trim());
+    kvs(&cwd, &home)
+        .arg("open")
+        .env("KVS_DB", other.path())
+        .assert()
+        .success()
+        .stdout(eq(other.path().to_str().unwrap()).trim());
+}
+
+// a mistyped path is not made into a new store
+#[test]
+fn open_requires_a_store() {
+    let cwd = TempDir::new().expect("unable to create temporary working directory");
+    let home = TempDir::new().unwrap();
+    let parent = TempDir::new().unwrap();
+    let store = parent.path().join("store");
+    let typo = parent.path().join("stroe");
+
+    kvs(&cwd, &home)
+        .args(["open", store.to_str().unwrap()])
+        .assert()
+        .failure()
+        .stderr(contains("--create"));
+    assert!(!store.exists());
+    assert!(!home.path().join(".kvs_db").exists());
+
+    kvs(&cwd, &home)
+        .args(["open", "--create", store.to_str().unwrap()])
+        .assert()
+        .success();
+    kvs(&cwd, &home)
+        .args(["open", typo.to_str().unwrap()])
+        .assert()
+        .failure();
+    assert!(!typo.exists());
+    // an existing store needs no --create
+    kvs(&cwd, &home)
+        .args(["open", store.to_str().unwrap()])
+        .assert()
+        .success();
+    kvs(&cwd, &home)
+        .arg("open")
+        .assert()
+        .success()
+        .stdout(eq(store.canonicalize().unwrap().to_str().unwrap()).trim());
+}
+
+// opening a legacy database migrates it, the directory it was in is the store
+#[test]
+fn open_legacy_database() {
+    let cwd = TempDir::new().expect("unable to create temporary working directory");
+    let home = TempDir::new().unwrap();
+    let store = TempDir::new().unwrap();
+    let legacy = store.path().join("test.db");
+    std::fs::write(&legacy, "").unwrap();
+
+    kvs(&cwd, &home)
+        .args(["open", legacy.to_str().unwrap()])
+        .assert()
+        .success();
+    assert!(!legacy.exists());
+    kvs(&cwd, &home)
+        .arg("open")
+        .assert()
+        .success()
+        .stdout(eq(store.path().canonicalize().unwrap().to_str().unwrap()).trim());
+    kvs(&cwd, &home)
+        .args(["set", "key1", "value1"])
+        .assert()
+        .success();
+    kvs(&cwd, &home)
+        .args(["get", "key1", "--db", store.path().to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(eq("value1").trim());
+
+    // a legacy database in the current directory, given by its name only
+    std::fs::write(cwd.path().join("test.db"), "").unwrap();
+    kvs(&cwd, &home)
+        .args(["open", "test.db"])
+        .assert()
+        .success();
+    kvs(&cwd, &home)
+        .arg("open")
+        .assert()
+        .success()
+        .stdout(eq(cwd.path().canonicalize().unwrap().to_str().unwrap()).trim());
+}
+
+#[test]
+fn open_refuses_other_engine() {
+    let cwd = TempDir::new().expect("unable to create temporary working directory");
+    let home = TempDir::new().unwrap();
+    let store = TempDir::new().unwrap();
+    std::fs::write(store.path().join("ENGINE"), "sled").unwrap();
+
+    kvs(&cwd, &home)
+        .args(["open", store.path().to_str().unwrap()])
+        .assert()
+        .failure()
+        .stdout(contains("sled"));
+    assert!(!home.path().join(".kvs_db").exists());
+}
+
+#[test]
+fn shell_refuses_db() {
+    let cwd = TempDir::new().expect("unable to create temporary working directory");
+    let home = TempDir::new().unwrap();
+    let store = TempDir::new().unwrap();
+    kvs(&cwd, &home)
+        .args(["--db", store.path().to_str().unwrap(), "exec", "-f", "-"])
+        .with_stdin()
+        .buffer(format!(
+            "set key1 value1\nget key1 --db {}\nopen\n",
+            cwd.path().display()
+        ))
+        .assert()
+        .failure()
+        .stderr(contains("line 2: --db only applies").and(contains("line 3: open cannot run")));
+    kvs(&cwd, &home)
+        .args(["get", "key1"])
+        .env("KVS_DB", store.path())
+        .assert()
+        .success()
+        .stdout(eq("value1").trim());
+}
+
+// commands that do not write leave a directory without a store as it is
+#[test]
+fn read_commands_do_not_set_up_a_store() {
+    let cwd = TempDir::new().expect("unable to create temporary working directory");
+    let home = TempDir::new().unwrap();
+    std::fs::write(cwd.path().join("7.log"), "application log").unwrap();
+    std::fs::write(cwd.path().join("notes.txt"), "notes").unwrap();
+    for args in [&["get", "foo"][..], &["rm", "foo"], &["scan"], &["stats"]] {
+        kvs(&cwd, &home)
+            .args(args)
+            .assert()
+            .failure()
+            .stdout(contains("holds no kvs store"));
+    }
+    assert_eq!(std::fs::read_dir(cwd.path()).unwrap().count(), 2);
+
+    // an empty directory reads as an empty store
+    let empty = TempDir::new().unwrap();
+    kvs(&empty, &home)
+        .args(["get", "foo"])
+        .assert()
+        .success()
+        .stdout(eq("Key not found").trim());
+    kvs(&empty, &home)
+        .args(["rm", "foo"])
+        .assert()
+        .failure()
+        .stdout(eq("Key not found").trim());
+    assert_eq!(std::fs::read_dir(empty.path()).unwrap().count(), 0);
+}
+
+// a store that cannot answer is an error, not a missing key
+#[test]
+fn get_reports_store_errors() {
+    let cwd = TempDir::new().expect("unable to create temporary working directory");
+    let home = TempDir::new().unwrap();
+    kvs(&cwd, &home)
+        .args(["set", "key1", "value1"])
+        .assert()
+        .success();
+    // with the hint written by the compaction the open does not read the record
+    kvs(&cwd, &home).arg("compact").assert().success();
+    let segment = std::fs::read_dir(cwd.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| {
+            path.extension().is_some_and(|ext| ext == "log")
+                && std::fs::read(path).unwrap().ends_with(b"value1")
+        })
+        .unwrap();
+    let mut bytes = std::fs::read(&segment).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 1;
+    std::fs::write(&segment, bytes).unwrap();
+
+    kvs(&cwd, &home)
+        .args(["get", "key1"])
+        .assert()
+        .failure()
+        .stdout(contains("CorruptedError"));
+}
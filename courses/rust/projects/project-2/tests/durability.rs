@@ -0,0 +1,227 @@
+use kvs::{Durability, KvStore, OpenOptions, Result, WriteBatch};
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use tempfile::TempDir;
+
+const MODES: [Durability; 3] = [Durability::Always, Durability::Group, Durability::Buffered];
+
+fn open(dir: &Path, durability: Durability) -> Result<KvStore> {
+    OpenOptions::new()
+        .compaction_threshold(u64::MAX)
+        .durability(durability)
+        .open(dir)
+}
+
+// A store that is leaked is never dropped, like a process that crashed.
+#[test]
+fn acknowledged_writes_survive_a_crash() -> Result<()> {
+    for mode in MODES.iter().copied() {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let store = open(temp_dir.path(), mode)?;
+        for i in 0..100 {
+            store.set(format!("key{}", i), format!("value{}", i))?;
+        }
+        store.remove("key0".to_owned())?;
+        let mut batch = WriteBatch::new();
+        batch
+            .set("key1".to_owned(), "batch".to_owned())
+            .remove("key2".to_owned());
+        store.write(batch)?;
+        std::mem::forget(store);
+
+        let store = open(temp_dir.path(), mode)?;
+        assert!(store.recovery_report().is_clean(), "{:?}", mode);
+        assert_eq!(store.get("key0".to_owned())?, None);
+        assert_eq!(store.get("key1".to_owned())?, Some("batch".to_owned()));
+        assert_eq!(store.get("key2".to_owned())?, None);
+        for i in 3..100 {
+            assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn group_commit_with_concurrent_writers() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = open(temp_dir.path(), Durability::Group)?;
+    let writers: Vec<_> = (0..8)
+        .map(|thread_id| {
+            let store = store.clone();
+            thread::spawn(move || {
+                for i in 0..200 {
+                    store
+                        .set(format!("key{}-{}", thread_id, i), i.to_string())
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    std::mem::forget(store);
+
+    let store = open(temp_dir.path(), Durability::Group)?;
+    for thread_id in 0..8 {
+        for i in 0..200 {
+            assert_eq!(
+                store.get(format!("key{}-{}", thread_id, i))?,
+                Some(i.to_string())
+            );
+        }
+    }
+    Ok(())
+}
+
+// Copy the files of the store while writers are appending to it, the copy is
+// what a crash at that moment leaves behind. Every write acknowledged before
+// the copy started is in it, a write caught halfway is cut off on open.
+#[test]
+fn crash_during_writes() -> Result<()> {
+    for mode in MODES.iter().copied() {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let crash_dir = TempDir::new().expect("unable to create temporary working directory");
+        let store = open(temp_dir.path(), mode)?;
+        let acked: Arc<Vec<AtomicUsize>> = Arc::new((0..4).map(|_| AtomicUsize::new(0)).collect());
+
+        let writers: Vec<_> = (0..4)
+            .map(|thread_id| {
+                let store = store.clone();
+                let acked = acked.clone();
+                thread::spawn(move || {
+                    for i in 0..500 {
+                        store
+                            .set(format!("key{}-{}", thread_id, i), i.to_string())
+                            .unwrap();
+                        acked[thread_id].store(i + 1, Ordering::SeqCst);
+                    }
+                })
+            })
+            .collect();
+
+        while acked
+            .iter()
+            .map(|n| n.load(Ordering::SeqCst))
+            .sum::<usize>()
+            < 100
+        {
+            thread::yield_now();
+        }
+        let before: Vec<usize> = acked.iter().map(|n| n.load(Ordering::SeqCst)).collect();
+        for entry in fs::read_dir(temp_dir.path())? {
+            let path = entry?.path();
+            fs::copy(&path, crash_dir.path().join(path.file_name().unwrap()))?;
+        }
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let crashed = open(crash_dir.path(), mode)?;
+        assert!(crashed.recovery_report().truncated.len() <= 1, "{:?}", mode);
+        for (thread_id, count) in before.into_iter().enumerate() {
+            for i in 0..count {
+                assert_eq!(
+                    crashed.get(format!("key{}-{}", thread_id, i))?,
+                    Some(i.to_string()),
+                    "{:?}",
+                    mode
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn save_flushes_buffered_writes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = open(temp_dir.path(), Durability::Buffered)?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.save()?;
+    std::mem::forget(store);
+
+    let store = open(temp_dir.path(), Durability::Buffered)?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
+
+// the crash tests above keep the page cache, so they pass without any fsync:
+// the number of fsyncs shows what each mode actually does
+
+fn log_syncs(store: &KvStore) -> Result<u64> {
+    Ok(store.stats()?.log_syncs)
+}
+
+#[test]
+fn always_syncs_every_write() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = open(temp_dir.path(), Durability::Always)?;
+    let before = log_syncs(&store)?;
+    for i in 0..50 {
+        store.set(format!("key{}", i), i.to_string())?;
+    }
+    store.remove("key0".to_owned())?;
+    let mut batch = WriteBatch::new();
+    batch.set("key1".to_owned(), "batch".to_owned());
+    store.write(batch)?;
+    assert_eq!(log_syncs(&store)? - before, 52);
+    Ok(())
+}
+
+// a write that does not overlap with another one waits for its own fsync
+#[test]
+fn group_syncs_every_lone_write() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = open(temp_dir.path(), Durability::Group)?;
+    let before = log_syncs(&store)?;
+    for i in 0..50 {
+        store.set(format!("key{}", i), i.to_string())?;
+    }
+    assert_eq!(log_syncs(&store)? - before, 50);
+    Ok(())
+}
+
+// concurrent writers wait for the same fsync instead of one each
+#[test]
+fn group_commit_shares_syncs() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = open(temp_dir.path(), Durability::Group)?;
+    let before = log_syncs(&store)?;
+    let writers: Vec<_> = (0..8)
+        .map(|thread_id| {
+            let store = store.clone();
+            thread::spawn(move || {
+                for i in 0..200 {
+                    store
+                        .set(format!("key{}-{}", thread_id, i), i.to_string())
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    let syncs = log_syncs(&store)? - before;
+    assert!(syncs > 0);
+    assert!(syncs < 8 * 200, "{} fsyncs for {} writes", syncs, 8 * 200);
+    Ok(())
+}
+
+#[test]
+fn buffered_syncs_only_on_save() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = open(temp_dir.path(), Durability::Buffered)?;
+    let before = log_syncs(&store)?;
+    for i in 0..50 {
+        store.set(format!("key{}", i), i.to_string())?;
+    }
+    assert_eq!(log_syncs(&store)?, before);
+    store.save()?;
+    assert_eq!(log_syncs(&store)? - before, 1);
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use kvs::error::KvErr;
+use kvs::{KvStore, KvsEngine, Result, SledKvsEngine};
+use tempfile::TempDir;
+
+fn exercise(engine: &impl KvsEngine) -> Result<()> {
+    engine.set("key1".to_owned(), "value1".to_owned())?;
+    engine.set("key2".to_owned(), "value2".to_owned())?;
+    engine.set("key1".to_owned(), "value3".to_owned())?;
+    assert_eq!(engine.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(engine.get("key3".to_owned())?, None);
+
+    engine.remove("key2".to_owned())?;
+    assert_eq!(engine.get("key2".to_owned())?, None);
+    match engine.remove("key2".to_owned()) {
+        Err(KvErr::KeyNotExistError) => {}
+        other => panic!("expected KeyNotExistError, got {:?}", other),
+    }
+    Ok(())
+}
+
+#[test]
+fn kvs_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    exercise(&KvStore::open(temp_dir.path())?)?;
+
+    let engine = KvStore::open(temp_dir.path())?;
+    assert_eq!(engine.get("key1".to_owned())?, Some("value3".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn sled_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    exercise(&SledKvsEngine::open(temp_dir.path())?)?;
+
+    let engine = SledKvsEngine::open(temp_dir.path())?;
+    assert_eq!(engine.get("key1".to_owned())?, Some("value3".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn open_kvs_directory_with_sled() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    drop(KvStore::open(temp_dir.path())?);
+
+    match SledKvsEngine::open(temp_dir.path()) {
+        Err(KvErr::WrongEngineError(_)) => Ok(()),
+        other => panic!("expected WrongEngineError, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn open_sled_directory_with_kvs() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    drop(SledKvsEngine::open(temp_dir.path())?);
+
+    match KvStore::open(temp_dir.path()) {
+        Err(KvErr::WrongEngineError(_)) => Ok(()),
+        other => panic!("expected WrongEngineError, got {:?}", other.map(|_| ())),
+    }
+}
+
+// a sled directory from before the engine file was written
+#[test]
+fn open_unmarked_sled_directory_with_kvs() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    drop(SledKvsEngine::open(temp_dir.path())?);
+    std::fs::remove_file(temp_dir.path().join("ENGINE"))?;
+
+    match KvStore::open(temp_dir.path()) {
+        Err(KvErr::WrongEngineError(e)) => assert!(e.contains("sled"), "{}", e),
+        other => panic!("expected WrongEngineError, got {:?}", other.map(|_| ())),
+    }
+    assert!(!temp_dir.path().join("ENGINE").exists());
+    Ok(())
+}
+
+// a file that is not a legacy database is left alone
+#[test]
+fn open_file_that_is_not_a_store() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let notes = temp_dir.path().join("notes.txt");
+    std::fs::write(&notes, "not a database")?;
+
+    match KvStore::open(&notes) {
+        Err(KvErr::WrongEngineError(_)) => {}
+        other => panic!("expected WrongEngineError, got {:?}", other.map(|_| ())),
+    }
+    assert_eq!(std::fs::read_to_string(&notes)?, "not a database");
+    assert!(!temp_dir.path().join("ENGINE").exists());
+    Ok(())
+}
+
+// only a legacy database is removed, not any file next to a store
+#[test]
+fn open_file_next_to_a_store() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    KvStore::open(temp_dir.path())?.set("key1".to_owned(), "value1".to_owned())?;
+    let notes = temp_dir.path().join("notes.txt");
+    std::fs::write(&notes, "not a database")?;
+
+    match KvStore::open(&notes) {
+        Err(KvErr::WrongEngineError(_)) => {}
+        other => panic!("expected WrongEngineError, got {:?}", other.map(|_| ())),
+    }
+    assert_eq!(std::fs::read_to_string(&notes)?, "not a database");
+    // neither is a test.db that is not a database
+    let test_db = temp_dir.path().join("test.db");
+    std::fs::write(&test_db, "not a database")?;
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(std::fs::read_to_string(&test_db)?, "not a database");
+    Ok(())
+}
+
+// a directory is only claimed once a store opened in it
+#[test]
+fn failed_open_does_not_claim() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    std::fs::write(temp_dir.path().join("MANIFEST"), "not a manifest")?;
+
+    assert!(KvStore::open(temp_dir.path()).is_err());
+    assert!(!temp_dir.path().join("ENGINE").exists());
+    match SledKvsEngine::open(temp_dir.path().join("MANIFEST")) {
+        Err(KvErr::WrongEngineError(_)) => {}
+        other => panic!("expected WrongEngineError, got {:?}", other.map(|_| ())),
+    }
+    Ok(())
+}
@@ -0,0 +1,93 @@
+use kvs::{KvStore, OpenOptions, Result};
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+fn open(dir: &Path) -> Result<KvStore> {
+    OpenOptions::new().compaction_threshold(u64::MAX).open(dir)
+}
+
+fn fill(store: &KvStore, iter: usize) -> Result<()> {
+    for key_id in 0..100 {
+        store.set(format!("key{}", key_id), format!("{}", iter))?;
+    }
+    Ok(())
+}
+
+fn check(store: &KvStore) -> Result<()> {
+    assert_eq!(store.get("key0".to_owned())?, None);
+    assert_eq!(store.get("key1".to_owned())?, Some("2".to_owned()));
+    for key_id in 2..100 {
+        assert_eq!(store.get(format!("key{}", key_id))?, Some("1".to_owned()));
+    }
+    assert_eq!(store.get("new".to_owned())?, Some("value".to_owned()));
+    Ok(())
+}
+
+// compact, then write a few records after the compacted generation
+fn compact_and_write(store: &KvStore) -> Result<()> {
+    fill(store, 0)?;
+    fill(store, 1)?;
+    store.compact()?;
+    store.remove("key0".to_owned())?;
+    store.set("key1".to_owned(), "2".to_owned())?;
+    store.set("new".to_owned(), "value".to_owned())
+}
+
+#[test]
+fn open_uses_hint_after_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = open(temp_dir.path())?;
+    compact_and_write(&store)?;
+    drop(store);
+    assert!(temp_dir.path().join("HINT").exists());
+
+    let store = open(temp_dir.path())?;
+    assert!(store.recovery_report().used_hint);
+    check(&store)?;
+
+    // log ids keep increasing after an open from the hint
+    store.set("key1".to_owned(), "3".to_owned())?;
+    drop(store);
+    let store = open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("3".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn corrupt_hint_falls_back_to_full_replay() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = open(temp_dir.path())?;
+    compact_and_write(&store)?;
+    drop(store);
+
+    let hint = temp_dir.path().join("HINT");
+    let mut data = fs::read(&hint).unwrap();
+    let last = data.len() - 1;
+    data[last] ^= 0xff;
+    fs::write(&hint, data).unwrap();
+
+    let store = open(temp_dir.path())?;
+    assert!(!store.recovery_report().used_hint);
+    check(&store)?;
+    Ok(())
+}
+
+#[test]
+fn stale_hint_falls_back_to_full_replay() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = open(temp_dir.path())?;
+    fill(&store, 5)?;
+    store.compact()?;
+    let old_hint = fs::read(temp_dir.path().join("HINT")).unwrap();
+    compact_and_write(&store)?;
+    drop(store);
+
+    // the old hint covers segments that were compacted away since
+    fs::write(temp_dir.path().join("HINT"), old_hint).unwrap();
+
+    let store = open(temp_dir.path())?;
+    assert!(!store.recovery_report().used_hint);
+    check(&store)?;
+    Ok(())
+}
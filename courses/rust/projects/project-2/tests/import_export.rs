@@ -0,0 +1,168 @@
+use assert_cmd::prelude::*;
+use predicates::ord::eq;
+use predicates::prelude::*;
+use predicates::str::{contains, PredicateStrExt};
+use tempfile::TempDir;
+
+mod common;
+use common::kvs;
+
+fn get(dir: &TempDir, key: &str) -> assert_cmd::assert::Assert {
+    kvs(dir).args(["get", key]).assert()
+}
+
+#[test]
+fn import_every_format() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    std::fs::write(
+        temp_dir.path().join("pairs.json"),
+        r#"{"key1": "value1", "key2": "two words", "key1": "value3"}"#,
+    )
+    .unwrap();
+    kvs(&temp_dir)
+        .args(["import", "pairs.json", "--batch-size", "2"])
+        .assert()
+        .success()
+        .stderr(contains("imported 3 pairs"));
+    get(&temp_dir, "key1").success().stdout(eq("value3").trim());
+    get(&temp_dir, "key2")
+        .success()
+        .stdout(eq("two words").trim());
+
+    kvs(&temp_dir)
+        .args(["import", "--format", "csv"])
+        .with_stdin()
+        .buffer("key,value\nkey3,\"a, b\"\nkey4,\"\"\"quoted\"\"\"\n")
+        .assert()
+        .success()
+        .stderr(contains("imported 2 pairs"));
+    get(&temp_dir, "key3").success().stdout(eq("a, b").trim());
+    get(&temp_dir, "key4")
+        .success()
+        .stdout(eq("\"quoted\"").trim());
+
+    // ndjson is the default without a format or an extension
+    kvs(&temp_dir)
+        .arg("import")
+        .with_stdin()
+        .buffer("{\"key\": \"key5\", \"value\": \"value5\"}\n\n{\"value\": \"value6\", \"key\": \"key6\"}\n")
+        .assert()
+        .success()
+        .stderr(contains("imported 2 pairs"));
+    get(&temp_dir, "key6").success().stdout(eq("value6").trim());
+}
+
+#[test]
+fn export_every_format() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    kvs(&temp_dir)
+        .arg("batch")
+        .with_stdin()
+        .buffer("set user:2 bob \"b\"\nset user:1 alice, a\nset other x\n")
+        .assert()
+        .success();
+
+    kvs(&temp_dir)
+        .args(["export", "--prefix", "user:", "--format", "json"])
+        .assert()
+        .success()
+        .stdout(eq(
+            "{\n  \"user:1\": \"alice, a\",\n  \"user:2\": \"bob \\\"b\\\"\"\n}\n",
+        ))
+        .stderr(contains("exported 2 pairs"));
+    kvs(&temp_dir)
+        .args(["export", "--prefix", "user:"])
+        .assert()
+        .success()
+        .stdout(eq(
+            "{\"key\":\"user:1\",\"value\":\"alice, a\"}\n{\"key\":\"user:2\",\"value\":\"bob \\\"b\\\"\"}\n",
+        ));
+    kvs(&temp_dir)
+        .args(["export", "pairs.csv"])
+        .assert()
+        .success()
+        .stderr(contains("exported 3 pairs"));
+    assert_eq!(
+        std::fs::read_to_string(temp_dir.path().join("pairs.csv")).unwrap(),
+        "key,value\nother,x\nuser:1,\"alice, a\"\nuser:2,\"bob \"\"b\"\"\"\n"
+    );
+    kvs(&temp_dir)
+        .args(["export", "--prefix", "none", "--format", "json"])
+        .assert()
+        .success()
+        .stdout(eq("{}\n"));
+}
+
+// what one store exports another imports, whatever the bytes
+#[test]
+fn export_then_import() {
+    let from = TempDir::new().expect("unable to create temporary working directory");
+    let to = TempDir::new().unwrap();
+    kvs(&from)
+        .args(["--encoding", "hex", "set", "00ff", "fffe00"])
+        .assert()
+        .success();
+    kvs(&from).args(["set", "text", "value"]).assert().success();
+    // the bytes are not text
+    kvs(&from).arg("export").assert().failure();
+
+    for format in ["json", "ndjson", "csv"] {
+        let file = to.path().join(format!("pairs.{}", format));
+        kvs(&from)
+            .args(["export", "--encoding", "base64", file.to_str().unwrap()])
+            .assert()
+            .success();
+        kvs(&to)
+            .args(["import", "--encoding", "base64", file.to_str().unwrap()])
+            .assert()
+            .success()
+            .stderr(contains("imported 2 pairs"));
+    }
+    kvs(&to)
+        .args(["get", "--encoding", "hex", "00ff"])
+        .assert()
+        .success()
+        .stdout(eq("fffe00").trim());
+    get(&to, "text").success().stdout(eq("value").trim());
+}
+
+// the batches read before a bad record are kept
+#[test]
+fn import_stops_at_invalid_input() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    kvs(&temp_dir)
+        .args(["import", "--batch-size", "1"])
+        .with_stdin()
+        .buffer("{\"key\": \"key1\", \"value\": \"value1\"}\n{\"key\": \"key2\"}\n")
+        .assert()
+        .failure()
+        .stderr(
+            contains("imported 1 pair")
+                .and(contains("invalid stdin"))
+                .and(contains("line 2")),
+        );
+    get(&temp_dir, "key1").success().stdout(eq("value1").trim());
+
+    kvs(&temp_dir)
+        .args(["import", "--format", "json"])
+        .with_stdin()
+        .buffer("{\"key2\": 2}")
+        .assert()
+        .failure()
+        .stderr(contains("expected a string"));
+    kvs(&temp_dir)
+        .args(["import", "--format", "csv"])
+        .with_stdin()
+        .buffer("name,value\nkey3,value3\n")
+        .assert()
+        .failure()
+        .stderr(contains("missing field `key`"));
+    kvs(&temp_dir)
+        .args(["import", "missing.json"])
+        .assert()
+        .failure()
+        .stderr(contains("cannot read missing.json"));
+    get(&temp_dir, "key2")
+        .success()
+        .stdout(eq("Key not found").trim());
+}
@@ -0,0 +1,207 @@
+use kvs::error::KvErr;
+use kvs::{KvStore, OpenOptions, Result, WriteBatch};
+use std::thread;
+use tempfile::TempDir;
+
+mod common;
+use common::segment_files;
+
+#[test]
+fn get_at_earlier_versions() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let empty = store.version();
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    let v1 = store.version();
+    store.set("key1".to_owned(), "value2".to_owned())?;
+    store.set("key2".to_owned(), "value3".to_owned())?;
+    let v2 = store.version();
+    store.remove("key1".to_owned())?;
+    let v3 = store.version();
+    store.set("key1".to_owned(), "value4".to_owned())?;
+    assert!(empty < v1 && v1 < v2 && v2 < v3);
+
+    assert_eq!(store.get_at("key1".to_owned(), empty)?, None);
+    assert_eq!(
+        store.get_at("key1".to_owned(), v1)?,
+        Some("value1".to_owned())
+    );
+    assert_eq!(store.get_at("key2".to_owned(), v1)?, None);
+    assert_eq!(
+        store.get_at("key1".to_owned(), v2)?,
+        Some("value2".to_owned())
+    );
+    assert_eq!(
+        store.get_at("key2".to_owned(), v2)?,
+        Some("value3".to_owned())
+    );
+    assert_eq!(store.get_at("key1".to_owned(), v3)?, None);
+    assert_eq!(
+        store.get_at("key1".to_owned(), store.version())?,
+        Some("value4".to_owned())
+    );
+    match store.get_at("key1".to_owned(), store.version() + 1) {
+        Err(KvErr::VersionError(_)) => {}
+        other => panic!("expected a VersionError, got {:?}", other),
+    }
+    Ok(())
+}
+
+// every write of a batch shares its version
+#[test]
+fn batch_is_one_version() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    let before = store.version();
+    let mut batch = WriteBatch::new();
+    batch.set("key1".to_owned(), "value2".to_owned());
+    batch.set("key2".to_owned(), "value3".to_owned());
+    batch.set("key1".to_owned(), "value4".to_owned());
+    store.write(batch)?;
+    assert_eq!(store.version(), before + 1);
+
+    assert_eq!(
+        store.get_at("key1".to_owned(), before)?,
+        Some("value1".to_owned())
+    );
+    assert_eq!(store.get_at("key2".to_owned(), before)?, None);
+    assert_eq!(
+        store.get_at("key1".to_owned(), before + 1)?,
+        Some("value4".to_owned())
+    );
+    Ok(())
+}
+
+#[test]
+fn read_transaction_ignores_later_writes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    for key in ["a", "b1", "b2", "c"].iter() {
+        store.set(key.to_string(), format!("value-{}", key))?;
+    }
+    let tx = store.read_transaction();
+    store.remove("b1".to_owned())?;
+    store.set("b2".to_owned(), "changed".to_owned())?;
+    store.set("b3".to_owned(), "new".to_owned())?;
+
+    assert_eq!(tx.get("b1".to_owned())?, Some("value-b1".to_owned()));
+    assert_eq!(tx.get("b3".to_owned())?, None);
+    let pairs = tx.scan("b".to_owned()..).collect::<Result<Vec<_>>>()?;
+    assert_eq!(
+        pairs,
+        vec![
+            ("b1".to_owned(), "value-b1".to_owned()),
+            ("b2".to_owned(), "value-b2".to_owned()),
+            ("c".to_owned(), "value-c".to_owned()),
+        ]
+    );
+    assert_eq!(store.get("b2".to_owned())?, Some("changed".to_owned()));
+    Ok(())
+}
+
+// A compaction keeps what an open transaction reads, and removes the old
+// segments once it ends. Versions no transaction uses are gone.
+#[test]
+fn compaction_keeps_versions_of_open_transactions() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = OpenOptions::new()
+        .compaction_threshold(u64::MAX)
+        .open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value0".to_owned())?;
+    store.set("key2".to_owned(), "kept".to_owned())?;
+    let unpinned = store.version();
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    let tx = store.read_transaction();
+    for iter in 2..100 {
+        store.set("key1".to_owned(), format!("value{}", iter))?;
+    }
+    store.remove("key2".to_owned())?;
+
+    store.compact()?;
+    store.compact()?;
+    assert_eq!(tx.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(tx.get("key2".to_owned())?, Some("kept".to_owned()));
+    assert_eq!(store.get("key1".to_owned())?, Some("value99".to_owned()));
+    match store.get_at("key1".to_owned(), unpinned) {
+        Err(KvErr::VersionError(_)) => {}
+        other => panic!("expected a VersionError, got {:?}", other),
+    }
+    let version = tx.version();
+    assert_eq!(
+        store.get_at("key2".to_owned(), version)?,
+        Some("kept".to_owned())
+    );
+    let files = segment_files(temp_dir.path()).len();
+
+    drop(tx);
+    store.set("key3".to_owned(), "value".to_owned())?;
+    assert!(segment_files(temp_dir.path()).len() < files);
+    assert!(store.get_at("key1".to_owned(), version).is_err());
+    Ok(())
+}
+
+// every transaction sees both keys of a batch from the same write, while
+// writes and compactions go on
+#[test]
+fn transactions_are_consistent_during_writes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = OpenOptions::new()
+        .compaction_threshold(4096)
+        .open(temp_dir.path())?;
+    store.set("from".to_owned(), "0".to_owned())?;
+    store.set("to".to_owned(), "0".to_owned())?;
+
+    let writer = {
+        let store = store.clone();
+        thread::spawn(move || -> Result<()> {
+            for iter in 1..=500 {
+                let mut batch = WriteBatch::new();
+                batch.set("from".to_owned(), (-iter).to_string());
+                batch.set("to".to_owned(), iter.to_string());
+                store.write(batch)?;
+            }
+            Ok(())
+        })
+    };
+    while !writer.is_finished() {
+        let tx = store.read_transaction();
+        let from: i64 = tx.get("from".to_owned())?.unwrap().parse().unwrap();
+        let to: i64 = tx.get("to".to_owned())?.unwrap().parse().unwrap();
+        assert_eq!(from + to, 0);
+        assert_eq!(tx.get("from".to_owned())?, Some(from.to_string()));
+    }
+    writer.join().unwrap()?;
+    // a background compaction may still be running, this one waits for it
+    store.compact()?;
+    assert!(store.stats()?.compactions > 0);
+    let tx = store.read_transaction();
+    assert_eq!(tx.get("from".to_owned())?, Some("-500".to_owned()));
+    assert_eq!(tx.get("to".to_owned())?, Some("500".to_owned()));
+    Ok(())
+}
+
+// versions start again where they were, only the ones written since the
+// store was opened can be read
+#[test]
+fn versions_after_reopen() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let old = {
+        let store = KvStore::open(temp_dir.path())?;
+        store.set("key1".to_owned(), "value1".to_owned())?;
+        let old = store.version();
+        store.set("key1".to_owned(), "value2".to_owned())?;
+        old
+    };
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.version(), old + 1);
+    assert!(store.get_at("key1".to_owned(), old).is_err());
+    let opened = store.version();
+    store.set("key1".to_owned(), "value3".to_owned())?;
+    assert_eq!(
+        store.get_at("key1".to_owned(), opened)?,
+        Some("value2".to_owned())
+    );
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use kvs::error::KvErr;
+use kvs::{KvStore, OpenOptions as StoreOptions, Result};
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+// the segment with the highest id is the one that was written last
+fn last_segment(dir: &Path) -> PathBuf {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map(|ext| ext == "log").unwrap_or(false))
+        .max_by_key(|path| {
+            path.file_stem()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .parse::<u64>()
+                .unwrap()
+        })
+        .unwrap()
+}
+
+fn write_three_keys(dir: &Path) -> Result<()> {
+    let store = KvStore::open(dir)?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    Ok(())
+}
+
+#[test]
+fn clean_open_has_empty_report() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    write_three_keys(temp_dir.path())?;
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert!(store.recovery_report().is_clean());
+    assert_eq!(store.recovery_report().discarded_bytes(), 0);
+    Ok(())
+}
+
+// A partial write at the tail is cut off and the records before it survive.
+#[test]
+fn torn_tail_is_truncated() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    write_three_keys(temp_dir.path())?;
+
+    let segment = last_segment(temp_dir.path());
+    let len = fs::metadata(&segment).unwrap().len();
+    let fd = OpenOptions::new().write(true).open(&segment).unwrap();
+    fd.set_len(len - 3).unwrap();
+    drop(fd);
+
+    let store = KvStore::open(temp_dir.path())?;
+    let report = store.recovery_report().clone();
+    assert_eq!(report.truncated.len(), 1);
+    assert!(report.discarded_bytes() > 0);
+    assert_eq!(
+        fs::metadata(&segment).unwrap().len(),
+        report.truncated[0].offset
+    );
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(store.get("key3".to_owned())?, None);
+
+    // the log is usable again after the truncation
+    store.set("key3".to_owned(), "value4".to_owned())?;
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert!(store.recovery_report().is_clean());
+    assert_eq!(store.get("key3".to_owned())?, Some("value4".to_owned()));
+    Ok(())
+}
+
+// A flipped bit is caught by the checksum, replay stops at that record.
+#[test]
+fn flipped_bit_stops_replay() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    write_three_keys(temp_dir.path())?;
+
+    let segment = last_segment(temp_dir.path());
+    let mut data = fs::read(&segment).unwrap();
+    let pos = data
+        .windows(6)
+        .position(|w| w == b"value2")
+        .expect("value2 is in the segment");
+    data[pos] ^= 0x01;
+    let len = data.len() as u64;
+    fs::write(&segment, data).unwrap();
+
+    let store = KvStore::open(temp_dir.path())?;
+    let report = store.recovery_report();
+    assert_eq!(report.truncated.len(), 1);
+    assert!(report.truncated[0].reason.contains("checksum"));
+    assert_eq!(report.discarded_bytes(), len - report.truncated[0].offset);
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    assert_eq!(store.get("key3".to_owned())?, None);
+    Ok(())
+}
+
+// A sealed segment was synced whole, a bad record in it is not a torn write.
+// The open fails instead of dropping the records after it, and a repair
+// salvages them.
+#[test]
+fn damaged_sealed_segment_fails_open() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = StoreOptions::new()
+        .compaction_threshold(u64::MAX)
+        .open(temp_dir.path())?;
+    // large enough to seal segment 0 after the last one
+    for key_id in 0..12 {
+        store.set(format!("key{}", key_id), "v".repeat(100_000))?;
+    }
+    assert!(last_segment(temp_dir.path()) != temp_dir.path().join("0.log"));
+    drop(store);
+
+    let sealed = temp_dir.path().join("0.log");
+    let mut data = fs::read(&sealed).unwrap();
+    let pos = data
+        .windows(4)
+        .position(|w| w == b"key5")
+        .expect("key5 is in the first segment");
+    data[pos + 100] ^= 0x01;
+    fs::write(&sealed, &data).unwrap();
+
+    match KvStore::open(temp_dir.path()) {
+        Err(KvErr::CorruptedError(reason)) => assert!(reason.contains("sealed segment 0")),
+        other => panic!("expected a CorruptedError, got {:?}", other.map(|_| ())),
+    }
+    assert_eq!(fs::read(&sealed).unwrap(), data);
+
+    let out = TempDir::new().unwrap();
+    let report = KvStore::repair(temp_dir.path(), out.path())?;
+    assert_eq!(report.bad_records.len(), 1);
+    let store = KvStore::open(out.path())?;
+    assert_eq!(store.get("key5".to_owned())?, None);
+    for key_id in (0..12).filter(|key_id| *key_id != 5) {
+        assert!(store.get(format!("key{}", key_id))?.is_some());
+    }
+    Ok(())
+}
+
+// the newest segment is made live by the manifest, after a power loss its
+// header may be missing: it is recreated like a torn tail, not a failed open
+#[test]
+fn headerless_newest_segment_is_recreated() -> Result<()> {
+    for torn in [&b"KV"[..], &[0u8; 8]] {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let store = KvStore::open(temp_dir.path())?;
+        store.set("key1".to_owned(), "value1".to_owned())?;
+        // the compaction leaves a new and empty active segment
+        store.compact()?;
+        drop(store);
+        let active = temp_dir.path().join("1.log");
+        fs::write(&active, torn)?;
+
+        let store = KvStore::open(temp_dir.path())?;
+        let report = store.recovery_report();
+        assert_eq!(report.truncated.len(), 1);
+        assert_eq!(report.truncated[0].segment, 1);
+        assert_eq!(report.discarded_bytes(), torn.len() as u64);
+        assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+        store.set("key2".to_owned(), "value2".to_owned())?;
+        drop(store);
+
+        let store = KvStore::open(temp_dir.path())?;
+        assert!(store.recovery_report().is_clean());
+        assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    }
+
+    // a sealed segment without its header still fails the open
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.compact()?;
+    drop(store);
+    fs::write(temp_dir.path().join("2.log"), [0u8; 8])?;
+    assert!(KvStore::open(temp_dir.path()).is_err());
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use assert_cmd::prelude::*;
+use kvs::{KvStore, Result};
+use predicates::ord::eq;
+use std::ops::Bound;
+use tempfile::TempDir;
+
+mod common;
+use common::kvs;
+
+fn keys(pairs: impl Iterator<Item = Result<(String, String)>>) -> Vec<String> {
+    pairs.map(|pair| pair.unwrap().0).collect()
+}
+
+fn fill(store: &KvStore) -> Result<()> {
+    for key in ["b", "a", "ab", "abc", "b1", "c", "ba"] {
+        store.set(key.to_owned(), format!("value_{}", key))?;
+    }
+    Ok(())
+}
+
+#[test]
+fn scan_in_key_order() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    fill(&store)?;
+
+    let pairs: Vec<_> = store.scan(..).collect::<Result<_>>()?;
+    assert_eq!(pairs.len(), 7);
+    assert_eq!(pairs[0], ("a".to_owned(), "value_a".to_owned()));
+    assert_eq!(
+        keys(store.scan(..)),
+        ["a", "ab", "abc", "b", "b1", "ba", "c"]
+    );
+    assert_eq!(
+        keys(store.scan("ab".to_owned().."b1".to_owned())),
+        ["ab", "abc", "b"]
+    );
+    assert_eq!(
+        keys(store.scan("b".to_owned()..="ba".to_owned())),
+        ["b", "b1", "ba"]
+    );
+    assert_eq!(
+        keys(store.scan((Bound::Excluded("b".to_owned()), Bound::Unbounded))),
+        ["b1", "ba", "c"]
+    );
+    assert!(keys(store.scan("x".to_owned()..)).is_empty());
+    assert!(keys(store.scan("c".to_owned().."a".to_owned())).is_empty());
+    Ok(())
+}
+
+#[test]
+fn scan_prefix() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    fill(&store)?;
+
+    assert_eq!(keys(store.scan_prefix("a")), ["a", "ab", "abc"]);
+    assert_eq!(keys(store.scan_prefix("b")), ["b", "b1", "ba"]);
+    assert_eq!(keys(store.scan_prefix("abc")), ["abc"]);
+    assert!(keys(store.scan_prefix("d")).is_empty());
+    assert_eq!(keys(store.scan_prefix("")).len(), 7);
+    Ok(())
+}
+
+// The iterator reads values lazily, so it sees writes made after it started
+// and skips keys removed before it reached them.
+#[test]
+fn scan_sees_later_writes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    fill(&store)?;
+
+    let mut scan = store.scan(..);
+    assert_eq!(scan.next().unwrap()?.0, "a");
+    store.remove("ab".to_owned())?;
+    store.set("abc".to_owned(), "new".to_owned())?;
+    store.compact()?;
+    assert_eq!(scan.next().unwrap()?, ("abc".to_owned(), "new".to_owned()));
+    drop(store);
+    assert_eq!(scan.count(), 4);
+    Ok(())
+}
+
+#[test]
+fn scan_after_reopen() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    fill(&store)?;
+    store.remove("b".to_owned())?;
+    drop(store);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(keys(store.scan_prefix("b")), ["b1", "ba"]);
+    Ok(())
+}
+
+#[test]
+fn cli_scan() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    fill(&store)?;
+    drop(store);
+
+    kvs(&temp_dir)
+        .args(["scan", "--prefix", "ab"])
+        .assert()
+        .success()
+        .stdout(eq("ab\tvalue_ab\nabc\tvalue_abc\n"));
+    kvs(&temp_dir)
+        .args(["scan", "--start", "b1", "--end", "c"])
+        .assert()
+        .success()
+        .stdout(eq("b1\tvalue_b1\nba\tvalue_ba\n"));
+    kvs(&temp_dir)
+        .args(["scan", "--prefix", "a", "--start", "b"])
+        .assert()
+        .failure();
+    Ok(())
+}
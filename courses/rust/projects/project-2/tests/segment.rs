@@ -0,0 +1,304 @@
+use byteorder::{LittleEndian, WriteBytesExt};
+use kvs::error::KvErr;
+use kvs::{KvStore, Result};
+use serde::Serialize;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use tempfile::TempDir;
+
+mod common;
+use common::segment_files;
+
+// mirrors of the legacy on-disk types, used to build a single file database
+#[derive(Serialize)]
+struct Metadata {
+    total_log: usize,
+    total_bytes: usize,
+}
+
+#[derive(Serialize)]
+struct Manifest {
+    segments: Vec<u64>,
+    next_segment_id: u64,
+}
+
+#[derive(Serialize)]
+struct LogEntry(usize, LogOperation);
+
+#[derive(Serialize)]
+enum LogOperation {
+    Set(String, String),
+    Rm(String),
+}
+
+// frame entries with a u16 length like v.1 databases and version 1 segments
+fn u16_frames(ops: Vec<LogOperation>) -> Vec<u8> {
+    let mut logs = Vec::new();
+    for (id, op) in ops.into_iter().enumerate() {
+        let entry = serde_cbor::to_vec(&LogEntry(id, op)).unwrap();
+        logs.write_u16::<LittleEndian>(entry.len() as u16).unwrap();
+        logs.extend_from_slice(&entry);
+    }
+    logs
+}
+
+fn write_legacy_db(path: &Path, ops: Vec<LogOperation>) {
+    let total_log = ops.len();
+    let logs = u16_frames(ops);
+
+    let metadata = Metadata {
+        total_log,
+        total_bytes: logs.len(),
+    };
+    let md = serde_cbor::to_vec(&metadata).unwrap();
+    let mut head = vec![0u8; 1024];
+    (&mut head[0..4])
+        .write_u32::<LittleEndian>(md.len() as u32)
+        .unwrap();
+    head[4..md.len() + 4].clone_from_slice(&md);
+
+    let fd = std::fs::File::create(path).unwrap();
+    fd.write_all_at(&head, 0).unwrap();
+    fd.write_all_at(&logs, 1024).unwrap();
+}
+
+// A single file database from before segments should be migrated on open.
+#[test]
+fn migrate_legacy_database() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let legacy = temp_dir.path().join("test.db");
+    write_legacy_db(
+        &legacy,
+        vec![
+            LogOperation::Set("key1".to_owned(), "value1".to_owned()),
+            LogOperation::Set("key2".to_owned(), "value2".to_owned()),
+            LogOperation::Set("key1".to_owned(), "value3".to_owned()),
+            LogOperation::Rm("key2".to_owned()),
+        ],
+    );
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert!(!legacy.exists());
+    assert!(temp_dir.path().join("MANIFEST").exists());
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    store.set("key2".to_owned(), "value4".to_owned())?;
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, Some("value4".to_owned()));
+    Ok(())
+}
+
+// Opening the legacy file itself migrates it into its parent directory.
+#[test]
+fn migrate_legacy_database_by_file_path() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let legacy = temp_dir.path().join("test.db");
+    write_legacy_db(
+        &legacy,
+        vec![LogOperation::Set("key1".to_owned(), "value1".to_owned())],
+    );
+
+    let store = KvStore::open(&legacy)?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert!(!legacy.exists());
+    Ok(())
+}
+
+// Large stores are spread over several bounded segment files.
+#[test]
+fn log_is_split_into_segments() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    let value = "x".repeat(50_000);
+    for key_id in 0..40 {
+        store.set(format!("key{}", key_id), value.clone())?;
+    }
+    drop(store);
+    assert!(segment_files(temp_dir.path()).len() > 1);
+
+    let store = KvStore::open(temp_dir.path())?;
+    for key_id in 0..40 {
+        assert_eq!(store.get(format!("key{}", key_id))?, Some(value.clone()));
+    }
+    Ok(())
+}
+
+// Segment files the manifest no longer lists are leftovers and removed, files
+// the store did not write are left alone.
+#[test]
+fn unreferenced_segments_are_removed() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.compact()?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.compact()?;
+    drop(store);
+
+    // segment 0 was compacted away, as if a crash left it behind
+    let leftover = temp_dir.path().join("0.log");
+    assert!(!leftover.exists());
+    let mut header = b"KVSL".to_vec();
+    header.write_u32::<LittleEndian>(4).unwrap();
+    std::fs::write(&leftover, &header).unwrap();
+    let foreign = temp_dir.path().join("2.log");
+    let unknown = temp_dir.path().join("999.log");
+    std::fs::write(&unknown, header).unwrap();
+    assert!(!foreign.exists());
+    std::fs::write(&foreign, b"garbage").unwrap();
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert!(!leftover.exists());
+    assert!(foreign.exists());
+    assert!(unknown.exists());
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
+
+// A directory that held no store keeps its files, the new store numbers its
+// segments after them.
+#[test]
+fn open_directory_with_other_files() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    std::fs::write(temp_dir.path().join("7.log"), b"application log").unwrap();
+    std::fs::write(temp_dir.path().join("notes.txt"), b"notes").unwrap();
+
+    match kvs::OpenOptions::new().create(false).open(temp_dir.path()) {
+        Err(kvs::error::KvErr::StoreNotExistError(_)) => {}
+        other => panic!("expected StoreNotExistError, got {:?}", other.map(|_| ())),
+    }
+    assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 2);
+
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(
+        std::fs::read(temp_dir.path().join("7.log")).unwrap(),
+        b"application log"
+    );
+    assert!(temp_dir.path().join("notes.txt").exists());
+    Ok(())
+}
+
+// Entries are no longer limited to 64Kb.
+#[test]
+fn store_large_value() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    let value = "x".repeat(3 * 1024 * 1024);
+    store.set("key1".to_owned(), value.clone())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some(value.clone()));
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some(value.clone()));
+    store.compact()?;
+    assert_eq!(store.get("key1".to_owned())?, Some(value));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    Ok(())
+}
+
+// Segments written with u16 framing stay readable, also after compaction.
+#[test]
+fn read_u16_framed_segment() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut segment = b"KVSL".to_vec();
+    segment.write_u32::<LittleEndian>(1).unwrap();
+    segment.extend(u16_frames(vec![
+        LogOperation::Set("key1".to_owned(), "value1".to_owned()),
+        LogOperation::Set("key2".to_owned(), "value2".to_owned()),
+        LogOperation::Rm("key1".to_owned()),
+    ]));
+    std::fs::write(temp_dir.path().join("0.log"), segment).unwrap();
+    let manifest = Manifest {
+        segments: vec![0],
+        next_segment_id: 1,
+    };
+    std::fs::write(
+        temp_dir.path().join("MANIFEST"),
+        serde_cbor::to_vec(&manifest).unwrap(),
+    )
+    .unwrap();
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    store.compact()?;
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+    Ok(())
+}
+
+// Segments written before records carried a compression flag stay readable,
+// and new records go to a new segment.
+#[test]
+fn read_crc_framed_segment() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut segment = b"KVSL".to_vec();
+    segment.write_u32::<LittleEndian>(3).unwrap();
+    let ops = vec![
+        LogOperation::Set("key1".to_owned(), "value1".to_owned()),
+        LogOperation::Set("key2".to_owned(), "value2".to_owned()),
+    ];
+    for (id, op) in ops.into_iter().enumerate() {
+        let entry = serde_cbor::to_vec(&LogEntry(id, op)).unwrap();
+        segment
+            .write_u32::<LittleEndian>(entry.len() as u32)
+            .unwrap();
+        segment
+            .write_u32::<LittleEndian>(crc32fast::hash(&entry))
+            .unwrap();
+        segment.extend_from_slice(&entry);
+    }
+    std::fs::write(temp_dir.path().join("0.log"), segment).unwrap();
+    let manifest = Manifest {
+        segments: vec![0],
+        next_segment_id: 1,
+    };
+    std::fs::write(
+        temp_dir.path().join("MANIFEST"),
+        serde_cbor::to_vec(&manifest).unwrap(),
+    )
+    .unwrap();
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    assert_eq!(store.stats()?.segments, 2);
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+    Ok(())
+}
+
+// without the manifest the segments cannot be told from leftovers, the open
+// fails instead of starting over
+#[test]
+fn missing_manifest_fails_open() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    KvStore::open(temp_dir.path())?.set("key1".to_owned(), "value1".to_owned())?;
+    std::fs::remove_file(temp_dir.path().join("MANIFEST"))?;
+    let files = segment_files(temp_dir.path());
+
+    match KvStore::open(temp_dir.path()) {
+        Err(KvErr::CorruptedError(e)) => assert!(e.contains("--repair"), "{}", e),
+        other => panic!("expected CorruptedError, got {:?}", other.map(|_| ())),
+    }
+    assert_eq!(segment_files(temp_dir.path()), files);
+    assert!(!temp_dir.path().join("MANIFEST").exists());
+    Ok(())
+}
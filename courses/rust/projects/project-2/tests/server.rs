@@ -0,0 +1,306 @@
+use assert_cmd::prelude::*;
+use byteorder::{LittleEndian, WriteBytesExt};
+use kvs::error::KvErr;
+use kvs::protocol::{
+    read_frame, read_message, read_message_async, write_message, write_message_as,
+    write_message_async, ErrorKind, Request, Response, PROTOCOL_VERSION,
+};
+use kvs::KvsClient;
+use predicates::ord::eq;
+use predicates::str::{contains, is_empty, PredicateStrExt};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::Command;
+use std::thread;
+use tempfile::TempDir;
+
+mod common;
+use common::start_server;
+
+fn client(args: &[&str], addr: &str) -> assert_cmd::assert::Assert {
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(args)
+        .args(["--addr", addr])
+        .assert()
+}
+
+fn access_server(engine: &str, addr: &str) {
+    let temp_dir = TempDir::new().unwrap();
+    let server = start_server(&temp_dir, &["--engine", engine, "--addr", addr]);
+
+    client(&["set", "key1", "value1"], addr)
+        .success()
+        .stdout(is_empty());
+    client(&["get", "key1"], addr)
+        .success()
+        .stdout(eq("value1").trim());
+    client(&["set", "key1", "value2"], addr)
+        .success()
+        .stdout(is_empty());
+    client(&["get", "key1"], addr)
+        .success()
+        .stdout(eq("value2").trim());
+    client(&["get", "key2"], addr)
+        .success()
+        .stdout(eq("Key not found").trim());
+    client(&["rm", "key2"], addr)
+        .failure()
+        .stderr(eq("Key not found").trim());
+    client(&["set", "key2", "value3"], addr)
+        .success()
+        .stdout(is_empty());
+    client(&["rm", "key1"], addr).success().stdout(is_empty());
+    drop(server);
+
+    // the data survives a restart of the server
+    let _server = start_server(&temp_dir, &["--engine", engine, "--addr", addr]);
+    client(&["get", "key2"], addr)
+        .success()
+        .stdout(eq("value3").trim());
+    client(&["get", "key1"], addr)
+        .success()
+        .stdout(eq("Key not found").trim());
+}
+
+#[test]
+fn client_access_server_kvs_engine() {
+    access_server("kvs", "127.0.0.1:4101");
+}
+
+#[test]
+fn client_access_server_sled_engine() {
+    access_server("sled", "127.0.0.1:4102");
+}
+
+#[test]
+fn server_rejects_wrong_engine() {
+    let temp_dir = TempDir::new().unwrap();
+    drop(start_server(
+        &temp_dir,
+        &["--engine", "kvs", "--addr", "127.0.0.1:4103"],
+    ));
+
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--engine", "sled", "--addr", "127.0.0.1:4104"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+}
+
+#[test]
+fn server_logs_configuration() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut server = start_server(&temp_dir, &["--engine", "kvs", "--addr", "127.0.0.1:4105"]);
+    server.0.kill().unwrap();
+    let mut stderr = String::new();
+    server
+        .0
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut stderr)
+        .unwrap();
+
+    assert!(stderr.contains(env!("CARGO_PKG_VERSION")));
+    assert!(stderr.contains("kvs"));
+    assert!(stderr.contains("127.0.0.1:4105"));
+}
+
+#[test]
+fn client_cli_invalid() {
+    Command::cargo_bin("kvs-client").unwrap().assert().failure();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get"])
+        .assert()
+        .failure();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "missing_field"])
+        .assert()
+        .failure();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["-V"])
+        .assert()
+        .stdout(contains(env!("CARGO_PKG_VERSION")));
+}
+
+#[test]
+fn protocol_rejects_unknown_version() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4106";
+    let _server = start_server(&temp_dir, &["--engine", "kvs", "--addr", addr]);
+    let body = serde_cbor::to_vec(&Request::Get {
+        key: "key1".to_owned(),
+    })
+    .unwrap();
+
+    // a newer client is answered in the version of the server, an older one
+    // in its own version
+    for (version, reply_version) in [(99, PROTOCOL_VERSION), (1, 1)] {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut frame = Vec::new();
+        frame
+            .write_u32::<LittleEndian>(body.len() as u32 + 1)
+            .unwrap();
+        frame.push(version);
+        frame.extend_from_slice(&body);
+        stream.write_all(&frame).unwrap();
+
+        let (got_version, reply) = read_frame(&mut stream).unwrap().unwrap();
+        assert_eq!(got_version, reply_version);
+        match serde_cbor::from_slice(&reply).unwrap() {
+            Response::Err(ErrorKind::UnsupportedVersion, _) => {}
+            other => panic!("expected an unsupported version error, got {:?}", other),
+        }
+    }
+}
+
+// a server of version 1 turns the client away in its own version, the client
+// still reads why
+#[test]
+fn client_reads_the_error_of_an_older_server() {
+    let listener = TcpListener::bind("127.0.0.1:4119").unwrap();
+    let old_server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let (version, _) = read_frame(&mut stream).unwrap().unwrap();
+        let msg = format!("unsupported protocol version {}", version);
+        let reply = Response::Err(ErrorKind::UnsupportedVersion, msg);
+        write_message_as(&mut stream, 1, &reply).unwrap();
+    });
+
+    let mut client = KvsClient::connect("127.0.0.1:4119").unwrap();
+    match client.get("key1".to_owned()) {
+        Err(KvErr::ServerError(msg)) => {
+            assert!(msg.contains(&PROTOCOL_VERSION.to_string()), "{}", msg)
+        }
+        other => panic!("expected a server error, got {:?}", other),
+    }
+    old_server.join().unwrap();
+}
+
+#[test]
+fn protocol_serves_several_requests_per_connection() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4107";
+    let _server = start_server(&temp_dir, &["--engine", "kvs", "--addr", addr]);
+    let mut stream = TcpStream::connect(addr).unwrap();
+
+    let set = Request::Set {
+        key: "key1".to_owned(),
+        value: "value1".to_owned(),
+    };
+    write_message(&mut stream, &set).unwrap();
+    let response: Option<Response> = read_message(&mut stream).unwrap();
+    assert_eq!(response, Some(Response::Ok(None)));
+
+    let remove = Request::Remove {
+        key: "key2".to_owned(),
+    };
+    write_message(&mut stream, &remove).unwrap();
+    let response: Option<Response> = read_message(&mut stream).unwrap();
+    assert_eq!(
+        response,
+        Some(Response::Err(
+            ErrorKind::KeyNotFound,
+            "Key not found".to_owned()
+        ))
+    );
+}
+
+// clients on several threads at once, each connection is kept open while the
+// others are served
+fn concurrent_clients(pool: &str, addr: &'static str) {
+    let temp_dir = TempDir::new().unwrap();
+    let _server = start_server(
+        &temp_dir,
+        &["--addr", addr, "--pool", pool, "--threads", "4"],
+    );
+
+    let handles: Vec<_> = (0..16)
+        .map(|thread_id| {
+            thread::spawn(move || {
+                let mut client = KvsClient::connect(addr).unwrap();
+                for i in 0..20 {
+                    let key = format!("key{}_{}", thread_id, i);
+                    client.set(key.clone(), format!("value{}", i)).unwrap();
+                    assert_eq!(client.get(key).unwrap(), Some(format!("value{}", i)));
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+#[test]
+fn concurrent_clients_naive_pool() {
+    concurrent_clients("naive", "127.0.0.1:4108");
+}
+
+#[test]
+fn concurrent_clients_shared_queue_pool() {
+    concurrent_clients("shared-queue", "127.0.0.1:4109");
+}
+
+#[test]
+fn concurrent_clients_rayon_pool() {
+    concurrent_clients("rayon", "127.0.0.1:4110");
+}
+
+#[test]
+fn server_rejects_invalid_pool_size() {
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4111", "--threads", "0"])
+        .current_dir(TempDir::new().unwrap().path())
+        .assert()
+        .failure();
+}
+
+#[test]
+fn async_server_serves_thousands_of_clients() {
+    const CLIENTS: usize = 3000;
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4112";
+    let _server = start_server(&temp_dir, &["--addr", addr, "--async", "--threads", "4"]);
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        // connect every client before any of them sends a request
+        let mut streams = Vec::with_capacity(CLIENTS);
+        for _ in 0..CLIENTS {
+            streams.push(tokio::net::TcpStream::connect(addr).await.unwrap());
+        }
+
+        let tasks: Vec<_> = streams
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut stream)| {
+                tokio::spawn(async move {
+                    let set = Request::Set {
+                        key: format!("key{}", i),
+                        value: format!("value{}", i),
+                    };
+                    write_message_async(&mut stream, &set).await.unwrap();
+                    let response: Option<Response> = read_message_async(&mut stream).await.unwrap();
+                    assert_eq!(response, Some(Response::Ok(None)));
+
+                    let get = Request::Get {
+                        key: format!("key{}", i),
+                    };
+                    write_message_async(&mut stream, &get).await.unwrap();
+                    let response: Option<Response> = read_message_async(&mut stream).await.unwrap();
+                    assert_eq!(response, Some(Response::Ok(Some(format!("value{}", i)))));
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+    });
+}
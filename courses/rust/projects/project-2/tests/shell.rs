@@ -0,0 +1,121 @@
+use assert_cmd::prelude::*;
+use predicates::ord::eq;
+use predicates::prelude::*;
+use predicates::str::{contains, PredicateStrExt};
+use tempfile::TempDir;
+
+mod common;
+use common::kvs;
+
+fn get(dir: &TempDir, key: &str) -> assert_cmd::assert::Assert {
+    kvs(dir).args(["get", key]).assert()
+}
+
+#[test]
+fn shell_keeps_history() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    kvs(&temp_dir)
+        .arg("shell")
+        .with_stdin()
+        .buffer("set key1 value1\nset key2 'two words'\n\nget key2\nscan --prefix key\nexit\nset key3 value3\n")
+        .assert()
+        .success()
+        .stdout(contains("two words\n").and(contains("key1\tvalue1\nkey2\ttwo words\n")));
+
+    get(&temp_dir, "key1").success().stdout(eq("value1").trim());
+    // nothing is read after exit
+    get(&temp_dir, "key3").stdout(eq("Key not found").trim());
+    let history = std::fs::read_to_string(temp_dir.path().join(".kvs_history")).unwrap();
+    assert!(history.contains("set key2 'two words'"));
+    assert!(!history.contains("exit"));
+}
+
+// a failed command is reported and the shell goes on
+#[test]
+fn shell_reports_failures() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    kvs(&temp_dir)
+        .arg("shell")
+        .with_stdin()
+        .buffer("rm key1\nfrobnicate\nset key1 'unbalanced\nset key1 value1\n")
+        .assert()
+        .success()
+        .stderr(
+            contains("Key not found")
+                .and(contains("frobnicate"))
+                .and(contains("quote")),
+        );
+    get(&temp_dir, "key1").success().stdout(eq("value1").trim());
+}
+
+#[test]
+fn exec_runs_script() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let script = temp_dir.path().join("script.txt");
+    std::fs::write(
+        &script,
+        "# load\nset key1 value1\nset key2 value2\nrm key1\n--encoding hex set 6b657933 76616c756533\nget key2\n",
+    )
+    .unwrap();
+    kvs(&temp_dir)
+        .arg("exec")
+        .arg("-f")
+        .arg(&script)
+        .assert()
+        .success()
+        .stdout(eq("value2").trim());
+    get(&temp_dir, "key1").stdout(eq("Key not found").trim());
+    get(&temp_dir, "key3").stdout(eq("value3").trim());
+}
+
+// every command runs, the failed ones are reported with their line and the
+// exit code says some failed
+#[test]
+fn exec_reports_failed_commands() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    kvs(&temp_dir)
+        .args(["exec", "-f", "-"])
+        .with_stdin()
+        .buffer("set key1 value1\nrm missing\nset key2 value2\nset key3 value3 --ttl soon\n")
+        .assert()
+        .failure()
+        .stderr(
+            contains("line 2: Key not found")
+                .and(contains("line 4: --ttl takes a number of seconds"))
+                .and(contains("2 commands failed")),
+        );
+    get(&temp_dir, "key2").stdout(eq("value2").trim());
+
+    kvs(&temp_dir)
+        .args(["exec", "--stop-on-error", "-f", "-"])
+        .with_stdin()
+        .buffer("rm missing\nset key4 value4\n")
+        .assert()
+        .failure()
+        .stderr(contains("line 1: Key not found"));
+    get(&temp_dir, "key4").stdout(eq("Key not found").trim());
+}
+
+#[test]
+fn exec_cannot_nest() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    kvs(&temp_dir)
+        .args(["exec", "-f", "-"])
+        .with_stdin()
+        .buffer("shell\nexec -f -\n")
+        .assert()
+        .failure()
+        .stderr(contains(
+            "line 1: shell cannot run inside a shell or a script",
+        ));
+}
+
+#[test]
+fn exec_missing_script() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    kvs(&temp_dir)
+        .args(["exec", "-f", "missing.txt"])
+        .assert()
+        .failure()
+        .stderr(contains("cannot read missing.txt"));
+}
@@ -0,0 +1,166 @@
+use assert_cmd::prelude::*;
+use kvs::{KvStore, OpenOptions, Result};
+use predicates::ord::eq;
+use predicates::str::PredicateStrExt;
+use std::thread;
+use tempfile::TempDir;
+
+mod common;
+use common::kvs;
+
+#[test]
+fn snapshot_ignores_later_writes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+
+    let snapshot = store.snapshot()?;
+    store.set("key1".to_owned(), "value3".to_owned())?;
+    store.remove("key2".to_owned())?;
+    store.set("key3".to_owned(), "value4".to_owned())?;
+
+    assert_eq!(snapshot.len(), 2);
+    assert_eq!(snapshot.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(snapshot.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(snapshot.get("key3".to_owned())?, None);
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    Ok(())
+}
+
+#[test]
+fn snapshot_scan() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    for key in ["a", "b1", "b2", "c"].iter() {
+        store.set(key.to_string(), format!("value-{}", key))?;
+    }
+    let snapshot = store.snapshot()?;
+    store.remove("b1".to_owned())?;
+
+    let pairs = snapshot
+        .scan("b".to_owned()..)
+        .collect::<Result<Vec<_>>>()?;
+    let keys: Vec<&str> = pairs.iter().map(|(key, _)| key.as_str()).collect();
+    assert_eq!(keys, vec!["b1", "b2", "c"]);
+    let pairs = snapshot.scan_prefix("b").collect::<Result<Vec<_>>>()?;
+    assert_eq!(
+        pairs,
+        vec![
+            ("b1".to_owned(), "value-b1".to_owned()),
+            ("b2".to_owned(), "value-b2".to_owned()),
+        ]
+    );
+    Ok(())
+}
+
+// The segment files a snapshot reads from stay readable after a compaction
+// removes them.
+#[test]
+fn snapshot_survives_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = OpenOptions::new()
+        .compaction_threshold(u64::MAX)
+        .open(temp_dir.path())?;
+    for key_id in 0..100 {
+        store.set(format!("key{}", key_id), "old".to_owned())?;
+    }
+    let snapshot = store.snapshot()?;
+    for key_id in 0..100 {
+        store.set(format!("key{}", key_id), "new".to_owned())?;
+    }
+    store.compact()?;
+    drop(store);
+
+    for key_id in 0..100 {
+        assert_eq!(
+            snapshot.get(format!("key{}", key_id))?,
+            Some("old".to_owned())
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn backup_while_writing() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let backup_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = OpenOptions::new()
+        .compaction_threshold(64 * 1024)
+        .open(temp_dir.path())?;
+    for key_id in 0..1000 {
+        store.set(format!("key{}", key_id), "0".to_owned())?;
+    }
+
+    let writer = store.clone();
+    let writer = thread::spawn(move || {
+        for iter in 1..20 {
+            for key_id in 0..1000 {
+                writer
+                    .set(format!("key{}", key_id), iter.to_string())
+                    .unwrap();
+            }
+        }
+    });
+    store.backup_to(backup_dir.path())?;
+    writer.join().unwrap();
+
+    // key0 is written first in each round, a consistent copy has no key
+    // ahead of it and none more than a round behind
+    let backup = KvStore::open(backup_dir.path())?;
+    assert!(backup.recovery_report().used_hint);
+    let round: u32 = backup.get("key0".to_owned())?.unwrap().parse().unwrap();
+    let mut count = 0;
+    for pair in backup.scan(..) {
+        let value: u32 = pair?.1.parse().unwrap();
+        assert!(value == round || value + 1 == round);
+        count += 1;
+    }
+    assert_eq!(count, 1000);
+    Ok(())
+}
+
+#[test]
+fn backup_needs_an_empty_target() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let backup_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.backup_to(backup_dir.path())?;
+    assert!(store.backup_to(backup_dir.path()).is_err());
+    assert!(store.backup_to(temp_dir.path()).is_err());
+
+    // log files of someone else in the target are left as they are
+    let other_dir = TempDir::new().expect("unable to create temporary working directory");
+    std::fs::write(other_dir.path().join("0.log"), "application log")?;
+    store.backup_to(other_dir.path())?;
+    assert_eq!(
+        std::fs::read_to_string(other_dir.path().join("0.log"))?,
+        "application log"
+    );
+    let backup = KvStore::open(other_dir.path())?;
+    assert_eq!(backup.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert!(other_dir.path().join("0.log").is_file());
+    Ok(())
+}
+
+#[test]
+fn cli_backup() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let backup_dir = TempDir::new().expect("unable to create temporary working directory");
+    kvs(&temp_dir)
+        .args(["set", "key1", "value1"])
+        .assert()
+        .success();
+    kvs(&temp_dir)
+        .args(["backup", backup_dir.path().to_str().unwrap()])
+        .assert()
+        .success();
+    kvs(&backup_dir)
+        .args(["get", "key1"])
+        .assert()
+        .success()
+        .stdout(eq("value1").trim());
+    kvs(&temp_dir).args(["backup"]).assert().failure();
+}
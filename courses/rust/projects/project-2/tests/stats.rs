@@ -0,0 +1,154 @@
+use assert_cmd::prelude::*;
+use kvs::{KvStore, KvsClient, OpenOptions, Result};
+use predicates::prelude::*;
+use predicates::str::contains;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::process::Command;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+mod common;
+use common::{kvs, start_server};
+
+// the status line and body of a GET request
+fn http_get(addr: &str, path: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, addr).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.lines().next().unwrap().to_owned(), body.to_owned())
+}
+
+#[test]
+fn stats_count_keys_and_calls() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.set("key1".to_owned(), "value3".to_owned())?;
+    store.get("key1".to_owned())?;
+    store.get("key3".to_owned())?;
+    store.clone().remove("key2".to_owned())?;
+
+    let stats = store.stats()?;
+    assert_eq!(stats.live_keys, 1);
+    assert_eq!(stats.segments, 1);
+    assert!(stats.stale_bytes > 0);
+    assert!(stats.log_bytes > stats.stale_bytes);
+    assert_eq!(stats.compactions, 0);
+    assert_eq!(stats.reads.count, 2);
+    assert_eq!(stats.writes.count, 4);
+    assert!(stats.writes.mean() <= stats.writes.max);
+    assert!(stats.writes.max <= stats.writes.total);
+    Ok(())
+}
+
+#[test]
+fn compaction_pays_the_debt() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = OpenOptions::new()
+        .compaction_threshold(u64::MAX)
+        .open(temp_dir.path())?;
+    for iter in 0..100 {
+        store.set("key".to_owned(), format!("value{}", iter))?;
+    }
+    let before = store.stats()?;
+    assert!(before.stale_bytes > 0);
+    assert_eq!(before.compaction_threshold, u64::MAX);
+
+    store.compact()?;
+    let after = store.stats()?;
+    assert_eq!(after.compactions, 1);
+    assert_eq!(after.stale_bytes, 0);
+    assert_eq!(after.live_keys, 1);
+    assert!(after.log_bytes < before.log_bytes);
+    Ok(())
+}
+
+#[test]
+fn prometheus_format() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    let text = store.stats()?.to_prometheus();
+    assert!(text.contains("# TYPE kvs_live_keys gauge\nkvs_live_keys 1\n"));
+    assert!(text.contains("# TYPE kvs_compactions_total counter\nkvs_compactions_total 0\n"));
+    assert!(text.contains("kvs_write_duration_seconds_count 1\n"));
+    assert!(text.contains("kvs_read_duration_seconds_count 0\n"));
+    // every sample line is a name and a number
+    for line in text.lines().filter(|line| !line.starts_with('#')) {
+        let (_, value) = line.split_once(' ').unwrap();
+        value.parse::<f64>().unwrap();
+    }
+    Ok(())
+}
+
+#[test]
+fn cli_stats() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    for key in ["key1", "key2", "key1"] {
+        kvs(&temp_dir)
+            .args(["set", key, "value"])
+            .assert()
+            .success();
+    }
+    kvs(&temp_dir)
+        .args(["stats"])
+        .assert()
+        .success()
+        .stdout(contains("live keys: 2").and(contains("segments: 1")));
+}
+
+#[test]
+fn server_metrics_endpoint() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4115";
+    let metrics_addr = "127.0.0.1:4116";
+    let _server = start_server(
+        &temp_dir,
+        &[
+            "--engine",
+            "kvs",
+            "--addr",
+            addr,
+            "--metrics-addr",
+            metrics_addr,
+        ],
+    );
+    let mut client = KvsClient::connect(addr).unwrap();
+    client.set("key1".to_owned(), "value1".to_owned()).unwrap();
+    client.get("key1".to_owned()).unwrap();
+
+    // a client that connects and sends nothing does not hold up the scrape
+    let _idle = TcpStream::connect(metrics_addr).unwrap();
+    let start = Instant::now();
+    let (status, body) = http_get(metrics_addr, "/metrics");
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert!(body.contains("kvs_live_keys 1\n"));
+    assert!(body.contains("kvs_write_duration_seconds_count 1\n"));
+    assert!(body.contains("kvs_read_duration_seconds_count 1\n"));
+
+    let (status, _) = http_get(metrics_addr, "/");
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+}
+
+#[test]
+fn server_metrics_need_kvs_engine() {
+    let temp_dir = TempDir::new().unwrap();
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args([
+            "--engine",
+            "sled",
+            "--addr",
+            "127.0.0.1:4117",
+            "--metrics-addr",
+            "127.0.0.1:4118",
+        ])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+}
@@ -1,3 +1,7 @@
+// the tests of the original project, kept as they were written against the
+// `&mut self` api, the cli ones only ignore the store chosen by the user
+#![allow(unused_mut, clippy::needless_borrows_for_generic_args)]
+
 use assert_cmd::prelude::*;
 use kvs::{KvStore, Result};
 use predicates::ord::eq;
@@ -22,6 +26,25 @@ fn cli_version() {
         .stdout(contains(env!("CARGO_PKG_VERSION")));
 }
 
+// `kvs -V` should exit with zero without looking at the store
+#[test]
+fn cli_version_exit_status() {
+    let temp_dir = TempDir::new().unwrap();
+    std::fs::write(temp_dir.path().join("test.db"), "").unwrap();
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["-V"])
+        .current_dir(&temp_dir)
+        .env_remove("KVS_DB")
+        .env("HOME", temp_dir.path())
+        .assert()
+        .success()
+        .stdout(contains(env!("CARGO_PKG_VERSION")));
+    let files: Vec<_> = std::fs::read_dir(temp_dir.path()).unwrap().collect();
+    assert_eq!(files.len(), 1);
+    assert!(temp_dir.path().join("test.db").is_file());
+}
+
 // `kvs get <KEY>` should print "Key not found" for a non-existent key and exit with zero.
 #[test]
 fn cli_get_non_existent_key() {
@@ -30,6 +53,8 @@ fn cli_get_non_existent_key() {
         .unwrap()
         .args(&["get", "key1"])
         .current_dir(&temp_dir)
+        .env_remove("KVS_DB")
+        .env("HOME", temp_dir.path())
         .assert()
         .success()
         .stdout(eq("Key not found").trim());
@@ -43,6 +68,8 @@ fn cli_rm_non_existent_key() {
         .unwrap()
         .args(&["rm", "key1"])
         .current_dir(&temp_dir)
+        .env_remove("KVS_DB")
+        .env("HOME", temp_dir.path())
         .assert()
         .failure()
         .stdout(eq("Key not found").trim());
@@ -56,6 +83,8 @@ fn cli_set() {
         .unwrap()
         .args(&["set", "key1", "value1"])
         .current_dir(&temp_dir)
+        .env_remove("KVS_DB")
+        .env("HOME", temp_dir.path())
         .assert()
         .success()
         .stdout(is_empty());
@@ -74,6 +103,8 @@ fn cli_get_stored() -> Result<()> {
         .unwrap()
         .args(&["get", "key1"])
         .current_dir(&temp_dir)
+        .env_remove("KVS_DB")
+        .env("HOME", temp_dir.path())
         .assert()
         .success()
         .stdout(eq("value1").trim());
@@ -82,6 +113,8 @@ fn cli_get_stored() -> Result<()> {
         .unwrap()
         .args(&["get", "key2"])
         .current_dir(&temp_dir)
+        .env_remove("KVS_DB")
+        .env("HOME", temp_dir.path())
         .assert()
         .success()
         .stdout(eq("value2").trim());
@@ -102,6 +135,8 @@ fn cli_rm_stored() -> Result<()> {
         .unwrap()
         .args(&["rm", "key1"])
         .current_dir(&temp_dir)
+        .env_remove("KVS_DB")
+        .env("HOME", temp_dir.path())
         .assert()
         .success()
         .stdout(is_empty());
@@ -110,6 +145,8 @@ fn cli_rm_stored() -> Result<()> {
         .unwrap()
         .args(&["get", "key1"])
         .current_dir(&temp_dir)
+        .env_remove("KVS_DB")
+        .env("HOME", temp_dir.path())
         .assert()
         .success()
         .stdout(eq("Key not found").trim());
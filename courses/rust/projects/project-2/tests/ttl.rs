@@ -0,0 +1,163 @@
+use assert_cmd::prelude::*;
+use kvs::error::KvErr;
+use kvs::{KvStore, OpenOptions, Result, WriteBatch};
+use predicates::ord::eq;
+use predicates::str::PredicateStrExt;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+mod common;
+use common::{dir_size, kvs};
+
+const TTL: Duration = Duration::from_millis(300);
+
+fn wait_for_expiry() {
+    thread::sleep(TTL * 2);
+}
+
+#[test]
+fn expired_key_is_gone() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set_with_ttl("key1".to_owned(), "value1".to_owned(), TTL)?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    wait_for_expiry();
+    assert_eq!(store.get("key1".to_owned())?, None);
+    match store.remove("key1".to_owned()) {
+        Err(KvErr::KeyNotExistError) => {}
+        other => panic!("expected KeyNotExistError, got {:?}", other),
+    }
+    let keys = store
+        .scan(..)
+        .map(|pair| pair.map(|(key, _)| key))
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(keys, vec!["key2".to_owned()]);
+    Ok(())
+}
+
+#[test]
+fn overwrite_clears_ttl() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set_with_ttl("key1".to_owned(), "value1".to_owned(), TTL)?;
+    store.set("key1".to_owned(), "value2".to_owned())?;
+    store.set("key2".to_owned(), "value3".to_owned())?;
+    store.set_with_ttl("key2".to_owned(), "value4".to_owned(), TTL)?;
+
+    wait_for_expiry();
+    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    Ok(())
+}
+
+#[test]
+fn expiration_survives_reopen() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set_with_ttl("key1".to_owned(), "value1".to_owned(), TTL)?;
+    drop(store);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    drop(store);
+
+    wait_for_expiry();
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert!(store.remove("key1".to_owned()).is_err());
+    Ok(())
+}
+
+#[test]
+fn compaction_drops_expired_keys() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = OpenOptions::new()
+        .compaction_threshold(u64::MAX)
+        .open(temp_dir.path())?;
+    let value = "v".repeat(1000);
+    for key_id in 0..1000 {
+        store.set_with_ttl(format!("key{}", key_id), value.clone(), TTL)?;
+    }
+    store.set("kept".to_owned(), "value".to_owned())?;
+    let before = dir_size(temp_dir.path());
+
+    wait_for_expiry();
+    store.compact()?;
+    assert!(dir_size(temp_dir.path()) < before / 10);
+    assert_eq!(store.get("key0".to_owned())?, None);
+    assert_eq!(store.get("kept".to_owned())?, Some("value".to_owned()));
+    Ok(())
+}
+
+// the hint written by a compaction keeps the expiration of the copied keys
+#[test]
+fn compacted_key_still_expires() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = OpenOptions::new()
+        .compaction_threshold(u64::MAX)
+        .open(temp_dir.path())?;
+    store.set_with_ttl("key1".to_owned(), "value1".to_owned(), TTL)?;
+    store.compact()?;
+    drop(store);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert!(store.recovery_report().used_hint);
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    drop(store);
+
+    wait_for_expiry();
+    let store = KvStore::open(temp_dir.path())?;
+    assert!(store.recovery_report().used_hint);
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert!(store.remove("key1".to_owned()).is_err());
+    Ok(())
+}
+
+#[test]
+fn ttl_in_batch_and_backup() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let backup_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let mut batch = WriteBatch::new();
+    batch
+        .set_with_ttl("key1".to_owned(), "value1".to_owned(), TTL)
+        .set("key2".to_owned(), "value2".to_owned());
+    store.write(batch)?;
+    store.backup_to(backup_dir.path())?;
+
+    let backup = KvStore::open(backup_dir.path())?;
+    assert_eq!(backup.get("key1".to_owned())?, Some("value1".to_owned()));
+    wait_for_expiry();
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(backup.get("key1".to_owned())?, None);
+    assert_eq!(backup.get("key2".to_owned())?, Some("value2".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn cli_set_ttl() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    kvs(&temp_dir)
+        .args(["set", "key1", "value1", "--ttl", "1"])
+        .assert()
+        .success();
+    kvs(&temp_dir)
+        .args(["get", "key1"])
+        .assert()
+        .success()
+        .stdout(eq("value1").trim());
+
+    thread::sleep(Duration::from_millis(1200));
+    kvs(&temp_dir)
+        .args(["get", "key1"])
+        .assert()
+        .success()
+        .stdout(eq("Key not found").trim());
+    kvs(&temp_dir)
+        .args(["set", "key1", "value1", "--ttl", "soon"])
+        .assert()
+        .failure();
+}